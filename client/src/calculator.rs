@@ -2,48 +2,549 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use std::cmp;
 use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
 
-use futures::{SinkExt, StreamExt};
 use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use futures::channel::oneshot;
+use futures::task::{Context, Poll};
+use futures::{SinkExt, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(unix)]
+use tokio::net::unix::UnixStream;
 use tokio::net::TcpStream;
+use tokio::timer::delay_for;
 
-use calc_utils::{MathRequest, MathResult, SerealSink, SerealStreamer};
+use calc_utils::{
+    connect_tls, sign_challenge, Aggregate, AggregateResult, BatchItem, BatchRequest,
+};
+use calc_utils::{BatchResult, CalcError, ClientConfig, Credentials, MathRequest, MathResult};
+use calc_utils::{
+    Complex, Convergence, PolynomialRequest, RootMethod, RootsResult, SerealStreamer,
+};
+use calc_utils::{ComplexOperation, ComplexRequest, ComplexResult, ComplexValue};
+use calc_utils::{Computation, IterativeRequest, Progress, Request, Response, SerealSink};
+use calc_utils::{CustomRequest, OperationInfo};
+use calc_utils::{DerivativeRequest, Estimate, EstimateResult, IntegralRequest};
+use calc_utils::{EvaluateRequest, Function, FunctionOperation, FunctionRequest, FunctionsResult};
+use calc_utils::{Expr, ExpressionResult, Symbolic, SymbolicRequest};
+use calc_utils::{Integer, IntegerOperation, IntegerRequest, IntegerResult, Overflow};
+use calc_utils::{MatrixOperation, MatrixRequest, MatrixResult, MatrixValue};
+use calc_utils::{Quantity, QuantityResult, UnitOperation, UnitRequest};
+use calc_utils::{StatisticsOperation, StatisticsRequest};
+
+/// How many times a request gets retried when the server says we're going too fast
+pub const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+
+/// Cap on how long we wait for a rate limit, no matter what the server asks for
+pub const MAX_RETRY_AFTER: Duration = Duration::from_secs(10);
 
 #[derive(Debug)]
 pub struct Calculator {
     message_sender: MsgSender,
+
+    /// What the server told us it can do on top of the usual requests
+    operations: Vec<OperationInfo>,
+}
+
+/// The progress of an iterative computation, which ends after its result
+#[derive(Debug)]
+pub struct Subscription {
+    updates: UnboundedReceiver<Update>,
+}
+
+#[derive(Debug)]
+pub enum Update {
+    Progress(Progress),
+    Done(Result<f64, CalcError>),
+}
+
+/// An aggregation open on the server, which we can push as many numbers to as we like before
+/// finishing it
+#[derive(Debug)]
+pub struct Aggregation {
+    id: u32,
+    message_sender: MsgSender,
+    result: oneshot::Receiver<AggregateResult>,
+}
+
+/// What we prove who we are with when connecting
+#[derive(Debug, Clone)]
+pub enum Auth {
+    /// For servers that don't have any keys set up
+    Anonymous,
+
+    /// Send the secret of one of the server's keys as is
+    Token(String),
+
+    /// Only send a signature made with the secret, never the secret itself
+    Key { id: String, secret: String },
 }
 
 #[derive(Debug)]
 pub enum Input {
-    Result(MathResult),
+    Response(io::Result<Response>),
     Request(Msg),
 }
 
-type Msg = (MathRequest, oneshot::Sender<MathResult>);
+/// Where the answer to a request we sent goes once it arrives
+#[derive(Debug)]
+pub enum Pending {
+    Math(oneshot::Sender<MathResult>),
+    Batch(oneshot::Sender<BatchResult>),
+    Aggregate(oneshot::Sender<AggregateResult>),
+    Roots(oneshot::Sender<RootsResult>),
+    Estimate(oneshot::Sender<EstimateResult>),
+    Expression(oneshot::Sender<ExpressionResult>),
+    Matrix(oneshot::Sender<MatrixResult>),
+    Complex(oneshot::Sender<ComplexResult>),
+    Integer(oneshot::Sender<IntegerResult>),
+    Quantity(oneshot::Sender<QuantityResult>),
+    Functions(oneshot::Sender<FunctionsResult>),
+
+    /// Gets every progress frame, and then the result
+    Subscription(UnboundedSender<Update>),
+}
+
+/// Requests that don't get an answer of their own, like pushes to an aggregation, come without
+/// anywhere to send one
+type Msg = (Request, Option<Pending>);
 type MsgSender = UnboundedSender<Msg>;
 type MsgReceiver = UnboundedReceiver<Msg>;
 
-
 impl Calculator {
-    pub fn new() -> Calculator {
+    /// Connects to the server at `addr` and logs in with `auth`. Once this returns, the server has
+    /// accepted our credentials and told us which operations it has.
+    pub async fn connect(addr: &str, auth: Auth) -> io::Result<Calculator> {
+        let stream = TcpStream::connect(addr).await?;
+
+        Calculator::start(stream, auth).await
+    }
+
+    /// Same as `connect`, but everything goes through TLS. The server's certificate has to be
+    /// valid for `domain` according to the roots in `tls`.
+    pub async fn connect_tls(
+        addr: &str,
+        tls: &Arc<ClientConfig>,
+        domain: &str,
+        auth: Auth,
+    ) -> io::Result<Calculator> {
+        let stream = TcpStream::connect(addr).await?;
+        let stream = connect_tls(tls, domain, stream).await?;
+
+        Calculator::start(stream, auth).await
+    }
+
+    /// Connects to a server on this host through the Unix socket at `path`
+    #[cfg(unix)]
+    pub async fn connect_unix<P: AsRef<Path>>(path: P, auth: Auth) -> io::Result<Calculator> {
+        let stream = UnixStream::connect(path).await?;
+
+        Calculator::start(stream, auth).await
+    }
+
+    async fn start<S>(mut stream: S, auth: Auth) -> io::Result<Calculator>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        // The server always starts by challenging us, so answer that before anything else. The
+        // streamer and sink only borrow the stream, so it's still all ours afterwards.
+        let first = SerealStreamer::<Response, _>::new(&mut stream).next().await;
+
+        let nonce = match first {
+            Some(Ok(Response::Challenge(nonce))) => nonce,
+            Some(Ok(Response::Rejected(e))) => {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, e))
+            }
+
+            Some(Ok(other)) => return Err(unexpected(&other)),
+            Some(Err(e)) => return Err(e),
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        };
+
+        SerealSink::new(&mut stream)
+            .send(&auth.answer(&nonce))
+            .await?;
+
+        // Once we're in, the server lists its operations before it answers anything else
+        let operations = match SerealStreamer::<Response, _>::new(&mut stream).next().await {
+            Some(Ok(Response::Operations(operations))) => operations,
+            Some(Ok(Response::Rejected(e))) => {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, e))
+            }
+
+            Some(Ok(other)) => return Err(unexpected(&other)),
+            Some(Err(e)) => return Err(e),
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        };
+
         let (tx, rx) = mpsc::unbounded::<Msg>();
 
-        tokio::spawn(process_responses(rx));
+        tokio::spawn(process_responses(stream, rx));
 
-        Calculator {
+        Ok(Calculator {
             message_sender: tx,
-        }
+            operations,
+        })
+    }
+
+    /// The operations `custom` can ask for, as the server listed them when we connected
+    pub fn operations(&self) -> &[OperationInfo] {
+        &self.operations
     }
 
     pub async fn send(&mut self, req: MathRequest) -> Result<MathResult, oneshot::Canceled> {
+        self.request(Request::Math(req), Pending::Math, |result| {
+            retry_after(&result.res)
+        })
+        .await
+    }
+
+    /// Sends all of `items` in a single frame, and gets back a result for each of them in the same
+    /// order. If the server turns the batch away as a whole, every item gets that error.
+    pub async fn batch(
+        &mut self,
+        items: Vec<BatchItem>,
+    ) -> Result<Vec<Result<f64, CalcError>>, oneshot::Canceled> {
+        let req = BatchRequest::new(items);
+        let len = req.items.len();
+
+        let result = self.request(Request::Batch(req), Pending::Batch, |result| {
+            retry_after(&result.res)
+        });
+
+        match result.await?.res {
+            Ok(results) => Ok(results),
+            Err(e) => Ok(vec![Err(e); len]),
+        }
+    }
+
+    /// Opens an aggregation on the server. Anything that goes wrong with it, even with opening
+    /// it, is only told to us when we finish it.
+    pub async fn aggregate(&mut self) -> Result<Aggregation, oneshot::Canceled> {
+        let req = Request::aggregate_open();
+        let id = req.id();
         let (one_tx, one_rx) = oneshot::channel();
 
-        self.message_sender.send((req, one_tx)).await.unwrap();
+        let msg = (req, Some(Pending::Aggregate(one_tx)));
+
+        if self.message_sender.send(msg).await.is_err() {
+            return Err(oneshot::Canceled);
+        }
+
+        Ok(Aggregation {
+            id,
+            message_sender: self.message_sender.clone(),
+            result: one_rx,
+        })
+    }
+
+    /// Starts `computation` on the server, and streams its progress as it comes in. The last
+    /// thing on the stream is its result, unless the connection goes away before then.
+    pub async fn subscribe(
+        &mut self,
+        computation: Computation,
+    ) -> Result<Subscription, oneshot::Canceled> {
+        let (updates_tx, updates) = mpsc::unbounded();
+        let req = Request::Iterate(IterativeRequest::new(computation));
+
+        if self
+            .message_sender
+            .send((req, Some(Pending::Subscription(updates_tx))))
+            .await
+            .is_err()
+        {
+            return Err(oneshot::Canceled);
+        }
+
+        Ok(Subscription { updates })
+    }
+
+    /// Finds a value of `variable` that makes `expression` zero, without caring how it got there.
+    /// Use `subscribe` with a `Computation::Root` to follow along instead.
+    pub async fn solve(
+        &mut self,
+        expression: &str,
+        variable: &str,
+        method: RootMethod,
+        convergence: Convergence,
+    ) -> Result<Result<f64, CalcError>, oneshot::Canceled> {
+        let computation = Computation::Root {
+            expression: expression.to_string(),
+            variable: variable.to_string(),
+            method,
+            convergence,
+        };
+
+        let mut subscription = self.subscribe(computation).await?;
+
+        while let Some(update) = subscription.next().await {
+            if let Update::Done(res) = update {
+                return Ok(res);
+            }
+        }
+
+        Err(oneshot::Canceled)
+    }
+
+    /// Gets every root of the polynomial with `coefficients`, highest power first, complex ones
+    /// included
+    pub async fn polynomial_roots(
+        &mut self,
+        coefficients: Vec<f64>,
+        convergence: Convergence,
+    ) -> Result<Result<Vec<Complex>, CalcError>, oneshot::Canceled> {
+        let req = PolynomialRequest::new(coefficients, convergence);
+        Ok(self
+            .request(Request::PolynomialRoots(req), Pending::Roots, |result| {
+                retry_after(&result.res)
+            })
+            .await?
+            .res)
+    }
+
+    /// The integral of `expression` over `variable` from `lower` to `upper`, along with about how
+    /// far off it might be
+    pub async fn integrate(
+        &mut self,
+        expression: &str,
+        variable: &str,
+        lower: f64,
+        upper: f64,
+        convergence: Convergence,
+    ) -> Result<Result<Estimate, CalcError>, oneshot::Canceled> {
+        let req = IntegralRequest::new(expression, variable, lower, upper, convergence);
+        self.estimate(Request::Integrate(req)).await
+    }
+
+    /// The derivative of `expression` with respect to `variable` at `at`, along with about how far
+    /// off it might be
+    pub async fn differentiate(
+        &mut self,
+        expression: &str,
+        variable: &str,
+        at: f64,
+    ) -> Result<Result<Estimate, CalcError>, oneshot::Canceled> {
+        let req = DerivativeRequest::new(expression, variable, at);
+        self.estimate(Request::Differentiate(req)).await
+    }
+
+    async fn estimate(
+        &mut self,
+        req: Request,
+    ) -> Result<Result<Estimate, CalcError>, oneshot::Canceled> {
+        Ok(self
+            .request(req, Pending::Estimate, |result| retry_after(&result.res))
+            .await?
+            .res)
+    }
+
+    /// The derivative of `expression` with respect to `variable`, as an expression of its own
+    pub async fn derivative(
+        &mut self,
+        expression: &str,
+        variable: &str,
+    ) -> Result<Result<Expr, CalcError>, oneshot::Canceled> {
+        let operation = Symbolic::Derivative {
+            variable: variable.to_string(),
+        };
+        self.symbolic(SymbolicRequest::new(expression, operation))
+            .await
+    }
+
+    pub async fn simplify(
+        &mut self,
+        expression: &str,
+    ) -> Result<Result<Expr, CalcError>, oneshot::Canceled> {
+        self.symbolic(SymbolicRequest::new(expression, Symbolic::Simplify))
+            .await
+    }
+
+    async fn symbolic(
+        &mut self,
+        req: SymbolicRequest,
+    ) -> Result<Result<Expr, CalcError>, oneshot::Canceled> {
+        Ok(self
+            .request(Request::Symbolic(req), Pending::Expression, |result| {
+                retry_after(&result.res)
+            })
+            .await?
+            .res)
+    }
+
+    pub async fn matrix(
+        &mut self,
+        operation: MatrixOperation,
+    ) -> Result<Result<MatrixValue, CalcError>, oneshot::Canceled> {
+        let req = MatrixRequest::new(operation);
+        Ok(self
+            .request(Request::Matrix(req), Pending::Matrix, |result| {
+                retry_after(&result.res)
+            })
+            .await?
+            .res)
+    }
+
+    pub async fn complex(
+        &mut self,
+        operation: ComplexOperation,
+    ) -> Result<Result<ComplexValue, CalcError>, oneshot::Canceled> {
+        let req = ComplexRequest::new(operation);
+        Ok(self
+            .request(Request::Complex(req), Pending::Complex, |result| {
+                retry_after(&result.res)
+            })
+            .await?
+            .res)
+    }
+
+    pub async fn integer<I: Into<Integer>>(
+        &mut self,
+        operation: IntegerOperation,
+        a: I,
+        b: I,
+        overflow: Overflow,
+    ) -> Result<Result<Integer, CalcError>, oneshot::Canceled> {
+        let req = IntegerRequest::new(operation, a, b, overflow);
+        Ok(self
+            .request(Request::Integer(req), Pending::Integer, |result| {
+                retry_after(&result.res)
+            })
+            .await?
+            .res)
+    }
+
+    pub async fn units(
+        &mut self,
+        operation: UnitOperation,
+    ) -> Result<Result<Quantity, CalcError>, oneshot::Canceled> {
+        let req = UnitRequest::new(operation);
+        Ok(self
+            .request(Request::Units(req), Pending::Quantity, |result| {
+                retry_after(&result.res)
+            })
+            .await?
+            .res)
+    }
+
+    /// Anything from `StatisticsOperation`, which all come back as a single number
+    pub async fn statistics(
+        &mut self,
+        operation: StatisticsOperation,
+    ) -> Result<MathResult, oneshot::Canceled> {
+        let req = StatisticsRequest::new(operation);
+        self.request(Request::Statistics(req), Pending::Math, |result| {
+            retry_after(&result.res)
+        })
+        .await
+    }
+
+    /// Converts `quantity` into `unit`, which has to measure the same thing
+    pub async fn convert(
+        &mut self,
+        quantity: Quantity,
+        unit: &str,
+    ) -> Result<Result<Quantity, CalcError>, oneshot::Canceled> {
+        self.units(UnitOperation::Convert(quantity, unit.to_string()))
+            .await
+    }
+
+    /// Works out `expression`, which can call the functions we've defined on this connection
+    pub async fn evaluate(&mut self, expression: &str) -> Result<MathResult, oneshot::Canceled> {
+        let req = EvaluateRequest::new(expression);
+        self.request(Request::Evaluate(req), Pending::Math, |result| {
+            retry_after(&result.res)
+        })
+        .await
+    }
+
+    /// Defines a function like `f(x, y) = x^2 + y` for later expressions to call
+    pub async fn define(
+        &mut self,
+        definition: &str,
+    ) -> Result<Result<Vec<Function>, CalcError>, oneshot::Canceled> {
+        self.functions(FunctionOperation::Define(definition.to_string()))
+            .await
+    }
+
+    pub async fn remove_function(
+        &mut self,
+        name: &str,
+    ) -> Result<Result<Vec<Function>, CalcError>, oneshot::Canceled> {
+        self.functions(FunctionOperation::Remove(name.to_string()))
+            .await
+    }
+
+    pub async fn list_functions(
+        &mut self,
+    ) -> Result<Result<Vec<Function>, CalcError>, oneshot::Canceled> {
+        self.functions(FunctionOperation::List).await
+    }
+
+    async fn functions(
+        &mut self,
+        operation: FunctionOperation,
+    ) -> Result<Result<Vec<Function>, CalcError>, oneshot::Canceled> {
+        let req = FunctionRequest::new(operation);
+        Ok(self
+            .request(Request::Functions(req), Pending::Functions, |result| {
+                retry_after(&result.res)
+            })
+            .await?
+            .res)
+    }
+
+    /// Runs one of the server's own operations, like the ones listed in `operations`
+    pub async fn custom(
+        &mut self,
+        operation: &str,
+        arguments: &[f64],
+    ) -> Result<MathResult, oneshot::Canceled> {
+        let req = CustomRequest::new(operation, arguments);
+        self.request(Request::Custom(req), Pending::Math, |result| {
+            retry_after(&result.res)
+        })
+        .await
+    }
+
+    /// Sends `req` and waits for its answer, which `pending` says how to route back to us. If the
+    /// server says we're going too fast, we wait as long as it asked and send it again, up to
+    /// `MAX_RATE_LIMIT_RETRIES` times.
+    async fn request<T>(
+        &mut self,
+        req: Request,
+        pending: fn(oneshot::Sender<T>) -> Pending,
+        rate_limited: fn(&T) -> Option<Duration>,
+    ) -> Result<T, oneshot::Canceled> {
+        let mut retries = 0;
+
+        loop {
+            let (one_tx, one_rx) = oneshot::channel();
+
+            // The connection is already gone if nobody is listening anymore, which is the same
+            // as the request being cancelled
+            let msg = (req.clone(), Some(pending(one_tx)));
+
+            if self.message_sender.send(msg).await.is_err() {
+                return Err(oneshot::Canceled);
+            }
+
+            let result = one_rx.await?;
+
+            match rate_limited(&result) {
+                Some(retry_after) if retries < MAX_RATE_LIMIT_RETRIES => {
+                    retries += 1;
+                    delay_for(cmp::min(retry_after, MAX_RETRY_AFTER)).await;
+                }
 
-        one_rx.await
+                _ => return Ok(result),
+            }
+        }
     }
 
     pub async fn add(&mut self, a: f64, b: f64) -> Result<MathResult, oneshot::Canceled> {
@@ -61,13 +562,99 @@ impl Calculator {
     pub async fn divide(&mut self, a: f64, b: f64) -> Result<MathResult, oneshot::Canceled> {
         self.send(MathRequest::divide(a, b)).await
     }
+
+    pub async fn power(&mut self, a: f64, b: f64) -> Result<MathResult, oneshot::Canceled> {
+        self.send(MathRequest::power(a, b)).await
+    }
+}
+
+impl Aggregation {
+    /// Adds `values` to the aggregation. The server doesn't answer these, so this only waits
+    /// until the chunk is on its way.
+    pub async fn push(&mut self, values: Vec<f64>) -> Result<(), oneshot::Canceled> {
+        let msg = (
+            Request::AggregatePush {
+                id: self.id,
+                values,
+            },
+            None,
+        );
+
+        self.message_sender
+            .send(msg)
+            .await
+            .map_err(|_| oneshot::Canceled)
+    }
+
+    /// Ends the aggregation and gets everything the server worked out, with an estimate for each
+    /// of `percentiles`, which go from 0 to 100
+    pub async fn finish(
+        mut self,
+        percentiles: Vec<f64>,
+    ) -> Result<Result<Aggregate, CalcError>, oneshot::Canceled> {
+        // If the server already told us opening it failed, there's nothing to finish
+        if let Ok(Some(result)) = self.result.try_recv() {
+            return Ok(result.res);
+        }
+
+        let msg = (
+            Request::AggregateFinish {
+                id: self.id,
+                percentiles,
+            },
+            None,
+        );
+
+        if self.message_sender.send(msg).await.is_err() {
+            return Err(oneshot::Canceled);
+        }
+
+        Ok(self.result.await?.res)
+    }
+}
+impl Stream for Subscription {
+    type Item = Update;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Update>> {
+        Pin::new(&mut self.updates).poll_next(cx)
+    }
+}
+
+impl Auth {
+    fn answer(&self, nonce: &[u8]) -> Credentials {
+        match self {
+            Auth::Anonymous => Credentials::Anonymous,
+            Auth::Token(token) => Credentials::Token(token.clone()),
+
+            Auth::Key { id, secret } => Credentials::Hmac {
+                key_id: id.clone(),
+                signature: sign_challenge(secret.as_bytes(), nonce),
+            },
+        }
+    }
+}
+
+/// How long the server wants us to wait, if that's why it turned a request away
+fn retry_after<T>(res: &Result<T, CalcError>) -> Option<Duration> {
+    match res {
+        Err(CalcError::RateLimited { retry_after }) => Some(*retry_after),
+        _ => None,
+    }
 }
 
+fn unexpected(response: &Response) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected response {:?}", response),
+    )
+}
 
-async fn process_responses(incoming_requests: MsgReceiver) {
-    // First lets connect to the server and split our stream into read and write
-    let mut stream = TcpStream::connect("127.0.0.1:7878").await.unwrap();
-    let (read_stream, write_stream) = stream.split();
+async fn process_responses<S>(stream: S, incoming_requests: MsgReceiver)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // First lets split our stream into read and write
+    let (read_stream, write_stream) = tokio::io::split(stream);
 
     // Lets take that write stream and pass it to a SerealSink which will take in Messages
     // and serialize them to send them down the tcp sink
@@ -76,7 +663,7 @@ async fn process_responses(incoming_requests: MsgReceiver) {
     // Now lets take that read stream, and pass it to a SerealStreamer which will read input
     // from the stream and deserialize it into Messages.
     // We map these messages to the Input enum
-    let results_stream = SerealStreamer::new(read_stream).map(Input::Result);
+    let results_stream = SerealStreamer::new(read_stream).map(Input::Response);
 
     // Now lets take the incoming requests stream and wrap them in the Input enum too.
     let requests_stream = incoming_requests.map(Input::Request);
@@ -89,7 +676,7 @@ async fn process_responses(incoming_requests: MsgReceiver) {
     // We also need a way to route each incoming result back to the request it came from. Luckily
     // Each message has a u32 id associated with it. So we create a hashmap of the ids and oneshot
     // senders that we will use to send back the result in.
-    let mut request_map: HashMap<u32, oneshot::Sender<MathResult>> = HashMap::new();
+    let mut request_map: HashMap<u32, Pending> = HashMap::new();
 
     // Now we're ready to receive results or requests from our stream.
     while let Some(input) = combined_stream.next().await {
@@ -97,19 +684,201 @@ async fn process_responses(incoming_requests: MsgReceiver) {
             // We've received a request from the client
             Input::Request((req, tx)) => {
                 println!("{:?}", req);
-                // Let's send the request to the server through the SerealSink
-                server_sink.send(&req).await.unwrap();
-                // And lets put that request id into the map so we can send the result back
-                request_map.insert(req.id, tx);
+                // Let's send the request to the server through the SerealSink. If we can't, the
+                // connection is gone (the server may have said goodbye already), so we give up
+                // on it. That drops this request's sender along with the map, which fails
+                // everything that is still waiting.
+                if let Err(e) = server_sink.send(&req).await {
+                    println!("Error writing to server: {}", e);
+                    break;
+                }
+                // And lets put that request id into the map so we can send the result back, if
+                // there's going to be one
+                if let Some(tx) = tx {
+                    request_map.insert(req.id(), tx);
+                }
             }
 
             // We've received a result from the server
-            Input::Result(result) => {
+            Input::Response(Ok(Response::Result(result))) => {
                 println!("{:?}", result);
                 // Get the oneshot sender from the map that matches with the id
-                let tx = request_map.remove(&result.id).unwrap();
-                // Send the result back to the client
-                tx.send(result).unwrap();
+                match request_map.remove(&result.id) {
+                    // Send the result back to the client, who might not be waiting for it anymore.
+                    // The same goes for every other kind of result below.
+                    Some(Pending::Math(tx)) => {
+                        let _ = tx.send(result);
+                    }
+
+                    // A subscription ends with its result, which dropping the sender takes care
+                    // of. Whoever is listening might have stopped already.
+                    Some(Pending::Subscription(tx)) => {
+                        let _ = tx.unbounded_send(Update::Done(result.res));
+                    }
+
+                    _ => println!("Error reading from server: no request for {:?}", result),
+                }
+            }
+
+            // Progress leaves the subscription where it is, since there's more to come
+            Input::Response(Ok(Response::Progress(progress))) => {
+                println!("{:?}", progress);
+
+                match request_map.get(&progress.id) {
+                    Some(Pending::Subscription(tx)) => {
+                        let _ = tx.unbounded_send(Update::Progress(progress));
+                    }
+
+                    _ => println!(
+                        "Error reading from server: no subscription for {:?}",
+                        progress
+                    ),
+                }
+            }
+
+            // Same for a batch, which gets all its results at once
+            Input::Response(Ok(Response::Batch(result))) => {
+                println!("Batch {} answered", result.id);
+
+                match request_map.remove(&result.id) {
+                    Some(Pending::Batch(tx)) => {
+                        let _ = tx.send(result);
+                    }
+                    _ => println!("Error reading from server: no batch for {:?}", result),
+                }
+            }
+
+            // And for an aggregation, which only gets an answer when it's finished or when it
+            // couldn't be opened
+            Input::Response(Ok(Response::Aggregate(result))) => {
+                println!("{:?}", result);
+
+                match request_map.remove(&result.id) {
+                    Some(Pending::Aggregate(tx)) => {
+                        let _ = tx.send(result);
+                    }
+                    _ => println!("Error reading from server: no aggregation for {:?}", result),
+                }
+            }
+
+            Input::Response(Ok(Response::Roots(result))) => {
+                println!("{:?}", result);
+
+                match request_map.remove(&result.id) {
+                    Some(Pending::Roots(tx)) => {
+                        let _ = tx.send(result);
+                    }
+                    _ => println!("Error reading from server: no polynomial for {:?}", result),
+                }
+            }
+
+            Input::Response(Ok(Response::Estimate(result))) => {
+                println!("{:?}", result);
+
+                match request_map.remove(&result.id) {
+                    Some(Pending::Estimate(tx)) => {
+                        let _ = tx.send(result);
+                    }
+                    _ => println!("Error reading from server: no estimate for {:?}", result),
+                }
+            }
+
+            Input::Response(Ok(Response::Expression(result))) => {
+                println!("{:?}", result);
+
+                match request_map.remove(&result.id) {
+                    Some(Pending::Expression(tx)) => {
+                        let _ = tx.send(result);
+                    }
+                    _ => println!("Error reading from server: no expression for {:?}", result),
+                }
+            }
+
+            Input::Response(Ok(Response::Matrix(result))) => {
+                println!("{:?}", result);
+
+                match request_map.remove(&result.id) {
+                    Some(Pending::Matrix(tx)) => {
+                        let _ = tx.send(result);
+                    }
+                    _ => println!("Error reading from server: no matrix for {:?}", result),
+                }
+            }
+
+            Input::Response(Ok(Response::Complex(result))) => {
+                println!("{:?}", result);
+
+                match request_map.remove(&result.id) {
+                    Some(Pending::Complex(tx)) => {
+                        let _ = tx.send(result);
+                    }
+                    _ => println!(
+                        "Error reading from server: no complex number for {:?}",
+                        result
+                    ),
+                }
+            }
+
+            Input::Response(Ok(Response::Integer(result))) => {
+                println!("{:?}", result);
+
+                match request_map.remove(&result.id) {
+                    Some(Pending::Integer(tx)) => {
+                        let _ = tx.send(result);
+                    }
+                    _ => println!("Error reading from server: no integer for {:?}", result),
+                }
+            }
+
+            Input::Response(Ok(Response::Quantity(result))) => {
+                println!("{:?}", result);
+
+                match request_map.remove(&result.id) {
+                    Some(Pending::Quantity(tx)) => {
+                        let _ = tx.send(result);
+                    }
+                    _ => println!("Error reading from server: no quantity for {:?}", result),
+                }
+            }
+
+            Input::Response(Ok(Response::Functions(result))) => {
+                println!("{:?}", result);
+
+                match request_map.remove(&result.id) {
+                    Some(Pending::Functions(tx)) => {
+                        let _ = tx.send(result);
+                    }
+                    _ => println!("Error reading from server: no functions for {:?}", result),
+                }
+            }
+
+            // The server is shutting down. It will still answer what it already read, so we keep
+            // going until it closes the connection, at which point anything left in the map
+            // gets cancelled.
+            Input::Response(Ok(Response::Goodbye)) => {
+                println!("Server is shutting down");
+            }
+
+            // The server won't talk to us, so there's nothing left to do but tell whoever is
+            // waiting, which happens when the map gets dropped
+            Input::Response(Ok(Response::Rejected(e))) => {
+                println!("Server rejected the connection: {}", e);
+                break;
+            }
+
+            // We already answered the challenge and got the operations in `connect`, so a second
+            // one of either makes no sense
+            Input::Response(Ok(other @ Response::Challenge(_)))
+            | Input::Response(Ok(other @ Response::Operations(_))) => {
+                println!("Error reading from server: {}", unexpected(&other));
+                break;
+            }
+
+            // We can't make sense of the connection anymore, so give up on it. Dropping the map
+            // cancels everything that is still waiting for a result.
+            Input::Response(Err(e)) => {
+                println!("Error reading from server: {}", e);
+                break;
             }
         }
     }
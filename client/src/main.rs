@@ -2,16 +2,300 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use std::env;
 use std::io;
 
-use crate::calculator::Calculator;
+use futures::StreamExt;
 
-mod calculator;
+use calc_utils::{client_tls_config, BatchItem, Computation, Convergence, Operation, RootMethod};
+use calc_utils::{Complex, ComplexOperation, ComplexValue, Matrix, MatrixOperation, MatrixValue};
+use calc_utils::{Distribution, StatisticsOperation};
+use calc_utils::{IntegerOperation, Overflow, Quantity, UnitOperation};
+
+use crate::calculator::{Auth, Calculator, Update};
+use crate::udp::UdpCalculator;
 
+mod calculator;
+mod udp;
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    let mut calc = Calculator::new();
+    // Log in with a key or token when one is given in the environment
+    let key = (env::var("CALC_KEY_ID"), env::var("CALC_KEY_SECRET"));
+
+    let auth = match (key, env::var("CALC_TOKEN")) {
+        ((Ok(id), Ok(secret)), _) => Auth::Key { id, secret },
+        (_, Ok(token)) => Auth::Token(token),
+        _ => Auth::Anonymous,
+    };
+
+    // Datagrams don't get a connection to log in on, so there's nothing else to set up
+    if let Ok(addr) = env::var("CALC_UDP") {
+        return udp_main(&addr).await;
+    }
+
+    // Go through a Unix socket when we're given one. Otherwise use TLS when we're told which roots
+    // to trust, and present a certificate of our own if we have one.
+    let mut calc = match (env::var("CALC_UNIX_SOCKET"), env::var("CALC_TLS_ROOTS")) {
+        #[cfg(unix)]
+        (Ok(path), _) => Calculator::connect_unix(path, auth).await?,
+
+        (_, Ok(roots)) => {
+            let identity = match (env::var("CALC_TLS_CERT"), env::var("CALC_TLS_KEY")) {
+                (Ok(cert), Ok(key)) => Some((cert, key)),
+                _ => None,
+            };
+
+            let tls = client_tls_config(roots, identity)?;
+            let domain = env::var("CALC_TLS_DOMAIN").unwrap_or_else(|_| "localhost".to_string());
+
+            Calculator::connect_tls("127.0.0.1:7878", &tls, &domain, auth).await?
+        }
+
+        _ => Calculator::connect("127.0.0.1:7878", auth).await?,
+    };
+
+    let res = calc.add(40.0, 200.0).await;
+    println!("{:?}", res);
+
+    let res = calc.subtract(40.0, 2.0).await;
+    println!("{:?}", res);
+
+    let res = calc.multiply(991.0, 997.0).await;
+    println!("{:?}", res);
+
+    let res = calc.divide(988027.0, 991.0).await;
+    println!("{:?}", res);
+
+    let res = calc.power(2.0, 10.0).await;
+    println!("{:?}", res);
+
+    let res = calc
+        .batch(vec![
+            BatchItem::new(Operation::Addition, 1.0, 2.0),
+            BatchItem::new(Operation::Division, 1.0, 0.0),
+            BatchItem::new(Operation::Multiplication, 6.0, 7.0),
+        ])
+        .await;
+    println!("{:?}", res);
+
+    let mut aggregation = calc.aggregate().await.unwrap();
+
+    for chunk in 0..10 {
+        let values = (0..1000).map(|i| f64::from(chunk * 1000 + i)).collect();
+        aggregation.push(values).await.unwrap();
+    }
+
+    let res = aggregation.finish(vec![50.0, 99.0]).await;
+    println!("{:?}", res);
+
+    let mut updates = calc.subscribe(Computation::Sqrt(2e100)).await.unwrap();
+
+    while let Some(update) = updates.next().await {
+        match update {
+            Update::Progress(progress) => {
+                println!(
+                    "Iteration {}: {}",
+                    progress.iteration, progress.approximation
+                );
+            }
+
+            Update::Done(res) => println!("Done: {:?}", res),
+        }
+    }
+
+    let method = RootMethod::Brent {
+        lower: 0.0,
+        upper: 2.0,
+    };
+    let res = calc
+        .solve("x^3 - 2*x - 1", "x", method, Convergence::default())
+        .await;
+    println!("{:?}", res);
+
+    // x^2 + 1 has no real roots, so this should get back i and -i
+    let res = calc
+        .polynomial_roots(vec![1.0, 0.0, 1.0], Convergence::default())
+        .await;
+    println!("{:?}", res);
+
+    // Should be 2, which the error estimate ought to cover
+    let res = calc
+        .integrate("x^2 * 3 / 4", "x", 0.0, 2.0, Convergence::default())
+        .await;
+    println!("{:?}", res);
+
+    let res = calc.differentiate("x^3", "x", 2.0).await;
+    println!("{:?}", res);
+
+    for expression in &["x^3 * 2 + x / y", "(x + 1) * (x - 1)"] {
+        match calc.derivative(expression, "x").await.unwrap() {
+            Ok(derivative) => println!("d/dx {} = {}", expression, derivative),
+            Err(e) => println!("d/dx {} failed: {}", expression, e),
+        }
+    }
+
+    match calc
+        .simplify("2 * x + 3 * x - x * 1 + 0 * y + 4 / 2")
+        .await
+        .unwrap()
+    {
+        Ok(simplified) => println!("Simplified: {}", simplified),
+        Err(e) => println!("Couldn't simplify: {}", e),
+    }
+
+    let a = Matrix::from_rows(&[vec![2.0, 1.0], vec![1.0, 3.0]]).unwrap();
+    let res = calc
+        .matrix(MatrixOperation::Solve(a.clone(), vec![3.0, 5.0]))
+        .await;
+    println!("{:?}", res);
+
+    if let Ok(Ok(MatrixValue::Matrix(inverse))) = calc.matrix(MatrixOperation::Inverse(a)).await {
+        println!("Inverse:\n{}", inverse);
+    }
+
+    // The second row is twice the first, so there's no inverse to be had
+    let singular = Matrix::from_rows(&[vec![1.0, 2.0], vec![2.0, 4.0]]).unwrap();
+    let res = calc.matrix(MatrixOperation::Inverse(singular)).await;
+    println!("{:?}", res);
+
+    // Should be i, not NaN
+    let res = calc
+        .complex(ComplexOperation::Sqrt(Complex::from(-1.0)))
+        .await;
+    println!("{:?}", res);
+
+    let (a, b) = (Complex::new(1.0, 2.0), Complex::new(3.0, -1.0));
+
+    for operation in [
+        ComplexOperation::Binary(Operation::Multiplication, a, b),
+        ComplexOperation::Binary(Operation::Division, a, b),
+        ComplexOperation::ToPolar(a),
+    ] {
+        match calc.complex(operation).await.unwrap() {
+            Ok(ComplexValue::Complex(z)) => println!("Complex: {}", z),
+            Ok(ComplexValue::Polar(polar)) => println!("Polar: {}", polar),
+            Ok(ComplexValue::Real(num)) => println!("Real: {}", num),
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+
+    // The same overflow three ways
+    for overflow in [Overflow::Checked, Overflow::Wrapping, Overflow::Saturating] {
+        let res = calc
+            .integer(IntegerOperation::Addition, i64::MAX, 1, overflow)
+            .await;
+        println!("{:?}: {:?}", overflow, res);
+    }
+
+    let res = calc
+        .integer(IntegerOperation::Lcm, 4u64, 6u64, Overflow::Checked)
+        .await;
+    println!("{:?}", res);
+
+    let res = calc
+        .integer(
+            IntegerOperation::ShiftLeft,
+            1i128,
+            100i128,
+            Overflow::Checked,
+        )
+        .await;
+    println!("{:?}", res);
+
+    let speed = Quantity::new(100.0, "km/h");
+    let res = calc.convert(speed.clone(), "mph").await;
+    println!("{:?}", res);
+
+    // Comes back in SI base units, as metres
+    let time = Quantity::new(90.0, "min");
+    let res = calc
+        .units(UnitOperation::Binary(
+            Operation::Multiplication,
+            speed,
+            time,
+        ))
+        .await;
+    println!("{:?}", res);
+
+    let res = calc.convert(Quantity::new(98.6, "degF"), "degC").await;
+    println!("{:?}", res);
+
+    // Can't add these
+    let (length, time) = (Quantity::new(3.0, "m"), Quantity::new(2.0, "s"));
+    let res = calc
+        .units(UnitOperation::Binary(Operation::Addition, length, time))
+        .await;
+    println!("{:?}", res);
+
+    // About 1.96
+    let normal = Distribution::Normal {
+        mean: 0.0,
+        std_dev: 1.0,
+    };
+    let res = calc
+        .statistics(StatisticsOperation::Quantile(normal, 0.975))
+        .await;
+    println!("{:?}", res);
+
+    let binomial = Distribution::Binomial {
+        trials: 10,
+        probability: 0.5,
+    };
+    let res = calc
+        .statistics(StatisticsOperation::Cdf(binomial, 5.0))
+        .await;
+    println!("{:?}", res);
+
+    let res = calc.statistics(StatisticsOperation::Choose(52, 5)).await;
+    println!("{:?}", res);
+
+    // Functions stay around for the rest of the connection
+    for definition in [
+        "hyp(a, b) = sqrt(a^2 + b^2)",
+        "f(x, y) = x^2 + y",
+        "sin(x) = x",
+    ] {
+        match calc.define(definition).await.unwrap() {
+            Ok(functions) => println!("Defined: {}", functions[0]),
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+
+    let res = calc.evaluate("hyp(3, 4) + f(2, 1)").await;
+    println!("{:?}", res);
+
+    // Wrong number of arguments
+    let res = calc.evaluate("f(1)").await;
+    println!("{:?}", res);
+
+    let res = calc.remove_function("f").await;
+    println!("{:?}", res);
+
+    if let Ok(functions) = calc.list_functions().await.unwrap() {
+        for function in functions {
+            println!("Still defined: {}", function);
+        }
+    }
+
+    for operation in calc.operations() {
+        println!(
+            "Server has {} taking {} arguments",
+            operation.name, operation.arity
+        );
+    }
+
+    let res = calc.custom("hypot", &[3.0, 4.0]).await;
+    println!("{:?}", res);
+
+    let res = calc.custom("frobnicate", &[1.0]).await;
+    println!("{:?}", res);
+
+    Ok(())
+}
+
+async fn udp_main(addr: &str) -> io::Result<()> {
+    let mut calc = UdpCalculator::connect(addr).await?;
 
     let res = calc.add(40.0, 200.0).await;
     println!("{:?}", res);
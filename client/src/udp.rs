@@ -0,0 +1,131 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::cmp;
+use std::io::{self, Cursor};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tokio::timer::{delay_for, Timeout};
+
+use calc_utils::{CalcError, Deserializer, MathRequest, MathResult, Serializer};
+
+use crate::calculator::{MAX_RATE_LIMIT_RETRIES, MAX_RETRY_AFTER};
+
+/// How long we wait for an answer before sending a request again
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// How many times a request gets sent before we give up on it
+const MAX_ATTEMPTS: u32 = 4;
+
+/// Big enough for any UDP datagram
+const MAX_DATAGRAM_LEN: usize = 64 * 1024;
+
+/// Sends each request in a datagram of its own, for when getting an answer fast matters more than
+/// always getting one. There's no connection, so there's no authentication either, which only
+/// servers that let anyone in will go along with.
+#[derive(Debug)]
+pub struct UdpCalculator {
+    socket: UdpSocket,
+    buf: Vec<u8>,
+}
+
+impl UdpCalculator {
+    /// Sets up a socket that only talks to the server at `addr`. Nothing gets sent until the first
+    /// request, so this doesn't tell us whether there's a server there at all.
+    pub async fn connect(addr: &str) -> io::Result<UdpCalculator> {
+        let server: SocketAddr = addr.parse().map_err(|_| {
+            let msg = format!("`{}` is not a socket address", addr);
+            io::Error::new(io::ErrorKind::InvalidInput, msg)
+        })?;
+
+        let local = if server.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(server).await?;
+
+        Ok(UdpCalculator {
+            socket,
+            buf: vec![0; MAX_DATAGRAM_LEN],
+        })
+    }
+
+    /// Sends `req` until the server answers it or we run out of attempts. The answer is matched up
+    /// by the request's id, so the server can answer any of the copies we sent.
+    pub async fn send(&mut self, req: MathRequest) -> io::Result<MathResult> {
+        let mut packet = Vec::new();
+        packet.serialize(&req)?;
+
+        let mut attempts = 0;
+        let mut retries = 0;
+
+        loop {
+            self.socket.send(&packet).await?;
+            attempts += 1;
+
+            match Timeout::new(self.receive(req.id), RESPONSE_TIMEOUT).await {
+                Ok(result) => {
+                    let result = result?;
+
+                    // Same as on a connection, wait as long as the server asked and try again
+                    match result.res {
+                        Err(CalcError::RateLimited { retry_after })
+                            if retries < MAX_RATE_LIMIT_RETRIES =>
+                        {
+                            retries += 1;
+                            attempts = 0;
+                            delay_for(cmp::min(retry_after, MAX_RETRY_AFTER)).await;
+                        }
+
+                        _ => return Ok(result),
+                    }
+                }
+
+                Err(_) if attempts < MAX_ATTEMPTS => {
+                    println!("No answer to request {} yet, sending it again", req.id);
+                }
+
+                Err(_) => {
+                    let msg = format!("no answer to request {} after {} tries", req.id, attempts);
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, msg));
+                }
+            }
+        }
+    }
+
+    pub async fn add(&mut self, a: f64, b: f64) -> io::Result<MathResult> {
+        self.send(MathRequest::add(a, b)).await
+    }
+
+    pub async fn subtract(&mut self, a: f64, b: f64) -> io::Result<MathResult> {
+        self.send(MathRequest::subtract(a, b)).await
+    }
+
+    pub async fn multiply(&mut self, a: f64, b: f64) -> io::Result<MathResult> {
+        self.send(MathRequest::multiply(a, b)).await
+    }
+
+    pub async fn divide(&mut self, a: f64, b: f64) -> io::Result<MathResult> {
+        self.send(MathRequest::divide(a, b)).await
+    }
+
+    /// Waits for the result for `id`. Anything else that shows up is a late answer to a request
+    /// we already have a result for or gave up on, so it gets skipped.
+    async fn receive(&mut self, id: u32) -> io::Result<MathResult> {
+        loop {
+            let len = self.socket.recv(&mut self.buf).await?;
+
+            match Cursor::new(&self.buf[..len]).deserialize::<MathResult>() {
+                Ok(result) if result.id == id => return Ok(result),
+                Ok(result) => println!("Skipping late result {:?}", result),
+                Err(e) => println!("Skipping datagram we can't read: {}", e),
+            }
+        }
+    }
+}
@@ -0,0 +1,378 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::collections::{HashMap, HashSet};
+use std::f64::consts::PI;
+
+use calc_utils::{Aggregate, CalcError, MAX_FRAME_LEN};
+
+/// How finely the t-digest splits up the numbers. It ends up with somewhere around this many
+/// centroids, so this is what bounds its memory no matter how many numbers get pushed.
+const COMPRESSION: f64 = 100.0;
+
+/// How many numbers we collect before merging them into the centroids all at once
+const BUFFER_LEN: usize = 512;
+
+/// Most aggregations a connection can have open at the same time
+const MAX_OPEN: usize = 64;
+
+/// Most numbers a single push can carry. Pushes get merged right on the connection's task, so
+/// this keeps any one of them from holding it up for long. More numbers just take more pushes.
+const MAX_PUSH_LEN: usize = MAX_FRAME_LEN / 64;
+
+/// The aggregations a connection has open. One that goes wrong stays around until it's finished,
+/// so that finishing it can say what went wrong.
+#[derive(Debug, Default)]
+pub struct Aggregations {
+    open: HashMap<u32, Result<Aggregator, CalcError>>,
+
+    /// Ids that got numbers pushed to them while they weren't open. Those numbers are gone, so
+    /// finishing one of these has to fail even if it got opened in the meantime.
+    missed: HashSet<u32>,
+}
+
+/// Keeps track of an aggregation with a fixed amount of state, no matter how many numbers get
+/// pushed to it
+#[derive(Debug)]
+struct Aggregator {
+    count: u64,
+    sum: f64,
+    mean: f64,
+    // Sum of squared differences from the mean, as Welford's algorithm keeps it
+    m2: f64,
+    min: f64,
+    max: f64,
+    digest: TDigest,
+}
+
+/// A merging t-digest, which estimates percentiles from clusters of nearby numbers. Clusters are
+/// kept small near the ends, so the percentiles people care about most stay accurate.
+#[derive(Debug, Default)]
+struct TDigest {
+    centroids: Vec<Centroid>,
+    buffer: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+impl Aggregations {
+    pub fn open(&mut self, id: u32) -> Result<(), CalcError> {
+        if self.open.contains_key(&id) {
+            return Err(invalid(format!("aggregation {} is already open", id)));
+        }
+
+        if self.open.len() >= MAX_OPEN {
+            return Err(invalid(format!(
+                "at most {} aggregations can be open at once",
+                MAX_OPEN
+            )));
+        }
+
+        self.open.insert(id, Ok(Aggregator::new()));
+
+        Ok(())
+    }
+
+    /// Adds `values` to an aggregation. Nobody is waiting to hear back about this, so anything
+    /// that goes wrong waits for the aggregation to be finished.
+    pub fn push(&mut self, id: u32, values: &[f64]) {
+        if values.len() > MAX_PUSH_LEN {
+            let msg = format!("at most {} numbers can be pushed at once", MAX_PUSH_LEN);
+            return self.fail(id, invalid(msg));
+        }
+
+        match self.open.get_mut(&id) {
+            Some(aggregation) => {
+                if let Ok(aggregator) = aggregation {
+                    if let Err(e) = aggregator.push(values) {
+                        *aggregation = Err(e);
+                    }
+                }
+            }
+
+            None => self.missed(id),
+        }
+    }
+
+    /// Spoils an aggregation, so finishing it gives `error`. The first thing that went wrong is
+    /// the one that gets reported.
+    pub fn fail(&mut self, id: u32, error: CalcError) {
+        match self.open.get_mut(&id) {
+            Some(aggregation @ Ok(_)) => *aggregation = Err(error),
+            Some(Err(_)) => {}
+            None => self.missed(id),
+        }
+    }
+
+    fn missed(&mut self, id: u32) {
+        // Remembering every id a client makes up would let it grow this forever. Past this many,
+        // finishing an id that was never opened still fails, just with a vaguer reason.
+        if self.missed.len() < MAX_OPEN {
+            self.missed.insert(id);
+        }
+    }
+
+    pub fn finish(&mut self, id: u32, percentiles: &[f64]) -> Result<Aggregate, CalcError> {
+        let aggregation = self.open.remove(&id);
+
+        if self.missed.remove(&id) {
+            let msg = format!(
+                "numbers were pushed to aggregation {} while it wasn't open",
+                id
+            );
+            return Err(invalid(msg));
+        }
+
+        match aggregation {
+            Some(aggregation) => aggregation?.finish(percentiles),
+            None => Err(invalid(format!("aggregation {} is not open", id))),
+        }
+    }
+}
+
+impl Aggregator {
+    fn new() -> Aggregator {
+        Aggregator {
+            count: 0,
+            sum: 0.0,
+            mean: 0.0,
+            m2: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            digest: TDigest::default(),
+        }
+    }
+
+    fn push(&mut self, values: &[f64]) -> Result<(), CalcError> {
+        // A NaN has no place among the others, so there's no sensible percentile to give with one
+        if values.iter().any(|value| value.is_nan()) {
+            return Err(invalid("NaN can't be aggregated".to_string()));
+        }
+
+        for &value in values {
+            self.count += 1;
+            self.sum += value;
+
+            let delta = value - self.mean;
+            self.mean += delta / self.count as f64;
+            self.m2 += delta * (value - self.mean);
+
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+
+            self.digest.insert(value);
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self, percentiles: &[f64]) -> Result<Aggregate, CalcError> {
+        if let Some(p) = percentiles.iter().find(|p| !(0.0..=100.0).contains(*p)) {
+            return Err(invalid(format!(
+                "percentile {} is not between 0 and 100",
+                p
+            )));
+        }
+
+        let (min, max) = if self.count > 0 {
+            (self.min, self.max)
+        } else {
+            (f64::NAN, f64::NAN)
+        };
+
+        let percentiles = percentiles
+            .iter()
+            .map(|p| self.digest.quantile(p / 100.0, min, max))
+            .collect();
+
+        Ok(Aggregate {
+            count: self.count,
+            sum: self.sum,
+            mean: if self.count > 0 { self.mean } else { f64::NAN },
+            variance: if self.count > 1 {
+                self.m2 / (self.count - 1) as f64
+            } else {
+                f64::NAN
+            },
+            min,
+            max,
+            percentiles,
+        })
+    }
+}
+
+impl TDigest {
+    fn insert(&mut self, value: f64) {
+        self.buffer.push(value);
+
+        if self.buffer.len() >= BUFFER_LEN {
+            self.merge();
+        }
+    }
+
+    /// Merges the buffered numbers into the centroids, joining neighbours for as long as the
+    /// scale function says the result isn't too big for where it sits
+    fn merge(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        let buffered = self
+            .buffer
+            .drain(..)
+            .map(|mean| Centroid { mean, weight: 1.0 });
+        let mut all: Vec<Centroid> = self.centroids.drain(..).chain(buffered).collect();
+
+        all.sort_by(|a, b| a.mean.total_cmp(&b.mean));
+
+        let total: f64 = all.iter().map(|c| c.weight).sum();
+
+        let mut merged = Vec::new();
+        let mut current = all[0];
+        let mut before = 0.0;
+
+        for next in all.into_iter().skip(1) {
+            let q0 = before / total;
+            let q2 = (before + current.weight + next.weight) / total;
+
+            if scale(q2) - scale(q0) <= 1.0 {
+                current.weight += next.weight;
+                current.mean += (next.mean - current.mean) * next.weight / current.weight;
+            } else {
+                before += current.weight;
+                merged.push(current);
+                current = next;
+            }
+        }
+
+        merged.push(current);
+        self.centroids = merged;
+    }
+
+    /// Estimates the number at `q` of the way through, by interpolating between the middles of
+    /// the centroids around it. Past the outermost middles we head towards `min` and `max`.
+    fn quantile(&mut self, q: f64, min: f64, max: f64) -> f64 {
+        self.merge();
+
+        if self.centroids.is_empty() {
+            return f64::NAN;
+        }
+
+        let total: f64 = self.centroids.iter().map(|c| c.weight).sum();
+
+        let target = q * total;
+        let mut prev = (0.0, min);
+        let mut cumulative = 0.0;
+
+        for c in &self.centroids {
+            let mid = cumulative + c.weight / 2.0;
+
+            if target < mid {
+                return interpolate(prev, (mid, c.mean), target);
+            }
+
+            prev = (mid, c.mean);
+            cumulative += c.weight;
+        }
+
+        interpolate(prev, (total, max), target)
+    }
+}
+
+/// The t-digest's k1 scale function. Two neighbours can only be joined while they span at most
+/// one unit of it, which makes for small centroids near 0 and 1 and big ones in the middle.
+fn scale(q: f64) -> f64 {
+    COMPRESSION / (2.0 * PI) * (2.0 * q - 1.0).asin()
+}
+
+fn invalid(msg: String) -> CalcError {
+    CalcError::InvalidAggregation(msg)
+}
+
+fn interpolate((x0, y0): (f64, f64), (x1, y1): (f64, f64), x: f64) -> f64 {
+    if x1 <= x0 {
+        return y1;
+    }
+
+    y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn invalid_aggregation(res: Result<Aggregate, CalcError>) -> String {
+        match res {
+            Err(CalcError::InvalidAggregation(msg)) => msg,
+            other => panic!("expected an invalid aggregation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn finish() {
+        let mut aggregations = Aggregations::default();
+
+        aggregations.open(1).unwrap();
+        aggregations.push(1, &[1.0, 2.0, 3.0, 4.0]);
+        aggregations.push(1, &[5.0]);
+
+        let aggregate = aggregations.finish(1, &[0.0, 50.0, 100.0]).unwrap();
+
+        assert_eq!(aggregate.count, 5);
+        assert_eq!(aggregate.sum, 15.0);
+        assert_eq!(aggregate.mean, 3.0);
+        assert_eq!(aggregate.variance, 2.5);
+        assert_eq!(aggregate.percentiles, vec![1.0, 3.0, 5.0]);
+    }
+
+    #[test]
+    fn push_before_open() {
+        let mut aggregations = Aggregations::default();
+
+        aggregations.push(1, &[1.0]);
+        aggregations.open(1).unwrap();
+        aggregations.push(1, &[2.0]);
+
+        let msg = invalid_aggregation(aggregations.finish(1, &[]));
+        assert!(msg.contains("wasn't open"), "{}", msg);
+
+        // Only the push that went missing spoils it, not the next aggregation with its id
+        aggregations.open(1).unwrap();
+        aggregations.push(1, &[2.0]);
+        assert_eq!(aggregations.finish(1, &[]).unwrap().count, 1);
+    }
+
+    #[test]
+    fn push_too_long() {
+        let mut aggregations = Aggregations::default();
+
+        aggregations.open(1).unwrap();
+        aggregations.push(1, &vec![0.0; MAX_PUSH_LEN + 1]);
+        aggregations.push(1, &[0.0]);
+
+        let msg = invalid_aggregation(aggregations.finish(1, &[]));
+        assert!(msg.contains("pushed at once"), "{}", msg);
+    }
+
+    #[test]
+    fn failed_push() {
+        let mut aggregations = Aggregations::default();
+
+        aggregations.open(1).unwrap();
+        let retry_after = Duration::from_secs(1);
+        aggregations.fail(1, CalcError::RateLimited { retry_after });
+        aggregations.push(1, &[f64::NAN]);
+
+        match aggregations.finish(1, &[]) {
+            Err(CalcError::RateLimited { .. }) => {}
+            other => panic!("expected to be rate limited, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,174 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use calc_utils::{verify_challenge, CalcError, Credentials, NONCE_LEN};
+
+/// Decides which clients get to use the server, based on the keys in the server's key file
+#[derive(Debug, Default)]
+pub struct Authenticator {
+    /// No keys at all means authentication is off, which only `disabled` does. A key file
+    /// always has at least one.
+    keys: Option<HashMap<String, Vec<u8>>>,
+}
+
+impl Authenticator {
+    /// Lets everyone in, including anonymous clients
+    pub fn disabled() -> Authenticator {
+        Authenticator::default()
+    }
+
+    /// Reads keys from a file with one `<key id> <secret>` pair per line. Empty lines and lines
+    /// starting with `#` are skipped, but there has to be at least one key. Otherwise a file
+    /// that's empty or cut short would quietly let everyone in.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Authenticator> {
+        let mut keys = HashMap::new();
+
+        for (num, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(key_id), Some(secret), None) => {
+                    keys.insert(key_id.to_string(), secret.as_bytes().to_vec());
+                }
+
+                _ => {
+                    let msg = format!("line {} of key file should be `<key id> <secret>`", num + 1);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+                }
+            }
+        }
+
+        if keys.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "key file has no keys",
+            ));
+        }
+
+        Ok(Authenticator { keys: Some(keys) })
+    }
+
+    /// A fresh nonce for a connection to sign
+    pub fn challenge(&self) -> Vec<u8> {
+        (0..NONCE_LEN).map(|_| rand::random()).collect()
+    }
+
+    /// Checks the credentials a client answered `nonce` with, giving back the name of the key
+    /// it used
+    pub fn verify(
+        &self,
+        nonce: &[u8],
+        credentials: &Credentials,
+    ) -> Result<Option<String>, CalcError> {
+        let keys = match &self.keys {
+            Some(keys) => keys,
+            None => return Ok(None),
+        };
+
+        match credentials {
+            Credentials::Anonymous => Err(CalcError::Unauthenticated),
+
+            // Go through every key without stopping early, and compare in constant time, so how
+            // long this takes doesn't say anything about the keys we have
+            Credentials::Token(token) => {
+                let mut found = None;
+
+                for (key_id, secret) in keys {
+                    if constant_time_eq(secret, token.as_bytes()) {
+                        found = Some(key_id.clone());
+                    }
+                }
+
+                found.map(Some).ok_or(CalcError::Unauthenticated)
+            }
+
+            Credentials::Hmac { key_id, signature } => match keys.get(key_id) {
+                Some(secret) if verify_challenge(secret, nonce, signature) => {
+                    Ok(Some(key_id.clone()))
+                }
+
+                _ => Err(CalcError::Unauthenticated),
+            },
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    /// Loads a key file with `contents`, from a file that's gone again afterwards
+    fn load(name: &str, contents: &str) -> io::Result<Authenticator> {
+        let path = env::temp_dir().join(format!("calc-keys-{}-{}", process::id(), name));
+        fs::write(&path, contents)?;
+
+        let res = Authenticator::load(&path);
+        let _ = fs::remove_file(&path);
+
+        res
+    }
+
+    #[test]
+    fn no_keys() {
+        let e = load("comments", "# nothing here yet\n\n   # or here\n").unwrap_err();
+
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "key file has no keys");
+
+        assert!(load("empty", "").is_err());
+    }
+
+    #[test]
+    fn keys() {
+        let auth = load("keys", "# ops\nalice s3cret\n").unwrap();
+        let nonce = auth.challenge();
+
+        assert!(auth.verify(&nonce, &Credentials::Anonymous).is_err());
+        assert!(auth
+            .verify(&nonce, &Credentials::Token("wrong".to_string()))
+            .is_err());
+
+        let token = Credentials::Token("s3cret".to_string());
+        assert_eq!(
+            auth.verify(&nonce, &token).unwrap(),
+            Some("alice".to_string())
+        );
+    }
+
+    #[test]
+    fn disabled() {
+        let auth = Authenticator::disabled();
+        let nonce = auth.challenge();
+
+        assert_eq!(auth.verify(&nonce, &Credentials::Anonymous).unwrap(), None);
+    }
+
+    #[test]
+    fn malformed() {
+        let e = load("malformed", "alice\n").unwrap_err();
+        assert!(e.to_string().contains("line 1"), "{}", e);
+    }
+}
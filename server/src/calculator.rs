@@ -2,38 +2,791 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use std::any::Any;
+use std::collections::HashMap;
 use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::Duration;
 
-use futures::{SinkExt, StreamExt};
-use tokio::net::TcpStream;
+use futures::channel::mpsc;
+#[cfg(feature = "http")]
+use futures::channel::oneshot;
+use futures::future::{self, Either, FutureExt};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::timer::Timeout;
 
-use calc_utils::{MathRequest, MathResult, Operation, SerealSink, SerealStreamer};
+use calc_utils::{AggregateResult, BatchRequest, BatchResult, CalcError, Credentials};
+use calc_utils::{Builtin, EvaluateRequest, Function, FunctionRequest, FunctionsResult};
+use calc_utils::{Complex, ComplexOperation, ComplexRequest, ComplexResult, ComplexValue};
+use calc_utils::{CustomRequest, MAX_EXPR_NODES, MAX_FRAME_LEN};
+use calc_utils::{EstimateResult, ExpressionResult, MatrixRequest, MatrixResult};
+use calc_utils::{Expr, IterativeRequest, PolynomialRequest, RootsResult, SerealSink};
+use calc_utils::{
+    Frame, FrameLimit, MathRequest, MathResult, Operation, PacketStreamer, Request, Response,
+};
+use calc_utils::{IntegerResult, QuantityResult, StatisticsRequest, UnitRequest};
 
-pub async fn process_client(mut stream: TcpStream) -> io::Result<()> {
-    let (read_stream, write_stream) = stream.split();
+use crate::aggregate::Aggregations;
+use crate::auth::Authenticator;
+use crate::calculus;
+use crate::functions::Functions;
+use crate::integer;
+use crate::iterative::{self, Reporter};
+use crate::limits::ConnectionPermit;
+use crate::linear;
+use crate::operations::{self, OperationRegistry};
+use crate::roots;
+use crate::shutdown::ShutdownSignal;
+use crate::statistics;
+use crate::symbolic;
+use crate::units;
+use crate::worker_pool::{Budget, WorkerPool};
 
-    let mut request_stream: SerealStreamer<MathRequest, _> = SerealStreamer::new(read_stream);
-    let mut response_sink: SerealSink<MathResult, _> = SerealSink::new(write_stream);
+/// How many requests from a single connection can be evaluated at the same time. Once we hit
+/// this we stop reading from the connection until some of them finish.
+const MAX_IN_FLIGHT: usize = 16;
 
-    while let Some(request) = request_stream.next().await {
-        println!("Math request: {:?}", &request);
+/// How long a client gets to answer the challenge before we give up on it
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
 
-        let res = match &request.operation {
-            Operation::Addition => request.a + request.b,
-            Operation::Subtraction => request.a - request.b,
-            Operation::Multiplication => request.a * request.b,
-            Operation::Division => request.a / request.b,
-        };
+/// Longest frame we'll read before a client has proven who it is. Credentials are a key id
+/// and a signature or token, so anything bigger isn't credentials.
+const MAX_CREDENTIALS_LEN: usize = 4 * 1024;
+
+/// Most operations a single batch can hold, however it came in. Batches get evaluated all in one
+/// go on a single worker, so this also keeps one of them from hogging a worker for too long.
+/// Each one takes 20 bytes on the wire, so a full batch fits in a frame with room to spare.
+pub const MAX_BATCH_LEN: usize = MAX_FRAME_LEN / 32;
+
+/// How deep calls to user defined functions can nest. With nothing to stop a function calling
+/// itself, any recursion would go on forever without this.
+const MAX_CALL_DEPTH: usize = 64;
+
+/// How deep an evaluation can go through expressions and calls altogether. Each function body
+/// can be as deep as an expression on its own, so this keeps calls from going any deeper than a
+/// single expression already could, which the worker's stack has to hold anyway.
+const MAX_EVALUATION_DEPTH: usize = MAX_EXPR_NODES / 2;
+
+/// Everything an admitted connection needs from the rest of the server
+#[derive(Debug)]
+pub struct Connection {
+    pub shutdown: ShutdownSignal,
+    pub permit: ConnectionPermit,
+    pub pool: WorkerPool,
+    pub auth: Arc<Authenticator>,
+    pub operations: Arc<OperationRegistry>,
+}
+
+/// What became of a request once we had a look at it
+enum Handled {
+    /// It's on the worker pool, which will send its result through the result channel
+    Queued,
+
+    /// It's already taken care of, and this is what to tell the client
+    Answered(Response),
+
+    /// It's taken care of and there's nothing to tell the client
+    Quietly,
+}
+
+enum Event {
+    Request(Option<io::Result<Request>>),
+    Result(Response),
+    Shutdown,
+}
+
+/// Serves a client speaking our own protocol, with length prefixed packets straight on `stream`
+pub async fn process_client<S>(stream: S, connection: Connection) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (read_stream, write_stream) = tokio::io::split(stream);
+
+    let packets = PacketStreamer::new(read_stream);
+    let limit = packets.limit();
+    let response_sink: SerealSink<Response, _> = SerealSink::new(write_stream);
+
+    serve(packets, response_sink, limit, connection).await
+}
+
+/// Takes a client from the challenge until it's done sending requests and has all its results,
+/// however its frames and our responses are carried. `limit` is how long `frames` lets a frame
+/// be, which stays small until the client is authenticated.
+pub async fn serve<R, F, W>(
+    mut frames: R,
+    mut response_sink: W,
+    limit: FrameLimit,
+    connection: Connection,
+) -> io::Result<()>
+where
+    R: Stream<Item = io::Result<F>> + Unpin,
+    F: Frame,
+    W: for<'a> Sink<&'a Response, Error = io::Error> + Unpin,
+{
+    let Connection {
+        mut shutdown,
+        mut permit,
+        pool,
+        auth,
+        operations,
+    } = connection;
+
+    // Nobody gets to send us requests before answering the challenge, so the first frame has to
+    // be their credentials. Until they check out, they don't get to make us buffer much.
+    limit.set(MAX_CREDENTIALS_LEN);
+
+    let nonce = auth.challenge();
+    response_sink
+        .send(&Response::Challenge(nonce.clone()))
+        .await?;
 
-        println!("Result: {}", res);
+    let credentials: Credentials = match Timeout::new(frames.next(), AUTH_TIMEOUT).await {
+        Ok(Some(frame)) => frame?.decode()?,
+        Ok(None) => return Ok(()),
+        Err(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "no credentials sent",
+            ))
+        }
+    };
 
-        let math_res = MathResult {
-            id: request.id,
-            res,
+    match auth.verify(&nonce, &credentials) {
+        Ok(Some(key_id)) => println!("Authenticated with key: {}", key_id),
+        Ok(None) => {}
+
+        Err(e) => {
+            response_sink.send(&Response::Rejected(e)).await?;
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "authentication failed",
+            ));
+        }
+    }
+
+    limit.set(MAX_FRAME_LEN);
+
+    // Now that they're in, let them know what else they can ask for
+    response_sink
+        .send(&Response::Operations(operations.describe()))
+        .await?;
+
+    let mut request_stream = frames.map(|frame| frame.and_then(F::decode::<Request>));
+
+    // Every request gets evaluated on the worker pool, which sends the result back through here
+    // so we can write it out as soon as it's done. The client matches results up by id, so the
+    // order doesn't matter.
+    let (result_tx, mut result_rx) = mpsc::unbounded::<Response>();
+
+    // Aggregations live with the connection, and go away with it if they never get finished
+    let mut aggregations = Aggregations::default();
+
+    // So do the functions it defines
+    let mut functions = Functions::default();
+
+    let mut in_flight = 0;
+    let mut reading = true;
+
+    while reading || in_flight > 0 {
+        let next_result = result_rx.next().map(|res| {
+            // We're holding on to a sender ourselves, so the channel can't end
+            Event::Result(res.expect("result channel closed"))
+        });
+
+        let event = if reading && in_flight < MAX_IN_FLIGHT {
+            // The shutdown goes before the next request so a chatty client can't keep us
+            // reading forever
+            let next_request = future::select(&mut shutdown, request_stream.next());
+            let next_request = next_request.map(|either| match either {
+                Either::Left(((), _)) => Event::Shutdown,
+                Either::Right((req, _)) => Event::Request(req),
+            });
+
+            future::select(next_result, next_request)
+                .await
+                .factor_first()
+                .0
+        } else {
+            next_result.await
         };
 
-        response_sink.send(&math_res).await.unwrap();
+        match event {
+            Event::Request(Some(request)) => {
+                let request = request?;
+                println!("Request: {:?}", &request);
+
+                let handled = handle(
+                    request,
+                    &mut permit,
+                    &pool,
+                    &result_tx,
+                    &mut aggregations,
+                    &mut functions,
+                    &operations,
+                );
+
+                match handled {
+                    Handled::Queued => in_flight += 1,
+
+                    // Tell the client right away instead of making it wait
+                    Handled::Answered(response) => response_sink.send(&response).await?,
+                    Handled::Quietly => {}
+                }
+            }
+
+            // The client is done sending, but still wants to hear back about what it did send
+            Event::Request(None) => reading = false,
+
+            Event::Result(response) => {
+                println!("Result: {:?}", response);
+
+                // Progress comes before the result of the same request, which is what finishes it,
+                // so only that one counts as drained
+                let finished = !matches!(response, Response::Progress(_));
+
+                if finished {
+                    in_flight -= 1;
+                }
+
+                response_sink.send(&response).await?;
+
+                if finished && shutdown.is_triggered() {
+                    shutdown.request_drained();
+                }
+            }
+
+            // Let the client know it shouldn't send anything else, then finish up everything
+            // we already read
+            Event::Shutdown => {
+                reading = false;
+                response_sink.send(&Response::Goodbye).await?;
+            }
+        }
     }
 
     Ok(())
 }
+
+/// Gets a request going, on the worker pool unless it's quick enough to do right here
+fn handle(
+    request: Request,
+    permit: &mut ConnectionPermit,
+    pool: &WorkerPool,
+    result_tx: &mpsc::UnboundedSender<Response>,
+    aggregations: &mut Aggregations,
+    functions: &mut Functions,
+    operations: &OperationRegistry,
+) -> Handled {
+    let result_tx = result_tx.clone();
+
+    match request {
+        Request::Math(request) => {
+            let id = request.id;
+            let respond = |id, res| Response::Result(MathResult { id, res });
+            queue(id, permit, pool, result_tx, respond, move |budget| {
+                evaluate(&request, budget)
+            })
+        }
+
+        // A batch only counts as one request, since it only costs us one frame. One that's too
+        // big gets turned away before it costs the client anything, same as over HTTP.
+        Request::Batch(BatchRequest { id, items }) => {
+            let respond = |id, res| Response::Batch(BatchResult { id, res });
+
+            if items.len() > MAX_BATCH_LEN {
+                let max = MAX_BATCH_LEN as u32;
+                return Handled::Answered(respond(id, Err(CalcError::BatchTooLarge { max })));
+            }
+
+            queue(id, permit, pool, result_tx, respond, move |budget| {
+                Ok(evaluate_batch(&items, budget, |item, _| {
+                    Ok(apply(&item.operation, item.a, item.b))
+                }))
+            })
+        }
+
+        // Opening and pushing count against the rate limit, but finishing doesn't since it only
+        // ever ends one that was let in. All of them are cheap enough to do right here.
+        Request::AggregateOpen { id } => {
+            match permit.check_request().and_then(|()| aggregations.open(id)) {
+                Ok(()) => Handled::Quietly,
+
+                Err(e) => {
+                    let agg_res = AggregateResult { id, res: Err(e) };
+                    Handled::Answered(Response::Aggregate(agg_res))
+                }
+            }
+        }
+
+        // Progress goes out on its own copy of the sender, ahead of the result
+        Request::Iterate(IterativeRequest { id, computation }) => {
+            let respond = |id, res| Response::Result(MathResult { id, res });
+            let progress_tx = result_tx.clone();
+
+            queue(id, permit, pool, result_tx, respond, move |budget| {
+                let mut reporter = Reporter::new(id, &progress_tx);
+                iterative::evaluate(&computation, budget, &mut reporter)
+            })
+        }
+
+        Request::PolynomialRoots(PolynomialRequest {
+            id,
+            coefficients,
+            convergence,
+        }) => {
+            let respond = |id, res| Response::Roots(RootsResult { id, res });
+            queue(id, permit, pool, result_tx, respond, move |budget| {
+                roots::polynomial(&coefficients, &convergence, budget)
+            })
+        }
+
+        Request::Integrate(request) => {
+            let id = request.id;
+            let respond = |id, res| Response::Estimate(EstimateResult { id, res });
+            queue(id, permit, pool, result_tx, respond, move |budget| {
+                calculus::integrate(&request, budget)
+            })
+        }
+
+        Request::Differentiate(request) => {
+            let id = request.id;
+            let respond = |id, res| Response::Estimate(EstimateResult { id, res });
+            queue(id, permit, pool, result_tx, respond, move |budget| {
+                calculus::differentiate(&request, budget)
+            })
+        }
+
+        Request::Symbolic(request) => {
+            let id = request.id;
+            let respond = |id, res| Response::Expression(ExpressionResult { id, res });
+            queue(id, permit, pool, result_tx, respond, move |budget| {
+                symbolic::evaluate(&request, budget)
+            })
+        }
+
+        Request::Matrix(MatrixRequest { id, operation }) => {
+            let respond = |id, res| Response::Matrix(MatrixResult { id, res });
+            queue(id, permit, pool, result_tx, respond, move |budget| {
+                linear::evaluate(&operation, budget)
+            })
+        }
+
+        Request::Complex(ComplexRequest { id, operation }) => {
+            let respond = |id, res| Response::Complex(ComplexResult { id, res });
+            queue(id, permit, pool, result_tx, respond, move |budget| {
+                evaluate_complex(&operation, budget)
+            })
+        }
+
+        Request::Integer(request) => {
+            let id = request.id;
+            let respond = |id, res| Response::Integer(IntegerResult { id, res });
+            queue(id, permit, pool, result_tx, respond, move |_| {
+                integer::evaluate(&request)
+            })
+        }
+
+        Request::Statistics(StatisticsRequest { id, operation }) => {
+            let respond = |id, res| Response::Result(MathResult { id, res });
+            queue(id, permit, pool, result_tx, respond, move |budget| {
+                statistics::evaluate(&operation, budget)
+            })
+        }
+
+        Request::Units(UnitRequest { id, operation }) => {
+            let respond = |id, res| Response::Quantity(QuantityResult { id, res });
+            queue(id, permit, pool, result_tx, respond, move |_| {
+                units::evaluate(&operation)
+            })
+        }
+
+        // Functions only ever get parsed here, which is no more work than for any other request
+        // with an expression in it, so there's no need to queue them up
+        Request::Functions(FunctionRequest { id, operation }) => {
+            let res = permit
+                .check_request()
+                .and_then(|()| functions.apply(&operation));
+            Handled::Answered(Response::Functions(FunctionsResult { id, res }))
+        }
+
+        // The evaluation gets the functions as they are now, so redefining them while it runs
+        // doesn't change its answer
+        Request::Evaluate(EvaluateRequest { id, expression }) => {
+            let defined = functions.defined();
+            let respond = |id, res| Response::Result(MathResult { id, res });
+
+            queue(id, permit, pool, result_tx, respond, move |budget| {
+                let expr = Expr::parse(&expression)?;
+                evaluate_with(&expr, &[], &defined, budget)
+            })
+        }
+
+        // Asking for an operation we don't have is answered right away, before it can cost the
+        // client anything against its rate limit
+        Request::Custom(CustomRequest {
+            id,
+            operation,
+            arguments,
+        }) => {
+            let respond = |id, res| Response::Result(MathResult { id, res });
+
+            let handler = match operations.get(&operation) {
+                Some(handler) => handler,
+                None => {
+                    let res = Err(CalcError::UnknownOperation(operation));
+                    return Handled::Answered(respond(id, res));
+                }
+            };
+
+            queue(id, permit, pool, result_tx, respond, move |budget| {
+                operations::call(&*handler, &arguments, budget)
+            })
+        }
+
+        // There's no answer to a push, so being turned away waits for the finish like any other
+        // problem with it
+        Request::AggregatePush { id, values } => {
+            match permit.check_request() {
+                Ok(()) => aggregations.push(id, &values),
+                Err(e) => aggregations.fail(id, e),
+            }
+
+            Handled::Quietly
+        }
+
+        Request::AggregateFinish { id, percentiles } => {
+            let res = aggregations.finish(id, &percentiles);
+            Handled::Answered(Response::Aggregate(AggregateResult { id, res }))
+        }
+    }
+}
+
+/// Puts `evaluation` on the worker pool, if the rate limit lets it through. Whatever it comes up
+/// with, or why it never got to run, goes back to the client the way `respond` wraps it.
+fn queue<T, F>(
+    id: u32,
+    permit: &mut ConnectionPermit,
+    pool: &WorkerPool,
+    result_tx: mpsc::UnboundedSender<Response>,
+    respond: fn(u32, Result<T, CalcError>) -> Response,
+    evaluation: F,
+) -> Handled
+where
+    T: 'static,
+    F: FnOnce(&Budget) -> Result<T, CalcError> + Send + 'static,
+{
+    // Over the rate limit requests get turned away before they cost us anything
+    let queued = permit.check_request().and_then(|()| {
+        pool.execute(move |budget| {
+            let res = isolated(|| evaluation(budget));
+
+            // The connection might have died while we were working on this
+            let _ = result_tx.unbounded_send(respond(id, res));
+        })
+    });
+
+    match queued {
+        Ok(()) => Handled::Queued,
+        Err(e) => Handled::Answered(respond(id, Err(e))),
+    }
+}
+
+/// Tells a client we won't serve it before hanging up on it
+pub async fn reject_client<S>(mut stream: S, error: CalcError) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut response_sink: SerealSink<Response, _> = SerealSink::new(&mut stream);
+
+    response_sink.send(&Response::Rejected(error)).await
+}
+
+/// Runs `evaluation` on the worker pool, for callers that only want the one result back
+#[cfg(feature = "http")]
+pub async fn evaluate_on<T, F>(pool: &WorkerPool, evaluation: F) -> Result<T, CalcError>
+where
+    T: Send + 'static,
+    F: FnOnce(&Budget) -> Result<T, CalcError> + Send + 'static,
+{
+    let (result_tx, result_rx) = oneshot::channel();
+
+    pool.execute(move |budget| {
+        // Whoever asked might not be around anymore
+        let _ = result_tx.send(isolated(|| evaluation(budget)));
+    })?;
+
+    // The pool runs every job it takes, so the sender only goes away after sending
+    result_rx.await.expect("worker dropped a job")
+}
+
+/// Runs an evaluation, turning a panic into an error for the client instead of taking the whole
+/// connection down with it
+pub fn isolated<T, F>(evaluation: F) -> Result<T, CalcError>
+where
+    F: FnOnce() -> Result<T, CalcError>,
+{
+    panic::catch_unwind(AssertUnwindSafe(evaluation))
+        .unwrap_or_else(|payload| Err(CalcError::Internal(panic_message(payload))))
+}
+
+pub fn evaluate(request: &MathRequest, budget: &Budget) -> Result<f64, CalcError> {
+    let res = apply(&request.operation, request.a, request.b);
+
+    // None of these can run away, but anything that loops should check the budget as it goes
+    budget.check()?;
+
+    Ok(res)
+}
+
+pub fn evaluate_complex(
+    operation: &ComplexOperation,
+    budget: &Budget,
+) -> Result<ComplexValue, CalcError> {
+    let res = match *operation {
+        ComplexOperation::Binary(ref operation, a, b) => {
+            ComplexValue::Complex(apply_complex(operation, a, b))
+        }
+
+        ComplexOperation::Sqrt(z) => ComplexValue::Complex(z.sqrt()),
+        ComplexOperation::Exp(z) => ComplexValue::Complex(z.exp()),
+        ComplexOperation::Ln(z) => ComplexValue::Complex(z.ln()),
+        ComplexOperation::Abs(z) => ComplexValue::Real(z.abs()),
+        ComplexOperation::Arg(z) => ComplexValue::Real(z.arg()),
+        ComplexOperation::Conjugate(z) => ComplexValue::Complex(z.conj()),
+        ComplexOperation::ToPolar(z) => ComplexValue::Polar(z.to_polar()),
+        ComplexOperation::FromPolar(polar) => ComplexValue::Complex(Complex::from_polar(polar)),
+    };
+
+    // Same as `evaluate`, none of these loop so there's nothing to cut short
+    budget.check()?;
+
+    Ok(res)
+}
+
+/// Evaluates every item of a batch in order with `evaluate`. The budget covers the whole batch,
+/// so once it runs out the rest of the items all get timed out without being looked at.
+pub fn evaluate_batch<T, F>(
+    items: &[T],
+    budget: &Budget,
+    evaluate: F,
+) -> Vec<Result<f64, CalcError>>
+where
+    F: Fn(&T, &Budget) -> Result<f64, CalcError>,
+{
+    let mut results = Vec::with_capacity(items.len());
+
+    for item in items {
+        if let Err(e) = budget.check() {
+            results.resize(items.len(), Err(e));
+            break;
+        }
+
+        results.push(isolated(|| evaluate(item, budget)));
+    }
+
+    results
+}
+
+/// Walks the whole tree, so it checks the budget at every node in case the tree is a big one.
+/// Variables get their values from `variables`, and one that isn't in there is an error. Only
+/// the built-in functions can be called.
+pub fn evaluate_expression(
+    expr: &Expr,
+    variables: &[(&str, f64)],
+    budget: &Budget,
+) -> Result<f64, CalcError> {
+    evaluate_with(expr, variables, &HashMap::new(), budget)
+}
+
+/// The same as `evaluate_expression`, except that `functions` can be called too
+pub fn evaluate_with(
+    expr: &Expr,
+    variables: &[(&str, f64)],
+    functions: &HashMap<String, Function>,
+    budget: &Budget,
+) -> Result<f64, CalcError> {
+    let evaluation = Evaluation { functions, budget };
+
+    evaluation.evaluate(expr, variables, Depth { nodes: 0, calls: 0 })
+}
+
+/// What an evaluation needs at every node, besides the variables in scope there
+struct Evaluation<'a> {
+    functions: &'a HashMap<String, Function>,
+    budget: &'a Budget,
+}
+
+/// How deep an evaluation is at some node, counting calls on their own too since they're what
+/// takes it deeper than the expression it started from
+#[derive(Clone, Copy)]
+struct Depth {
+    nodes: usize,
+    calls: usize,
+}
+
+impl<'a> Evaluation<'a> {
+    fn evaluate(
+        &self,
+        expr: &Expr,
+        variables: &[(&str, f64)],
+        depth: Depth,
+    ) -> Result<f64, CalcError> {
+        self.budget.check()?;
+
+        if depth.nodes >= MAX_EVALUATION_DEPTH {
+            return Err(CalcError::InvalidExpression(
+                "calls nest too deeply".to_string(),
+            ));
+        }
+
+        let inner = Depth {
+            nodes: depth.nodes + 1,
+            ..depth
+        };
+
+        Ok(match expr {
+            Expr::Number(num) => *num,
+            Expr::Negate(operand) => -self.evaluate(operand, variables, inner)?,
+
+            Expr::Variable(name) => match variables.iter().find(|(var, _)| var == name) {
+                Some(&(_, value)) => value,
+                None => {
+                    let msg = format!("unknown variable `{}`", name);
+                    return Err(CalcError::InvalidExpression(msg));
+                }
+            },
+
+            Expr::Binary(operation, lhs, rhs) => {
+                let lhs = self.evaluate(lhs, variables, inner)?;
+                let rhs = self.evaluate(rhs, variables, inner)?;
+
+                apply(operation, lhs, rhs)
+            }
+
+            Expr::Call(name, arguments) => self.call(name, arguments, variables, inner)?,
+        })
+    }
+
+    /// Kept apart from `evaluate` so that only calls pay for the stack space this takes
+    fn call(
+        &self,
+        name: &str,
+        arguments: &[Expr],
+        variables: &[(&str, f64)],
+        depth: Depth,
+    ) -> Result<f64, CalcError> {
+        if let Some(builtin) = Builtin::lookup(name) {
+            check_arity(name, 1, arguments)?;
+
+            let value = self.evaluate(&arguments[0], variables, depth)?;
+            return Ok(builtin.apply(value));
+        }
+
+        let function = match self.functions.get(name) {
+            Some(function) => function,
+            None => {
+                let msg = format!("unknown function `{}`", name);
+                return Err(CalcError::InvalidExpression(msg));
+            }
+        };
+
+        check_arity(name, function.parameters.len(), arguments)?;
+
+        if depth.calls >= MAX_CALL_DEPTH {
+            let msg = format!(
+                "calls nest more than {} deep, `{}` might be calling itself forever",
+                MAX_CALL_DEPTH, name
+            );
+
+            return Err(CalcError::InvalidExpression(msg));
+        }
+
+        let values = arguments
+            .iter()
+            .map(|argument| self.evaluate(argument, variables, depth))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let parameters: Vec<(&str, f64)> = function
+            .parameters
+            .iter()
+            .map(String::as_str)
+            .zip(values)
+            .collect();
+
+        let depth = Depth {
+            calls: depth.calls + 1,
+            ..depth
+        };
+
+        self.evaluate(&function.body, &parameters, depth)
+    }
+}
+
+fn check_arity(name: &str, arity: usize, arguments: &[Expr]) -> Result<(), CalcError> {
+    if arguments.len() == arity {
+        return Ok(());
+    }
+
+    let plural = if arity == 1 { "" } else { "s" };
+    let msg = format!(
+        "`{}` takes {} argument{}, not {}",
+        name,
+        arity,
+        plural,
+        arguments.len()
+    );
+
+    Err(CalcError::InvalidExpression(msg))
+}
+
+/// `expr` as a function of `variable` alone, for the numerical methods to work on. A NaN has no
+/// sign or size, so none of them can tell where to go from one and it gets turned into an error
+/// that says where it came from.
+pub fn function_of<'a>(
+    expr: &'a Expr,
+    variable: &'a str,
+    budget: &'a Budget,
+) -> impl Fn(f64) -> Result<f64, CalcError> + 'a {
+    move |x| match evaluate_expression(expr, &[(variable, x)], budget)? {
+        y if y.is_nan() => {
+            let msg = format!("the expression is not a number at {} = {}", variable, x);
+            Err(CalcError::InvalidArgument(msg))
+        }
+
+        y => Ok(y),
+    }
+}
+
+fn apply(operation: &Operation, a: f64, b: f64) -> f64 {
+    match operation {
+        Operation::Addition => a + b,
+        Operation::Subtraction => a - b,
+        Operation::Multiplication => a * b,
+        Operation::Division => a / b,
+        Operation::Power => a.powf(b),
+    }
+}
+
+fn apply_complex(operation: &Operation, a: Complex, b: Complex) -> Complex {
+    match operation {
+        Operation::Addition => a + b,
+        Operation::Subtraction => a - b,
+        Operation::Multiplication => a * b,
+        Operation::Division => a / b,
+        Operation::Power => a.powc(b),
+    }
+}
+
+/// Panics are usually created with a `&str` or a formatted `String`, anything else we can't say
+/// much about
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(msg) => msg.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
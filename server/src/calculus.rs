@@ -0,0 +1,210 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use calc_utils::{CalcError, DerivativeRequest, Estimate, Expr, IntegralRequest};
+
+use crate::calculator::function_of;
+use crate::roots::check_convergence;
+use crate::worker_pool::Budget;
+
+/// Where the 15-point Kronrod rule evaluates, as a fraction of the way from the middle of an
+/// interval to its ends. Every other one of these, starting from the second, is also where the
+/// 7-point Gauss rule evaluates, which is what makes comparing the two so cheap.
+const KRONROD_NODES: [f64; 8] = [
+    0.991_455_371_120_812_6,
+    0.949_107_912_342_758_5,
+    0.864_864_423_359_769_1,
+    0.741_531_185_599_394_4,
+    0.586_087_235_467_691_1,
+    0.405_845_151_377_397_2,
+    0.207_784_955_007_898_5,
+    0.0,
+];
+
+const KRONROD_WEIGHTS: [f64; 8] = [
+    0.022_935_322_010_529_22,
+    0.063_092_092_629_978_55,
+    0.104_790_010_322_250_18,
+    0.140_653_259_715_525_92,
+    0.169_004_726_639_267_9,
+    0.190_350_578_064_785_4,
+    0.204_432_940_075_298_9,
+    0.209_482_141_084_727_83,
+];
+
+/// The Gauss weights for the 2nd, 4th, 6th and 8th of the Kronrod nodes
+const GAUSS_WEIGHTS: [f64; 4] = [
+    0.129_484_966_168_869_7,
+    0.279_705_391_489_276_7,
+    0.381_830_050_505_118_9,
+    0.417_959_183_673_469_4,
+];
+
+/// How many times smaller each step Ridders' method takes is than the one before
+const RIDDERS_SHRINK: f64 = 1.4;
+
+/// Most steps Ridders' method takes, which is also the most times it extrapolates
+const RIDDERS_STEPS: usize = 10;
+
+/// A piece of the interval being integrated, with what the Kronrod rule made of it
+#[derive(Debug)]
+struct Piece {
+    lower: f64,
+    upper: f64,
+    estimate: Estimate,
+}
+
+/// Adaptive Gauss–Kronrod quadrature. We keep splitting whichever piece has the biggest error in
+/// half, until all the errors together are small enough.
+pub fn integrate(request: &IntegralRequest, budget: &Budget) -> Result<Estimate, CalcError> {
+    let IntegralRequest {
+        lower,
+        upper,
+        convergence,
+        ..
+    } = *request;
+
+    check_convergence(&convergence)?;
+
+    if !lower.is_finite() || !upper.is_finite() {
+        let msg = format!("[{}, {}] is not a finite interval", lower, upper);
+        return Err(CalcError::InvalidArgument(msg));
+    }
+
+    let expr = Expr::parse(&request.expression)?;
+    let f = function_of(&expr, &request.variable, budget);
+
+    let mut pieces = vec![piece(&f, lower, upper)?];
+
+    loop {
+        let total = pieces.iter().fold(
+            Estimate {
+                value: 0.0,
+                error: 0.0,
+            },
+            |total, piece| Estimate {
+                value: total.value + piece.estimate.value,
+                error: total.error + piece.estimate.error,
+            },
+        );
+
+        if total.error <= convergence.tolerance * total.value.abs().max(1.0) {
+            return Ok(total);
+        }
+
+        let worst = pieces
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.estimate.error.total_cmp(&b.estimate.error))
+            .map(|(i, _)| i)
+            .expect("nothing to integrate");
+
+        let iterations = pieces.len() as u32;
+        let Piece { lower, upper, .. } = pieces.swap_remove(worst);
+        let mid = lower + (upper - lower) / 2.0;
+
+        // Either we're out of pieces, or the worst one is already as small as a piece can get,
+        // which happens around a singularity
+        if iterations >= convergence.max_iterations || mid == lower || mid == upper {
+            return Err(CalcError::DidNotConverge { iterations });
+        }
+
+        pieces.push(piece(&f, lower, mid)?);
+        pieces.push(piece(&f, mid, upper)?);
+    }
+}
+
+/// Ridders' method, which takes central differences with smaller and smaller steps and
+/// extrapolates them towards a step of zero. Comparing the extrapolations with each other is what
+/// gives us the error.
+pub fn differentiate(request: &DerivativeRequest, budget: &Budget) -> Result<Estimate, CalcError> {
+    let at = request.at;
+
+    if !at.is_finite() {
+        return Err(CalcError::InvalidArgument(format!("{} is not finite", at)));
+    }
+
+    let expr = Expr::parse(&request.expression)?;
+    let f = function_of(&expr, &request.variable, budget);
+
+    let central = |h: f64| -> Result<f64, CalcError> { Ok((f(at + h)? - f(at - h)?) / (2.0 * h)) };
+
+    // Row `j` holds the differences extrapolated `j` times, for every step so far
+    let mut table = [[0.0; RIDDERS_STEPS]; RIDDERS_STEPS];
+
+    let mut h = 0.1 * at.abs().max(1.0);
+    table[0][0] = central(h)?;
+
+    let mut best = Estimate {
+        value: table[0][0],
+        error: f64::INFINITY,
+    };
+
+    for i in 1..RIDDERS_STEPS {
+        h /= RIDDERS_SHRINK;
+        table[0][i] = central(h)?;
+
+        let mut factor = RIDDERS_SHRINK * RIDDERS_SHRINK;
+
+        for j in 1..=i {
+            table[j][i] = (table[j - 1][i] * factor - table[j - 1][i - 1]) / (factor - 1.0);
+            factor *= RIDDERS_SHRINK * RIDDERS_SHRINK;
+
+            let error = (table[j][i] - table[j - 1][i])
+                .abs()
+                .max((table[j][i] - table[j - 1][i - 1]).abs());
+
+            if error <= best.error {
+                best = Estimate {
+                    value: table[j][i],
+                    error,
+                };
+            }
+        }
+
+        // Once rounding starts to win out over the smaller steps, things only get worse
+        if (table[i][i] - table[i - 1][i - 1]).abs() >= 2.0 * best.error {
+            break;
+        }
+    }
+
+    Ok(best)
+}
+
+/// Integrates `f` from `lower` to `upper` with both rules. The Kronrod one is far more accurate,
+/// so we go with it and take how much the Gauss one differs as its error.
+fn piece<F>(f: F, lower: f64, upper: f64) -> Result<Piece, CalcError>
+where
+    F: Fn(f64) -> Result<f64, CalcError>,
+{
+    let center = lower + (upper - lower) / 2.0;
+    let half = (upper - lower) / 2.0;
+
+    let mut kronrod = 0.0;
+    let mut gauss = 0.0;
+
+    for (i, (&node, &weight)) in KRONROD_NODES.iter().zip(&KRONROD_WEIGHTS).enumerate() {
+        // The middle only counts once
+        let sum = if node == 0.0 {
+            f(center)?
+        } else {
+            f(center - half * node)? + f(center + half * node)?
+        };
+
+        kronrod += weight * sum;
+
+        if i % 2 == 1 {
+            gauss += GAUSS_WEIGHTS[i / 2] * sum;
+        }
+    }
+
+    Ok(Piece {
+        lower,
+        upper,
+        estimate: Estimate {
+            value: kronrod * half,
+            error: ((kronrod - gauss) * half).abs(),
+        },
+    })
+}
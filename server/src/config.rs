@@ -0,0 +1,134 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::env;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// Everything about the server that can be changed from the command line
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Where to read the authentication keys from. Without one, anyone can connect.
+    pub key_file: Option<PathBuf>,
+
+    /// The certificate chain and private key to serve TLS with. Without them we speak plain TCP.
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+
+    /// When set, clients have to present a certificate signed by one of these CAs
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Also accept WebSocket connections on this address, for clients that can't open a plain
+    /// TCP connection like browsers
+    pub websocket: Option<SocketAddr>,
+
+    /// Also serve the JSON API over HTTP on this address
+    #[cfg(feature = "http")]
+    pub http: Option<SocketAddr>,
+
+    /// Also answer requests sent as UDP datagrams on this address
+    pub udp: Option<SocketAddr>,
+
+    /// Also listen on a Unix socket at this path, for clients on the same host
+    pub unix_socket: Option<PathBuf>,
+
+    /// Permission bits for the Unix socket file, which decide who on the host can connect
+    pub unix_socket_mode: Option<u32>,
+
+    /// Plugin libraries to load operations from, which can be given any number of times
+    pub plugins: Vec<PathBuf>,
+
+    /// WebAssembly modules to load operations from, which can also be given any number of times
+    #[cfg(feature = "wasm")]
+    pub wasm_modules: Vec<PathBuf>,
+
+    /// A directory of WebAssembly modules that keeps getting checked while the server runs, so
+    /// operations can be added, replaced and taken away without a restart
+    #[cfg(feature = "wasm")]
+    pub wasm_dir: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn from_args() -> io::Result<Config> {
+        let mut config = Config::default();
+        let mut args = env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--key-file" => config.key_file = Some(value(&arg, args.next())?.into()),
+                "--tls-cert" => config.tls_cert = Some(value(&arg, args.next())?.into()),
+                "--tls-key" => config.tls_key = Some(value(&arg, args.next())?.into()),
+                "--tls-client-ca" => config.tls_client_ca = Some(value(&arg, args.next())?.into()),
+                "--websocket" => config.websocket = Some(socket_addr(&arg, args.next())?),
+
+                #[cfg(feature = "http")]
+                "--http" => config.http = Some(socket_addr(&arg, args.next())?),
+
+                "--udp" => config.udp = Some(socket_addr(&arg, args.next())?),
+                "--unix-socket" => config.unix_socket = Some(value(&arg, args.next())?.into()),
+
+                "--unix-socket-mode" => {
+                    let mode = value(&arg, args.next())?;
+                    let mode = u32::from_str_radix(&mode, 8)
+                        .map_err(|_| invalid(format!("`{}` is not an octal mode", mode)))?;
+
+                    config.unix_socket_mode = Some(mode);
+                }
+
+                "--plugin" => config.plugins.push(value(&arg, args.next())?.into()),
+
+                #[cfg(feature = "wasm")]
+                "--wasm" => config.wasm_modules.push(value(&arg, args.next())?.into()),
+
+                #[cfg(feature = "wasm")]
+                "--wasm-dir" => config.wasm_dir = Some(value(&arg, args.next())?.into()),
+
+                _ => return Err(invalid(format!("unknown argument `{}`", arg))),
+            }
+        }
+
+        if config.tls_cert.is_some() != config.tls_key.is_some() {
+            return Err(invalid(
+                "`--tls-cert` and `--tls-key` go together".to_string(),
+            ));
+        }
+
+        if config.tls_client_ca.is_some() && config.tls_cert.is_none() {
+            return Err(invalid(
+                "`--tls-client-ca` needs `--tls-cert` and `--tls-key`".to_string(),
+            ));
+        }
+
+        // Datagrams come without a challenge to answer, so there'd be no way to keep anyone out
+        if config.udp.is_some() && config.key_file.is_some() {
+            return Err(invalid(
+                "`--udp` can't be used with `--key-file`".to_string(),
+            ));
+        }
+
+        if config.unix_socket_mode.is_some() && config.unix_socket.is_none() {
+            return Err(invalid(
+                "`--unix-socket-mode` needs `--unix-socket`".to_string(),
+            ));
+        }
+
+        Ok(config)
+    }
+}
+
+fn value(arg: &str, value: Option<String>) -> io::Result<String> {
+    value.ok_or_else(|| invalid(format!("`{}` needs a value", arg)))
+}
+
+fn socket_addr(arg: &str, value: Option<String>) -> io::Result<SocketAddr> {
+    let addr = self::value(arg, value)?;
+
+    addr.parse()
+        .map_err(|_| invalid(format!("`{}` is not a socket address", addr)))
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
@@ -0,0 +1,64 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use calc_utils::{CalcError, Function, FunctionOperation};
+
+/// Most functions a connection can have defined at the same time
+const MAX_FUNCTIONS: usize = 64;
+
+/// The functions a connection has defined. Evaluations share them with us while they run, so
+/// changing them makes a copy if any evaluation is still holding on to the old ones.
+#[derive(Debug, Default)]
+pub struct Functions {
+    defined: Arc<HashMap<String, Function>>,
+}
+
+impl Functions {
+    pub fn apply(&mut self, operation: &FunctionOperation) -> Result<Vec<Function>, CalcError> {
+        match operation {
+            FunctionOperation::Define(definition) => {
+                let function = Function::parse(definition)?;
+
+                let replacing = self.defined.contains_key(&function.name);
+
+                if !replacing && self.defined.len() >= MAX_FUNCTIONS {
+                    let msg = format!("at most {} functions can be defined at once", MAX_FUNCTIONS);
+                    return Err(CalcError::InvalidArgument(msg));
+                }
+
+                Arc::make_mut(&mut self.defined).insert(function.name.clone(), function.clone());
+
+                Ok(vec![function])
+            }
+
+            FunctionOperation::Remove(name) => {
+                if !self.defined.contains_key(name) {
+                    return Err(CalcError::InvalidArgument(format!(
+                        "`{}` is not defined",
+                        name
+                    )));
+                }
+
+                let function = Arc::make_mut(&mut self.defined).remove(name);
+
+                Ok(function.into_iter().collect())
+            }
+
+            FunctionOperation::List => {
+                let mut functions: Vec<Function> = self.defined.values().cloned().collect();
+                functions.sort_by(|a, b| a.name.cmp(&b.name));
+
+                Ok(functions)
+            }
+        }
+    }
+
+    /// The functions as they are right now, for an evaluation to call
+    pub fn defined(&self) -> Arc<HashMap<String, Function>> {
+        self.defined.clone()
+    }
+}
@@ -0,0 +1,390 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::convert::Infallible;
+use std::io;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+use futures::future;
+use hyper::header::{self, HeaderValue};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use calc_utils::{CalcError, Credentials, Expr, MathRequest, Operation};
+
+use crate::auth::Authenticator;
+use crate::calculator::{
+    evaluate, evaluate_batch, evaluate_expression, evaluate_on, MAX_BATCH_LEN,
+};
+use crate::limits::{ConnectionPermit, Limits};
+use crate::operations::{self, OperationRegistry};
+use crate::shutdown::ShutdownSignal;
+use crate::worker_pool::{Budget, WorkerPool};
+
+/// Biggest request body we'll read, anything more gets a 413
+const MAX_BODY_LEN: usize = 64 * 1024;
+
+/// What the HTTP handlers share with the rest of the server
+#[derive(Debug, Clone)]
+pub struct Api {
+    pub pool: WorkerPool,
+    pub limits: Limits,
+    pub auth: Arc<Authenticator>,
+    pub operations: Arc<OperationRegistry>,
+}
+
+/// Whether an HTTP connection got let in, shared by all the requests that come in on it
+type Admission = Arc<Mutex<Result<ConnectionPermit, CalcError>>>;
+
+/// One calculation in a request body, either written out or already split up
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Calculation {
+    Expression {
+        expression: String,
+    },
+    Operation {
+        operation: Operation,
+        a: f64,
+        b: f64,
+    },
+    Custom {
+        operation: String,
+        arguments: Vec<f64>,
+    },
+}
+
+/// Why a request didn't get a result, turned into a status code and a JSON body at the end
+#[derive(Debug)]
+enum ApiError {
+    Calc(CalcError),
+    BadRequest(String),
+    NotFound,
+    MethodNotAllowed,
+    PayloadTooLarge,
+}
+
+/// Serves the JSON API on `listener` until `shutdown` is triggered, then lets the requests that
+/// are already in flight finish
+///
+/// * `POST /v1/eval` takes one calculation and answers with `{"result": ...}`
+/// * `POST /v1/batch` takes a list of them and answers with a `{"results": [...]}` list, where
+///   every entry has either a result or an error
+///
+/// A calculation is `{"expression": "1 + 2 * 3"}` or `{"operation": "Addition", "a": 1, "b": 2}`,
+/// or `{"operation": "hypot", "arguments": [3, 4]}` for one of the registered operations.
+/// When the server has keys, requests need an `Authorization: Bearer <secret>` header.
+pub async fn serve(
+    listener: TcpListener,
+    api: Api,
+    mut shutdown: ShutdownSignal,
+) -> io::Result<()> {
+    let server = Server::from_tcp(listener).map_err(hyper_error)?;
+
+    let make_service = make_service_fn(move |conn: &AddrStream| {
+        // Every HTTP connection counts against the same limits as any other connection
+        let admitted = api.limits.admit(conn.remote_addr().ip());
+        let admission: Admission = Arc::new(Mutex::new(admitted));
+        let api = api.clone();
+
+        future::ok::<_, Infallible>(service_fn(move |request| {
+            handle(api.clone(), admission.clone(), request)
+        }))
+    });
+
+    // We hold on to our own signal until everything is done, so draining waits for us too
+    server
+        .serve(make_service)
+        .with_graceful_shutdown(&mut shutdown)
+        .await
+        .map_err(hyper_error)
+}
+
+async fn handle(
+    api: Api,
+    admission: Admission,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let res = match (request.method(), request.uri().path()) {
+        (&Method::POST, "/v1/eval") => eval(&api, &admission, request).await,
+        (&Method::POST, "/v1/batch") => batch(&api, &admission, request).await,
+
+        (_, "/v1/eval") | (_, "/v1/batch") => Err(ApiError::MethodNotAllowed),
+        _ => Err(ApiError::NotFound),
+    };
+
+    Ok(match res {
+        Ok(body) => json_response(StatusCode::OK, &body),
+        Err(e) => e.into_response(),
+    })
+}
+
+async fn eval(api: &Api, admission: &Admission, request: Request<Body>) -> Result<Value, ApiError> {
+    let calculation: Calculation = read_json(api, request).await?;
+    let result = calculate(api, admission, calculation)
+        .await
+        .map_err(ApiError::Calc)?;
+
+    Ok(json!({ "result": result }))
+}
+
+async fn batch(
+    api: &Api,
+    admission: &Admission,
+    request: Request<Body>,
+) -> Result<Value, ApiError> {
+    let calculations: Vec<Calculation> = read_json(api, request).await?;
+
+    if calculations.len() > MAX_BATCH_LEN {
+        let max = MAX_BATCH_LEN as u32;
+        return Err(ApiError::Calc(CalcError::BatchTooLarge { max }));
+    }
+
+    // A batch only counts as one request, the same as on a connection, and goes to a single
+    // worker as a whole
+    charge(admission).map_err(ApiError::Calc)?;
+
+    let operations = api.operations.clone();
+
+    let results = evaluate_on(&api.pool, move |budget| {
+        Ok(evaluate_batch(
+            &calculations,
+            budget,
+            |calculation, budget| run(&operations, calculation, budget),
+        ))
+    })
+    .await
+    .map_err(ApiError::Calc)?;
+
+    let results = results
+        .into_iter()
+        .map(|res| match res {
+            Ok(result) => json!({ "result": result }),
+            Err(e) => ApiError::Calc(e).body(),
+        })
+        .collect::<Vec<_>>();
+
+    Ok(json!({ "results": results }))
+}
+
+/// Checks who's asking, then reads the whole body as JSON
+async fn read_json<T>(api: &Api, request: Request<Body>) -> Result<T, ApiError>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    let credentials = match request.headers().get(header::AUTHORIZATION) {
+        Some(value) => match value.to_str().ok().and_then(|v| v.strip_prefix("Bearer ")) {
+            Some(token) => Credentials::Token(token.trim().to_string()),
+            None => return Err(ApiError::Calc(CalcError::Unauthenticated)),
+        },
+
+        None => Credentials::Anonymous,
+    };
+
+    // Nobody can sign a challenge over plain HTTP, so there's no nonce to check against
+    api.auth.verify(&[], &credentials).map_err(ApiError::Calc)?;
+
+    let mut body = request.into_body();
+    let mut bytes = Vec::new();
+
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| ApiError::BadRequest(e.to_string()))?;
+
+        if bytes.len() + chunk.len() > MAX_BODY_LEN {
+            return Err(ApiError::PayloadTooLarge);
+        }
+
+        bytes.extend_from_slice(&chunk);
+    }
+
+    serde_json::from_slice(&bytes).map_err(|e| ApiError::BadRequest(e.to_string()))
+}
+
+/// Evaluates one calculation on the worker pool, the same way a request on a connection would be
+async fn calculate(
+    api: &Api,
+    admission: &Admission,
+    calculation: Calculation,
+) -> Result<f64, CalcError> {
+    // Same as on a connection, asking for an operation we don't have doesn't cost anything
+    if let Calculation::Custom { operation, .. } = &calculation {
+        if api.operations.get(operation).is_none() {
+            return Err(CalcError::UnknownOperation(operation.clone()));
+        }
+    }
+
+    charge(admission)?;
+
+    let operations = api.operations.clone();
+    evaluate_on(&api.pool, move |budget| {
+        run(&operations, &calculation, budget)
+    })
+    .await
+}
+
+/// Counts a request against the connection's rate limit, if it was let in at all
+fn charge(admission: &Admission) -> Result<(), CalcError> {
+    match &mut *admission.lock().unwrap() {
+        Ok(permit) => permit.check_request(),
+        Err(e) => Err(e.clone()),
+    }
+}
+
+/// Works out one calculation, on a worker
+fn run(
+    operations: &OperationRegistry,
+    calculation: &Calculation,
+    budget: &Budget,
+) -> Result<f64, CalcError> {
+    match calculation {
+        Calculation::Expression { expression } => {
+            evaluate_expression(&Expr::parse(expression)?, &[], budget)
+        }
+
+        Calculation::Operation { operation, a, b } => {
+            let request = MathRequest {
+                id: 0,
+                operation: operation.clone(),
+                a: *a,
+                b: *b,
+            };
+            evaluate(&request, budget)
+        }
+
+        Calculation::Custom {
+            operation,
+            arguments,
+        } => {
+            let handler = operations.get(operation);
+            let handler = handler.ok_or_else(|| CalcError::UnknownOperation(operation.clone()))?;
+            operations::call(&*handler, arguments, budget)
+        }
+    }
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::Calc(e) => match e {
+                CalcError::InvalidExpression(_)
+                | CalcError::InvalidAggregation(_)
+                | CalcError::InvalidArgument(_)
+                | CalcError::DimensionMismatch { .. }
+                | CalcError::IncompatibleUnits { .. } => StatusCode::BAD_REQUEST,
+
+                CalcError::UnknownOperation(_) => StatusCode::NOT_FOUND,
+
+                // Nothing wrong with how it was asked, there just wasn't an answer to be found
+                CalcError::DidNotConverge { .. } | CalcError::Singular | CalcError::Overflow => {
+                    StatusCode::UNPROCESSABLE_ENTITY
+                }
+
+                CalcError::BatchTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+                CalcError::Unauthenticated => StatusCode::UNAUTHORIZED,
+                CalcError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+                CalcError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+
+                // The server couldn't do it right now, but might be able to later
+                CalcError::Overloaded | CalcError::TimedOut | CalcError::TooManyConnections => {
+                    StatusCode::SERVICE_UNAVAILABLE
+                }
+            },
+
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+
+    /// `{"error": <kind>, "message": <for humans>}`, plus whatever else the kind has to say
+    fn body(&self) -> Value {
+        let (kind, message) = match self {
+            ApiError::Calc(e) => {
+                let kind = match e {
+                    CalcError::Internal(_) => "internal",
+                    CalcError::Overloaded => "overloaded",
+                    CalcError::TimedOut => "timed_out",
+                    CalcError::RateLimited { .. } => "rate_limited",
+                    CalcError::TooManyConnections => "too_many_connections",
+                    CalcError::Unauthenticated => "unauthenticated",
+                    CalcError::InvalidExpression(_) => "invalid_expression",
+                    CalcError::BatchTooLarge { .. } => "batch_too_large",
+                    CalcError::InvalidAggregation(_) => "invalid_aggregation",
+                    CalcError::DidNotConverge { .. } => "did_not_converge",
+                    CalcError::InvalidArgument(_) => "invalid_argument",
+                    CalcError::DimensionMismatch { .. } => "dimension_mismatch",
+                    CalcError::Singular => "singular",
+                    CalcError::Overflow => "overflow",
+                    CalcError::IncompatibleUnits { .. } => "incompatible_units",
+                    CalcError::UnknownOperation(_) => "unknown_operation",
+                };
+
+                (kind, e.to_string())
+            }
+
+            ApiError::BadRequest(msg) => ("bad_request", msg.clone()),
+            ApiError::NotFound => ("not_found", "no such endpoint".to_string()),
+            ApiError::MethodNotAllowed => ("method_not_allowed", "use POST".to_string()),
+
+            ApiError::PayloadTooLarge => (
+                "payload_too_large",
+                format!("bodies can be at most {} bytes", MAX_BODY_LEN),
+            ),
+        };
+
+        let mut body = json!({ "error": kind, "message": message });
+
+        if let ApiError::Calc(CalcError::RateLimited { retry_after }) = self {
+            body["retry_after_ms"] = json!(retry_after.as_millis() as u64);
+        }
+
+        body
+    }
+
+    fn into_response(self) -> Response<Body> {
+        let mut response = json_response(self.status(), &self.body());
+        let headers = response.headers_mut();
+
+        match &self {
+            ApiError::Calc(CalcError::RateLimited { retry_after }) => {
+                // Retry-After only does whole seconds, so round up
+                let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                headers.insert(header::RETRY_AFTER, HeaderValue::from(secs));
+            }
+
+            ApiError::Calc(CalcError::Unauthenticated) => {
+                headers.insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+
+            ApiError::MethodNotAllowed => {
+                headers.insert(header::ALLOW, HeaderValue::from_static("POST"));
+            }
+
+            _ => {}
+        }
+
+        response
+    }
+}
+
+fn json_response(status: StatusCode, body: &Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(body.to_string()));
+
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+
+    response
+}
+
+fn hyper_error(e: hyper::Error) -> io::Error {
+    io::Error::other(e)
+}
@@ -0,0 +1,258 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::convert::TryFrom;
+
+use calc_utils::{CalcError, Integer, IntegerOperation, IntegerRequest, Overflow};
+
+pub fn evaluate(request: &IntegerRequest) -> Result<Integer, CalcError> {
+    let IntegerRequest {
+        operation,
+        a,
+        b,
+        overflow,
+        ..
+    } = *request;
+
+    match (a, b) {
+        (Integer::I64(a), Integer::I64(b)) => {
+            apply_i64(operation, a, b, overflow).map(Integer::I64)
+        }
+
+        (Integer::U64(a), Integer::U64(b)) => {
+            apply_u64(operation, a, b, overflow).map(Integer::U64)
+        }
+
+        (Integer::I128(a), Integer::I128(b)) => {
+            apply_i128(operation, a, b, overflow).map(Integer::I128)
+        }
+
+        (a, b) => {
+            let msg = format!("can't mix {} and {}", a.type_name(), b.type_name());
+            Err(CalcError::InvalidArgument(msg))
+        }
+    }
+}
+
+/// Picks between what each of the overflow policies made of an operation. Only the one we go
+/// with gets worked out.
+fn with_policy<T, W, S>(
+    overflow: Overflow,
+    checked: Option<T>,
+    wrapping: W,
+    saturating: S,
+) -> Result<T, CalcError>
+where
+    W: FnOnce() -> T,
+    S: FnOnce() -> T,
+{
+    match overflow {
+        Overflow::Checked => checked.ok_or(CalcError::Overflow),
+        Overflow::Wrapping => Ok(wrapping()),
+        Overflow::Saturating => Ok(saturating()),
+    }
+}
+
+/// Euclid's algorithm, on magnitudes so it works the same for every type
+fn gcd<U: Copy + PartialEq + Default + std::ops::Rem<Output = U>>(mut a: U, mut b: U) -> U {
+    while b != U::default() {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+
+    a
+}
+
+/// The same thing for every type, which only differ in what their magnitudes fit in and how
+/// to get them. Signed types need one more bit for the magnitude of their smallest number.
+macro_rules! integer_arithmetic {
+    ($name:ident, $int:ty, $unsigned:ty, $magnitude:expr) => {
+        fn $name(
+            operation: IntegerOperation,
+            a: $int,
+            b: $int,
+            overflow: Overflow,
+        ) -> Result<$int, CalcError> {
+            let magnitude: fn($int) -> $unsigned = $magnitude;
+
+            // Gcd and lcm are never negative, so they can only go over the top
+            let from_magnitude = |num: Option<$unsigned>, wrapped: $unsigned| {
+                let checked = num.and_then(|num| <$int>::try_from(num).ok());
+                with_policy(overflow, checked, || wrapped as $int, || <$int>::MAX)
+            };
+
+            match operation {
+                IntegerOperation::Addition => with_policy(
+                    overflow,
+                    a.checked_add(b),
+                    || a.wrapping_add(b),
+                    || a.saturating_add(b),
+                ),
+
+                IntegerOperation::Subtraction => with_policy(
+                    overflow,
+                    a.checked_sub(b),
+                    || a.wrapping_sub(b),
+                    || a.saturating_sub(b),
+                ),
+
+                IntegerOperation::Multiplication => with_policy(
+                    overflow,
+                    a.checked_mul(b),
+                    || a.wrapping_mul(b),
+                    || a.saturating_mul(b),
+                ),
+
+                // The only way these overflow is the smallest number over -1
+                IntegerOperation::Division => {
+                    check_divisor(b != 0)?;
+
+                    with_policy(
+                        overflow,
+                        a.checked_div(b),
+                        || a.wrapping_div(b),
+                        || a.saturating_div(b),
+                    )
+                }
+
+                // Which always leaves nothing over, no matter what the quotient did, so there's
+                // nothing for even the checked policy to complain about
+                IntegerOperation::Remainder => {
+                    check_divisor(b != 0)?;
+
+                    with_policy(
+                        overflow,
+                        Some(a.wrapping_rem(b)),
+                        || a.wrapping_rem(b),
+                        || a.wrapping_rem(b),
+                    )
+                }
+
+                IntegerOperation::Power => {
+                    let exponent = u32::try_from(b).map_err(|_| {
+                        CalcError::InvalidArgument(format!("{} is not a valid exponent", b))
+                    })?;
+
+                    with_policy(
+                        overflow,
+                        a.checked_pow(exponent),
+                        || a.wrapping_pow(exponent),
+                        || a.saturating_pow(exponent),
+                    )
+                }
+
+                IntegerOperation::Gcd => {
+                    let g = gcd(magnitude(a), magnitude(b));
+                    from_magnitude(Some(g), g)
+                }
+
+                IntegerOperation::Lcm => {
+                    let (a, b) = (magnitude(a), magnitude(b));
+
+                    if a == 0 || b == 0 {
+                        return Ok(0);
+                    }
+
+                    let a = a / gcd(a, b);
+                    from_magnitude(a.checked_mul(b), a.wrapping_mul(b))
+                }
+
+                // Shifting bits out the top is the same as wrapping, and checking that nothing
+                // got lost is the same as checking the multiplication
+                IntegerOperation::ShiftLeft => {
+                    let shift = shift_amount(i128::from(b))?;
+
+                    let shifted = if shift < <$int>::BITS { a << shift } else { 0 };
+                    let fits = shift < <$int>::BITS && shifted >> shift == a;
+
+                    let saturated = if a > 0 {
+                        <$int>::MAX
+                    } else if a == 0 {
+                        0
+                    } else {
+                        <$int>::MIN
+                    };
+
+                    with_policy(
+                        overflow,
+                        Some(shifted).filter(|_| fits),
+                        || shifted,
+                        || {
+                            if fits {
+                                shifted
+                            } else {
+                                saturated
+                            }
+                        },
+                    )
+                }
+
+                // Shifting twice works out to 0 or -1 past the end for signed types, and always
+                // to 0 for unsigned ones
+                IntegerOperation::ShiftRight => {
+                    let shift = shift_amount(i128::from(b))?;
+
+                    if shift < <$int>::BITS {
+                        Ok(a >> shift)
+                    } else {
+                        Ok((a >> (<$int>::BITS - 1)) >> 1)
+                    }
+                }
+
+                IntegerOperation::And => Ok(a & b),
+                IntegerOperation::Or => Ok(a | b),
+                IntegerOperation::Xor => Ok(a ^ b),
+            }
+        }
+    };
+}
+
+integer_arithmetic!(apply_i64, i64, u64, i64::unsigned_abs);
+integer_arithmetic!(apply_u64, u64, u64, |num| num);
+integer_arithmetic!(apply_i128, i128, u128, i128::unsigned_abs);
+
+/// Dividing by zero is an error whatever the overflow policy, since there's no answer to wrap
+/// or saturate
+fn check_divisor(nonzero: bool) -> Result<(), CalcError> {
+    if nonzero {
+        Ok(())
+    } else {
+        Err(CalcError::InvalidArgument("division by zero".to_string()))
+    }
+}
+
+/// Any shift past the width of the type gives the same answer, so only negative shifts are a
+/// problem
+fn shift_amount(shift: i128) -> Result<u32, CalcError> {
+    if shift < 0 {
+        return Err(CalcError::InvalidArgument(format!(
+            "can't shift by {}",
+            shift
+        )));
+    }
+
+    Ok(u32::try_from(shift).unwrap_or(u32::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remainder_of_smallest_over_minus_one() {
+        for &overflow in &[Overflow::Checked, Overflow::Wrapping, Overflow::Saturating] {
+            let request = IntegerRequest::new(IntegerOperation::Remainder, i64::MIN, -1, overflow);
+            assert_eq!(evaluate(&request), Ok(Integer::I64(0)), "{:?}", overflow);
+
+            let request = IntegerRequest::new(IntegerOperation::Remainder, i128::MIN, -1, overflow);
+            assert_eq!(evaluate(&request), Ok(Integer::I128(0)), "{:?}", overflow);
+        }
+
+        // The quotient still doesn't fit, so that one is an overflow like before
+        let request =
+            IntegerRequest::new(IntegerOperation::Division, i64::MIN, -1, Overflow::Checked);
+        assert_eq!(evaluate(&request), Err(CalcError::Overflow));
+    }
+}
@@ -0,0 +1,111 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc::UnboundedSender;
+
+use calc_utils::{CalcError, Computation, Progress, Response};
+
+use crate::roots;
+use crate::worker_pool::Budget;
+
+/// Least time between two progress frames for the same computation. Fast computations would
+/// otherwise spend more time telling the client about it than computing.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Most iterations Newton's method gets for a square root. It doubles the correct digits every
+/// step once it's close, so this is plenty even starting from something far off.
+const MAX_SQRT_ITERATIONS: u32 = 2048;
+
+/// Sends progress on a computation back down its connection, never more often than
+/// `PROGRESS_INTERVAL` apart
+#[derive(Debug)]
+pub struct Reporter<'a> {
+    id: u32,
+    results: &'a UnboundedSender<Response>,
+    last: Option<Instant>,
+}
+
+impl<'a> Reporter<'a> {
+    pub fn new(id: u32, results: &'a UnboundedSender<Response>) -> Reporter<'a> {
+        Reporter {
+            id,
+            results,
+            last: None,
+        }
+    }
+
+    /// Tells the client where we are, unless we did so only a moment ago. The first report
+    /// always goes out.
+    pub fn report(&mut self, iteration: u32, approximation: f64) {
+        let now = Instant::now();
+
+        if self
+            .last
+            .is_some_and(|last| now.duration_since(last) < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+
+        self.last = Some(now);
+
+        let progress = Progress {
+            id: self.id,
+            iteration,
+            approximation,
+        };
+
+        // The connection might have died while we were working on this
+        let _ = self.results.unbounded_send(Response::Progress(progress));
+    }
+}
+
+/// Works out `computation`, checking the budget and reporting progress after every iteration
+pub fn evaluate(
+    computation: &Computation,
+    budget: &Budget,
+    reporter: &mut Reporter,
+) -> Result<f64, CalcError> {
+    match computation {
+        Computation::Sqrt(value) => sqrt(*value, budget, reporter),
+
+        Computation::Root {
+            expression,
+            variable,
+            method,
+            convergence,
+        } => roots::find(expression, variable, method, convergence, budget, reporter),
+    }
+}
+
+fn sqrt(value: f64, budget: &Budget, reporter: &mut Reporter) -> Result<f64, CalcError> {
+    // Nothing to iterate on for these, and Newton's method would never settle on them anyway
+    if value.is_nan() || value < 0.0 {
+        return Ok(f64::NAN);
+    }
+
+    if value == 0.0 || value.is_infinite() {
+        return Ok(value);
+    }
+
+    // Starting above the root means every step after this goes down, so we're done as soon as
+    // one doesn't
+    let mut x = value.max(1.0);
+
+    for iteration in 1..=MAX_SQRT_ITERATIONS {
+        budget.check()?;
+
+        let next = (x + value / x) / 2.0;
+        reporter.report(iteration, next);
+
+        if next >= x {
+            return Ok(x);
+        }
+
+        x = next;
+    }
+
+    Ok(x)
+}
@@ -0,0 +1,210 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::cmp;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use calc_utils::CalcError;
+
+/// How much a connection or an address is allowed to do
+#[derive(Debug, Clone)]
+pub struct LimitConfig {
+    pub max_connections: usize,
+    pub max_connections_per_ip: usize,
+    pub connection_rate: Rate,
+    pub ip_rate: Rate,
+}
+
+/// A token bucket rate: up to `burst` requests at once, refilling at `per_second`
+#[derive(Debug, Clone, Copy)]
+pub struct Rate {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+/// Shared by the whole server, keeps track of who is connected and how fast they're going
+#[derive(Debug, Clone)]
+pub struct Limits {
+    config: Arc<LimitConfig>,
+    state: Arc<Mutex<State>>,
+}
+
+/// Held by a connection for as long as it is open. Dropping it gives the connection slot back.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    limits: Limits,
+    ip: IpAddr,
+    bucket: TokenBucket,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    connections: usize,
+    addresses: HashMap<IpAddr, Address>,
+}
+
+#[derive(Debug)]
+struct Address {
+    connections: usize,
+    bucket: TokenBucket,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: Rate,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Limits {
+    pub fn new(config: LimitConfig) -> Limits {
+        Limits {
+            config: Arc::new(config),
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    /// Lets a new connection from `ip` in, unless the server or that address is already full
+    pub fn admit(&self, ip: IpAddr) -> Result<ConnectionPermit, CalcError> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        if state.connections >= self.config.max_connections {
+            return Err(CalcError::TooManyConnections);
+        }
+
+        let address = state.address(ip, self.config.ip_rate, now);
+
+        if address.connections >= self.config.max_connections_per_ip {
+            return Err(CalcError::TooManyConnections);
+        }
+
+        address.connections += 1;
+        state.connections += 1;
+
+        Ok(ConnectionPermit {
+            limits: self.clone(),
+            ip,
+            bucket: TokenBucket::new(self.config.connection_rate, now),
+        })
+    }
+
+    /// Takes a token from `ip`'s bucket for a request that didn't come in on a connection, like
+    /// a datagram, or says how long to wait until there will be one
+    pub fn check_datagram(&self, ip: IpAddr) -> Result<(), CalcError> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        let address = state.address(ip, self.config.ip_rate, now);
+        address.bucket.refill(now);
+
+        let retry_after = address.bucket.wait_time();
+
+        if retry_after > Duration::from_secs(0) {
+            return Err(CalcError::RateLimited { retry_after });
+        }
+
+        address.bucket.take();
+
+        Ok(())
+    }
+}
+
+impl ConnectionPermit {
+    /// Takes a token for one request from both this connection's and its address' buckets, or
+    /// says how long to wait until there will be one in both
+    pub fn check_request(&mut self) -> Result<(), CalcError> {
+        let now = Instant::now();
+        let mut state = self.limits.state.lock().unwrap();
+
+        // We're still counted as a connection, so our address can't have been cleaned up
+        let address = state
+            .addresses
+            .get_mut(&self.ip)
+            .expect("address of open connection missing");
+
+        address.bucket.refill(now);
+        self.bucket.refill(now);
+
+        let retry_after = cmp::max(address.bucket.wait_time(), self.bucket.wait_time());
+
+        if retry_after > Duration::from_secs(0) {
+            return Err(CalcError::RateLimited { retry_after });
+        }
+
+        address.bucket.take();
+        self.bucket.take();
+
+        Ok(())
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut state = self.limits.state.lock().unwrap();
+
+        state.connections -= 1;
+
+        if let Some(address) = state.addresses.get_mut(&self.ip) {
+            address.connections -= 1;
+        }
+    }
+}
+
+impl State {
+    /// Looks up what we know about `ip`, starting it off with a full bucket if it's new
+    fn address(&mut self, ip: IpAddr, ip_rate: Rate, now: Instant) -> &mut Address {
+        if !self.addresses.contains_key(&ip) {
+            // Forget about addresses that have left and whose buckets have filled back up,
+            // they'd start from a full bucket anyway
+            self.addresses.retain(|_, address| {
+                address.bucket.refill(now);
+                address.connections > 0 || !address.bucket.is_full()
+            });
+        }
+
+        self.addresses.entry(ip).or_insert_with(|| Address {
+            connections: 0,
+            bucket: TokenBucket::new(ip_rate, now),
+        })
+    }
+}
+
+impl TokenBucket {
+    fn new(rate: Rate, now: Instant) -> TokenBucket {
+        TokenBucket {
+            rate,
+            tokens: f64::from(rate.burst),
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+
+        self.tokens =
+            (self.tokens + elapsed * self.rate.per_second).min(f64::from(self.rate.burst));
+        self.updated = now;
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= f64::from(self.rate.burst)
+    }
+
+    /// How long until there is a whole token to take, zero if there already is one
+    fn wait_time(&self) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.rate.per_second)
+        }
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
@@ -0,0 +1,378 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use calc_utils::{CalcError, Dimensions, Matrix, MatrixOperation, MatrixValue, MAX_MATRIX_LEN};
+
+use crate::worker_pool::Budget;
+
+/// A square matrix split up into lower and upper triangular parts, `P A = L U`. Both parts share
+/// the one matrix, with the ones on the diagonal of `L` left out.
+#[derive(Debug)]
+struct Lu {
+    n: usize,
+    lu: Vec<f64>,
+
+    /// Which row of the original matrix ended up in each row
+    rows: Vec<usize>,
+
+    /// Swapping two rows flips the determinant's sign, so this is -1 after an odd number of them
+    sign: f64,
+
+    /// Whether some pivot was zero, or too small next to the rest of its row to divide by
+    singular: bool,
+}
+
+pub fn evaluate(operation: &MatrixOperation, budget: &Budget) -> Result<MatrixValue, CalcError> {
+    match operation {
+        MatrixOperation::Add(a, b) => {
+            a.check()?;
+            b.check()?;
+
+            if a.dimensions() != b.dimensions() {
+                return Err(mismatch(a.dimensions(), b.dimensions()));
+            }
+
+            let data = a.data.iter().zip(&b.data).map(|(x, y)| x + y).collect();
+            Ok(MatrixValue::Matrix(Matrix {
+                rows: a.rows,
+                columns: a.columns,
+                data,
+            }))
+        }
+
+        MatrixOperation::Multiply(a, b) => multiply(a, b, budget).map(MatrixValue::Matrix),
+
+        MatrixOperation::Transpose(a) => {
+            a.check()?;
+
+            let (rows, columns) = (a.rows as usize, a.columns as usize);
+            let mut data = Vec::with_capacity(a.data.len());
+
+            for column in 0..columns {
+                data.extend((0..rows).map(|row| a.get(row, column)));
+            }
+
+            Ok(MatrixValue::Matrix(Matrix {
+                rows: a.columns,
+                columns: a.rows,
+                data,
+            }))
+        }
+
+        MatrixOperation::Determinant(a) => {
+            let lu = Lu::decompose(a, budget)?;
+            Ok(MatrixValue::Scalar(lu.determinant()))
+        }
+
+        MatrixOperation::Inverse(a) => inverse(a, budget).map(MatrixValue::Matrix),
+
+        MatrixOperation::Solve(a, b) => {
+            check_vector(b)?;
+
+            let lu = Lu::decompose(a, budget)?;
+
+            if b.len() != lu.n {
+                return Err(mismatch(a.dimensions(), column(b)));
+            }
+
+            if lu.singular {
+                return Err(CalcError::Singular);
+            }
+
+            Ok(MatrixValue::Vector(lu.solve(b)))
+        }
+
+        MatrixOperation::Dot(a, b) => {
+            check_vector(a)?;
+            check_vector(b)?;
+
+            if a.len() != b.len() {
+                return Err(mismatch(column(a), column(b)));
+            }
+
+            Ok(MatrixValue::Scalar(
+                a.iter().zip(b).map(|(x, y)| x * y).sum(),
+            ))
+        }
+
+        MatrixOperation::Cross(a, b) => {
+            if a.len() != 3 || b.len() != 3 {
+                let msg = "cross products are only for vectors of 3 numbers".to_string();
+                return Err(CalcError::InvalidArgument(msg));
+            }
+
+            Ok(MatrixValue::Vector(vec![
+                a[1] * b[2] - a[2] * b[1],
+                a[2] * b[0] - a[0] * b[2],
+                a[0] * b[1] - a[1] * b[0],
+            ]))
+        }
+    }
+}
+
+fn multiply(a: &Matrix, b: &Matrix, budget: &Budget) -> Result<Matrix, CalcError> {
+    a.check()?;
+    b.check()?;
+
+    if a.columns != b.rows {
+        return Err(mismatch(a.dimensions(), b.dimensions()));
+    }
+
+    // Both can be small enough while what they make isn't, like a column times a row
+    let len = u64::from(a.rows) * u64::from(b.columns);
+
+    if len > MAX_MATRIX_LEN as u64 {
+        let msg = format!("the result would have more than {} numbers", MAX_MATRIX_LEN);
+        return Err(CalcError::InvalidArgument(msg));
+    }
+
+    let (rows, inner, columns) = (a.rows as usize, a.columns as usize, b.columns as usize);
+    let mut data = vec![0.0; len as usize];
+
+    for row in 0..rows {
+        budget.check()?;
+
+        // Going along the rows of `b` instead of down its columns keeps to memory order
+        for k in 0..inner {
+            let scale = a.get(row, k);
+
+            for column in 0..columns {
+                data[row * columns + column] += scale * b.get(k, column);
+            }
+        }
+    }
+
+    Ok(Matrix {
+        rows: a.rows,
+        columns: b.columns,
+        data,
+    })
+}
+
+/// Solves for every column of the identity, which gives us the inverse's columns one at a time
+fn inverse(a: &Matrix, budget: &Budget) -> Result<Matrix, CalcError> {
+    let lu = Lu::decompose(a, budget)?;
+
+    if lu.singular {
+        return Err(CalcError::Singular);
+    }
+
+    let n = lu.n;
+    let mut data = vec![0.0; n * n];
+    let mut unit = vec![0.0; n];
+
+    for column in 0..n {
+        budget.check()?;
+
+        unit[column] = 1.0;
+
+        for (row, num) in lu.solve(&unit).into_iter().enumerate() {
+            data[row * n + column] = num;
+        }
+
+        unit[column] = 0.0;
+    }
+
+    Ok(Matrix {
+        rows: a.rows,
+        columns: a.columns,
+        data,
+    })
+}
+
+impl Lu {
+    /// Gaussian elimination with partial pivoting. A pivot that's tiny next to the biggest number
+    /// its row started out with is as good as zero after rounding, so we call the matrix singular
+    /// then. Comparing within the row means a matrix that's only badly scaled, like one with a
+    /// row of tiny numbers, doesn't count.
+    fn decompose(matrix: &Matrix, budget: &Budget) -> Result<Lu, CalcError> {
+        matrix.check()?;
+
+        if matrix.rows != matrix.columns {
+            let msg = format!("a {} matrix is not square", matrix.dimensions());
+            return Err(CalcError::InvalidArgument(msg));
+        }
+
+        let n = matrix.rows as usize;
+        let mut lu = matrix.data.clone();
+
+        // The biggest number in each row, which moves along with the row when it gets swapped
+        let mut scales: Vec<f64> = lu
+            .chunks(n.max(1))
+            .map(|row| {
+                row.iter()
+                    .fold(0.0_f64, |biggest, num| biggest.max(num.abs()))
+            })
+            .collect();
+
+        let mut rows: Vec<usize> = (0..n).collect();
+        let mut sign = 1.0;
+        let mut singular = false;
+
+        for k in 0..n {
+            budget.check()?;
+
+            let pivot = (k..n)
+                .max_by(|&i, &j| lu[i * n + k].abs().total_cmp(&lu[j * n + k].abs()))
+                .expect("no rows left to pivot on");
+
+            // Nothing to divide by at all, and the column is already done
+            if lu[pivot * n + k] == 0.0 {
+                singular = true;
+                continue;
+            }
+
+            if pivot != k {
+                for column in 0..n {
+                    lu.swap(pivot * n + column, k * n + column);
+                }
+
+                rows.swap(pivot, k);
+                scales.swap(pivot, k);
+                sign = -sign;
+            }
+
+            if lu[k * n + k].abs() <= n as f64 * f64::EPSILON * scales[k] {
+                singular = true;
+            }
+
+            for row in k + 1..n {
+                let factor = lu[row * n + k] / lu[k * n + k];
+                lu[row * n + k] = factor;
+
+                for column in k + 1..n {
+                    lu[row * n + column] -= factor * lu[k * n + column];
+                }
+            }
+        }
+
+        Ok(Lu {
+            n,
+            lu,
+            rows,
+            sign,
+            singular,
+        })
+    }
+
+    /// The product of the pivots, as they came out. A zero pivot makes it zero, but a tiny one
+    /// is left alone, since a matrix that's hard to solve can still have a perfectly good
+    /// determinant.
+    fn determinant(&self) -> f64 {
+        let diagonal: f64 = (0..self.n).map(|i| self.lu[i * self.n + i]).product();
+
+        self.sign * diagonal
+    }
+
+    /// Solves `A x = b` by going forward through `L` and then back through `U`. Only for when
+    /// the matrix isn't singular.
+    fn solve(&self, b: &[f64]) -> Vec<f64> {
+        let n = self.n;
+        let mut x: Vec<f64> = self.rows.iter().map(|&row| b[row]).collect();
+
+        for row in 0..n {
+            for column in 0..row {
+                x[row] -= self.lu[row * n + column] * x[column];
+            }
+        }
+
+        for row in (0..n).rev() {
+            for column in row + 1..n {
+                x[row] -= self.lu[row * n + column] * x[column];
+            }
+
+            x[row] /= self.lu[row * n + row];
+        }
+
+        x
+    }
+}
+
+fn check_vector(vector: &[f64]) -> Result<(), CalcError> {
+    if vector.len() > MAX_MATRIX_LEN {
+        let msg = format!("vectors can hold at most {} numbers", MAX_MATRIX_LEN);
+        return Err(CalcError::InvalidArgument(msg));
+    }
+
+    Ok(())
+}
+
+/// A vector's dimensions, as a matrix with a single column
+fn column(vector: &[f64]) -> Dimensions {
+    Dimensions {
+        rows: vector.len() as u32,
+        columns: 1,
+    }
+}
+
+fn mismatch(lhs: Dimensions, rhs: Dimensions) -> CalcError {
+    CalcError::DimensionMismatch { lhs, rhs }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn matrix(rows: &[&[f64]]) -> Matrix {
+        Matrix {
+            rows: rows.len() as u32,
+            columns: rows[0].len() as u32,
+            data: rows.concat(),
+        }
+    }
+
+    fn determinant(a: Matrix) -> f64 {
+        let budget = Budget::new(Duration::from_secs(5));
+
+        match evaluate(&MatrixOperation::Determinant(a), &budget) {
+            Ok(MatrixValue::Scalar(det)) => det,
+            other => panic!("expected a scalar, got {:?}", other),
+        }
+    }
+
+    fn inverse(a: Matrix) -> Result<Vec<f64>, CalcError> {
+        let budget = Budget::new(Duration::from_secs(5));
+
+        match evaluate(&MatrixOperation::Inverse(a), &budget)? {
+            MatrixValue::Matrix(inverse) => Ok(inverse.data),
+            other => panic!("expected a matrix, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn determinants() {
+        let a = matrix(&[&[6.0, 1.0, 1.0], &[4.0, -2.0, 5.0], &[2.0, 8.0, 7.0]]);
+        assert!((determinant(a) + 306.0).abs() < 1e-12);
+
+        // Swapping two rows flips the sign
+        let a = matrix(&[&[0.0, 1.0], &[1.0, 0.0]]);
+        assert_eq!(determinant(a), -1.0);
+
+        let a = matrix(&[&[1.0, 2.0], &[2.0, 4.0]]);
+        assert_eq!(determinant(a), 0.0);
+    }
+
+    #[test]
+    fn badly_scaled() {
+        let a = matrix(&[&[1e-20, 0.0], &[0.0, 1.0]]);
+
+        assert_eq!(determinant(a.clone()), 1e-20);
+        assert_eq!(inverse(a).unwrap(), vec![1e20, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn singular() {
+        let a = matrix(&[&[1.0, 2.0], &[2.0, 4.0]]);
+        assert!(matches!(inverse(a), Err(CalcError::Singular)));
+
+        // Not exactly singular, but only because of rounding
+        let a = matrix(&[&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0], &[7.0, 8.0, 9.0]]);
+        assert!(matches!(inverse(a), Err(CalcError::Singular)));
+
+        let a = matrix(&[&[0.0, 0.0], &[0.0, 1.0]]);
+        assert!(matches!(inverse(a), Err(CalcError::Singular)));
+    }
+}
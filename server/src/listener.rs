@@ -0,0 +1,295 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+
+use futures::future;
+use futures::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+
+#[cfg(unix)]
+use std::path::PathBuf;
+#[cfg(unix)]
+use tokio::net::unix::{UnixListener, UnixStream};
+
+use crate::config::Config;
+
+/// Only our own user and group get to connect through the Unix socket, unless told otherwise
+#[cfg(unix)]
+const DEFAULT_UNIX_SOCKET_MODE: u32 = 0o660;
+
+/// Where connections come from: always a TCP port, and a WebSocket port and Unix socket too if
+/// we're asked for them
+#[derive(Debug)]
+pub struct Listener {
+    tcp: TcpListener,
+    websocket: Option<TcpListener>,
+
+    #[cfg(unix)]
+    unix: Option<UnixSocket>,
+}
+
+/// A connection accepted by a `Listener`, whichever kind of socket it came in on
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// Who is on the other end of a `Stream`
+#[derive(Debug, Clone)]
+pub enum Peer {
+    Tcp(SocketAddr),
+
+    /// Came in on the WebSocket port, so it starts with an HTTP upgrade
+    WebSocket(SocketAddr),
+
+    /// Unix sockets don't have addresses worth showing, but we do know which user connected
+    Unix {
+        uid: Option<u32>,
+    },
+}
+
+/// A bound Unix socket, which removes its file again once we stop listening
+#[cfg(unix)]
+#[derive(Debug)]
+struct UnixSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+type Accept<'a> = Pin<Box<dyn Future<Output = io::Result<(Stream, Peer)>> + 'a>>;
+
+impl Listener {
+    /// Binds to `addr`, and to whatever else `config` asks for
+    pub async fn bind(addr: &str, config: &Config) -> io::Result<Listener> {
+        let tcp = TcpListener::bind(addr).await?;
+
+        let websocket = match config.websocket {
+            Some(addr) => Some(TcpListener::bind(addr).await?),
+            None => None,
+        };
+
+        #[cfg(unix)]
+        {
+            let mode = config.unix_socket_mode.unwrap_or(DEFAULT_UNIX_SOCKET_MODE);
+
+            let unix = match &config.unix_socket {
+                Some(path) => Some(UnixSocket::bind(path.clone(), mode)?),
+                None => None,
+            };
+
+            Ok(Listener {
+                tcp,
+                websocket,
+                unix,
+            })
+        }
+
+        #[cfg(not(unix))]
+        match config.unix_socket {
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "no Unix sockets here",
+            )),
+            None => Ok(Listener { tcp, websocket }),
+        }
+    }
+
+    /// Waits for the next connection on any of our sockets
+    pub async fn accept(&mut self) -> io::Result<(Stream, Peer)> {
+        let tcp = &mut self.tcp;
+
+        let mut accepts: Vec<Accept> = vec![Box::pin(async move {
+            let (stream, addr) = tcp.accept().await?;
+            Ok((Stream::Tcp(stream), Peer::Tcp(addr)))
+        })];
+
+        if let Some(websocket) = &mut self.websocket {
+            accepts.push(Box::pin(async move {
+                let (stream, addr) = websocket.accept().await?;
+                Ok((Stream::Tcp(stream), Peer::WebSocket(addr)))
+            }));
+        }
+
+        #[cfg(unix)]
+        {
+            if let Some(unix) = &mut self.unix {
+                accepts.push(Box::pin(async move {
+                    let (stream, _) = unix.listener.accept().await?;
+                    let uid = stream.peer_cred().ok().map(|cred| cred.uid);
+
+                    Ok((Stream::Unix(stream), Peer::Unix { uid }))
+                }));
+            }
+        }
+
+        // The accepts that lose just get dropped, the connections they were waiting for stay in
+        // the backlog until we come back for them
+        future::select_all(accepts).await.0
+    }
+
+    /// Everywhere we're listening, for the logs
+    pub fn describe(&self) -> String {
+        let describe = |listener: &TcpListener| match listener.local_addr() {
+            Ok(addr) => addr.to_string(),
+            Err(_) => "an unknown address".to_string(),
+        };
+
+        let mut description = describe(&self.tcp);
+
+        if let Some(websocket) = &self.websocket {
+            description.push_str(&format!(", WebSockets on {}", describe(websocket)));
+        }
+
+        #[cfg(unix)]
+        {
+            if let Some(unix) = &self.unix {
+                description.push_str(&format!(", {}", unix.path.display()));
+            }
+        }
+
+        description
+    }
+}
+
+#[cfg(unix)]
+impl UnixSocket {
+    fn bind(path: PathBuf, mode: u32) -> io::Result<UnixSocket> {
+        use std::fs;
+        use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+
+        // A socket file left behind by a server that didn't get to clean up would make the bind
+        // fail. Only take it over if nobody is listening on it anymore.
+        if let Ok(meta) = fs::symlink_metadata(&path) {
+            if !meta.file_type().is_socket() {
+                let msg = format!("{} exists and is not a socket", path.display());
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, msg));
+            }
+
+            if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+                let msg = format!("something is already listening on {}", path.display());
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, msg));
+            }
+
+            fs::remove_file(&path)?;
+        }
+
+        // The file permissions are all the access control a Unix socket gets, but binding
+        // creates the file with whatever the umask allows. So we bind inside a directory only
+        // we can get into, fix the permissions there, and only then move it into place.
+        let file_name = path.file_name().ok_or_else(|| {
+            let msg = format!("{} is not a file path", path.display());
+            io::Error::new(io::ErrorKind::InvalidInput, msg)
+        })?;
+
+        let mut private = path.clone();
+        private.set_file_name(format!(
+            ".{}.{}",
+            file_name.to_string_lossy(),
+            std::process::id()
+        ));
+
+        fs::DirBuilder::new().mode(0o700).create(&private)?;
+
+        let staged = private.join("socket");
+        let bound = UnixListener::bind(&staged).and_then(|listener| {
+            fs::set_permissions(&staged, fs::Permissions::from_mode(mode))?;
+            fs::rename(&staged, &path)?;
+
+            Ok(listener)
+        });
+
+        let socket = bound.map(|listener| UnixSocket { listener, path });
+
+        // The socket file is only still in there if something went wrong. If the directory
+        // can't be removed, the socket gets dropped and its file removed again.
+        let _ = fs::remove_file(&staged);
+        fs::remove_dir(&private)?;
+
+        socket
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            println!("Error removing socket file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+impl Peer {
+    /// The address to count this peer's connections and requests against. Everything on a Unix
+    /// socket is on this host, so it shares limits with local TCP clients.
+    pub fn ip(&self) -> IpAddr {
+        match self {
+            Peer::Tcp(addr) | Peer::WebSocket(addr) => addr.ip(),
+            Peer::Unix { .. } => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        }
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Peer::Tcp(addr) => write!(f, "{}", addr),
+            Peer::WebSocket(addr) => write!(f, "{} (WebSocket)", addr),
+            Peer::Unix { uid: Some(uid) } => write!(f, "unix socket (uid {})", uid),
+            Peer::Unix { uid: None } => write!(f, "unix socket"),
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -2,34 +2,294 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use std::cmp;
 use std::io;
+use std::sync::Arc;
+use std::time::Duration;
 
-use tokio::net::TcpListener;
+use futures::future::{self, Either};
+use tokio::net::UdpSocket;
 use tokio::prelude::*;
+use tokio::timer::{delay_for, Timeout};
 
-use crate::calculator::process_client;
+use calc_utils::{accept_tls, server_tls_config, CalcError};
 
+use crate::auth::Authenticator;
+use crate::calculator::{process_client, reject_client, Connection};
+use crate::config::Config;
+use crate::limits::{LimitConfig, Limits, Rate};
+use crate::listener::{Listener, Peer, Stream};
+use crate::shutdown::Shutdown;
+use crate::websocket::process_websocket;
+use crate::worker_pool::WorkerPool;
+
+mod aggregate;
+mod auth;
 mod calculator;
+mod calculus;
+mod config;
+mod functions;
+#[cfg(feature = "http")]
+mod http;
+mod integer;
+mod iterative;
+mod limits;
+mod linear;
+mod listener;
+mod operations;
+mod roots;
+mod shutdown;
+mod special;
+mod statistics;
+mod symbolic;
+mod udp;
+mod units;
+#[cfg(feature = "wasm")]
+mod wasm;
+mod websocket;
+mod worker_pool;
+
+/// How long connections get to finish their in-flight requests once we start shutting down
+const GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Bounds for how long we wait before accepting again after `accept` fails. Most accept errors
+/// (like running out of file descriptors) clear up on their own once other connections close.
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How long a client gets to finish the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many requests can be waiting for a free worker before we start turning them away
+const WORKER_QUEUE_LIMIT: usize = 1024;
+
+/// How long a single request is allowed to spend being evaluated
+const EVALUATION_BUDGET: Duration = Duration::from_secs(5);
+
+const LIMITS: LimitConfig = LimitConfig {
+    max_connections: 1024,
+    max_connections_per_ip: 32,
+    connection_rate: Rate {
+        burst: 200,
+        per_second: 100.0,
+    },
+    ip_rate: Rate {
+        burst: 1000,
+        per_second: 500.0,
+    },
+};
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    let tcp_listener = TcpListener::bind("127.0.0.1:7878").await?;
-    let mut incoming_connections = tcp_listener.incoming();
+    let config = Config::from_args()?;
+
+    let auth = Arc::new(match &config.key_file {
+        Some(path) => Authenticator::load(path)?,
+        None => Authenticator::disabled(),
+    });
+
+    let tls = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            Some(server_tls_config(cert, key, config.tls_client_ca.as_ref())?)
+        }
+
+        _ => None,
+    };
+
+    // Plugins go after the bundled operations, so they can't take over any of their names
+    let mut operations = operations::bundled()?;
+
+    for path in &config.plugins {
+        operations = operations.load_library(path)?;
+    }
 
-    println!("Listening on 127.0.0.1:7878");
+    #[cfg(feature = "wasm")]
+    let sandbox = wasm::Sandbox::new()?;
 
-    while let Some(tcp_stream) = incoming_connections.next().await {
-        let stream = tcp_stream.unwrap();
-        let addr = stream.peer_addr().unwrap();
+    #[cfg(feature = "wasm")]
+    {
+        for path in &config.wasm_modules {
+            for operation in sandbox.load(path)? {
+                operations = operations.register(operation)?;
+            }
+        }
+    }
+
+    let operations = Arc::new(operations.build());
+
+    // Modules in here can't stop the server from starting, the same as when they change later
+    #[cfg(feature = "wasm")]
+    {
+        if let Some(dir) = config.wasm_dir.clone() {
+            wasm::watch(sandbox, dir, operations.clone())?;
+        }
+    }
+
+    println!("Operations: {:?}", operations);
+
+    let mut listener = Listener::bind("127.0.0.1:7878", &config).await?;
+
+    let with_tls = if tls.is_some() { " with TLS" } else { "" };
+    println!("Listening on {}{}", listener.describe(), with_tls);
+
+    let pool = WorkerPool::new(num_cpus::get(), WORKER_QUEUE_LIMIT, EVALUATION_BUDGET);
+    let limits = Limits::new(LIMITS);
+    let shutdown = Shutdown::new();
+
+    #[cfg(feature = "http")]
+    {
+        if let Some(addr) = config.http {
+            let listener = std::net::TcpListener::bind(addr)?;
+            println!("HTTP API on {}", listener.local_addr()?);
+
+            let api = http::Api {
+                pool: pool.clone(),
+                limits: limits.clone(),
+                auth: auth.clone(),
+                operations: operations.clone(),
+            };
+
+            let signal = shutdown.listener_signal();
+
+            tokio::spawn(async move {
+                if let Err(e) = http::serve(listener, api, signal).await {
+                    println!("HTTP API failed: {}", e);
+                }
+            });
+        }
+    }
+
+    if let Some(addr) = config.udp {
+        let socket = UdpSocket::bind(addr).await?;
+        println!("Answering datagrams on {}", socket.local_addr()?);
+
+        let serving = udp::serve(
+            socket,
+            pool.clone(),
+            limits.clone(),
+            shutdown.listener_signal(),
+        );
 
         tokio::spawn(async move {
-            println!("Accepting stream from: {}", addr);
+            if let Err(e) = serving.await {
+                println!("UDP listener failed: {}", e);
+            }
+        });
+    }
+    let mut stop_signal = Box::pin(shutdown::signal());
+    let mut backoff = MIN_ACCEPT_BACKOFF;
+
+    loop {
+        // Stop accepting as soon as we get asked to stop
+        let next_connection = future::select(stop_signal.as_mut(), Box::pin(listener.accept()));
+
+        let accepted = match next_connection.await {
+            Either::Left((res, _)) => {
+                res?;
+                break;
+            }
+
+            Either::Right((accepted, _)) => accepted,
+        };
+
+        let (stream, peer) = match accepted {
+            Ok(accepted) => {
+                backoff = MIN_ACCEPT_BACKOFF;
+                accepted
+            }
+
+            Err(e) => {
+                println!("Error accepting stream, retrying in {:?}: {}", backoff, e);
+
+                delay_for(backoff).await;
+                backoff = cmp::min(backoff * 2, MAX_ACCEPT_BACKOFF);
+
+                continue;
+            }
+        };
+
+        let admission = match limits.admit(peer.ip()) {
+            Ok(permit) => {
+                println!("Accepting stream from: {}", peer);
+
+                Ok(Connection {
+                    shutdown: shutdown.signal(),
+                    permit,
+                    pool: pool.clone(),
+                    auth: auth.clone(),
+                    operations: operations.clone(),
+                })
+            }
 
-            process_client(stream).await.unwrap();
+            Err(e) => {
+                println!("Rejecting stream from: {}: {}", peer, e);
+                Err(e)
+            }
+        };
 
-            println!("Closing stream from: {}", addr);
+        // Only TCP gets TLS, a Unix socket never leaves the host and is already protected by its
+        // file permissions
+        let tls = match stream {
+            Stream::Tcp(_) => tls.clone(),
+            _ => None,
+        };
+
+        tokio::spawn(async move {
+            // Rejections go through TLS too, a TLS client couldn't read them otherwise
+            let res = match tls {
+                Some(tls) => {
+                    let handshake = Timeout::new(accept_tls(&tls, stream), TLS_HANDSHAKE_TIMEOUT);
+
+                    match handshake.await {
+                        Ok(Ok(stream)) => serve_client(stream, &peer, admission).await,
+                        Ok(Err(e)) => Err(e),
+                        Err(_) => Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "TLS handshake timed out",
+                        )),
+                    }
+                }
+
+                None => serve_client(stream, &peer, admission).await,
+            };
+
+            match res {
+                Ok(()) => println!("Closing stream from: {}", peer),
+                Err(e) => println!("Closing stream from: {} after error: {}", peer, e),
+            }
         });
     }
 
+    // Stop listening right away, which also takes the Unix socket file away so new clients
+    // don't try to connect while we're draining
+    drop(listener);
+
+    println!(
+        "Shutting down, waiting up to {:?} for connections to finish",
+        GRACE_PERIOD
+    );
+
+    let summary = shutdown.drain(GRACE_PERIOD).await;
+
+    println!(
+        "Drained {} connections and {} requests, abandoned {} connections",
+        summary.connections, summary.requests, summary.abandoned
+    );
+
     Ok(())
 }
+
+async fn serve_client<S>(
+    stream: S,
+    peer: &Peer,
+    admission: Result<Connection, CalcError>,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match (peer, admission) {
+        (Peer::WebSocket(_), admission) => process_websocket(stream, admission).await,
+        (_, Ok(connection)) => process_client(stream, connection).await,
+        (_, Err(e)) => reject_client(stream, e).await,
+    }
+}
@@ -0,0 +1,439 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::slice;
+use std::sync::{Arc, RwLock};
+
+use libloading::{Library, Symbol};
+
+use calc_utils::{CalcError, OperationInfo, PluginTable, PLUGIN_ABI_VERSION, PLUGIN_ENTRY_POINT};
+
+use crate::worker_pool::Budget;
+
+/// Most arguments an operation can take, which keeps a plugin from having us read past the end
+/// of a request with an arity it didn't mean
+const MAX_ARITY: usize = 64;
+
+/// An operation clients can ask for by name on top of the ones built into the protocol. It runs
+/// on the worker pool like everything else, so anything that loops should check the budget.
+pub trait OperationHandler: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// How many arguments it takes. Requests with any other number never get to `evaluate`.
+    fn arity(&self) -> usize;
+
+    fn evaluate(&self, arguments: &[f64], budget: &Budget) -> Result<f64, CalcError>;
+}
+
+/// Every operation the server has registered, shared by all the connections. Most of them are
+/// there from the start, but modules loaded while the server runs come and go with their files.
+#[derive(Default)]
+pub struct OperationRegistry {
+    handlers: RwLock<BTreeMap<String, Arc<dyn OperationHandler>>>,
+}
+
+/// Puts together an `OperationRegistry` with everything that's there from the start
+#[derive(Debug, Default)]
+pub struct RegistryBuilder {
+    registry: OperationRegistry,
+}
+
+/// A closure registered with `RegistryBuilder::register_fn`
+struct FnHandler<F> {
+    name: String,
+    arity: usize,
+    evaluate: F,
+}
+
+/// An operation from a plugin library
+struct LibraryHandler {
+    name: String,
+    arity: usize,
+    evaluate: unsafe extern "C" fn(*const f64, *mut f64) -> i32,
+
+    // Keeps the code `evaluate` points into loaded, unless it's part of the server itself
+    _library: Option<Arc<Library>>,
+}
+
+impl OperationRegistry {
+    pub fn builder() -> RegistryBuilder {
+        RegistryBuilder::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn OperationHandler>> {
+        self.handlers.read().unwrap().get(name).cloned()
+    }
+
+    /// Adds an operation while connections might be using the registry. Its name can't be taken
+    /// already, so nothing loaded later gets to replace what clients were relying on.
+    pub fn insert(&self, handler: Arc<dyn OperationHandler>) -> io::Result<()> {
+        let name = handler.name().to_string();
+
+        if !valid_name(&name) {
+            return Err(invalid(format!("`{}` is not a valid operation name", name)));
+        }
+
+        if handler.arity() > MAX_ARITY {
+            let msg = format!("`{}` takes more than {} arguments", name, MAX_ARITY);
+            return Err(invalid(msg));
+        }
+
+        let mut handlers = self.handlers.write().unwrap();
+
+        if handlers.contains_key(&name) {
+            return Err(invalid(format!("`{}` is registered twice", name)));
+        }
+
+        handlers.insert(name, handler);
+
+        Ok(())
+    }
+
+    /// Takes an operation away. Requests that already got hold of it still finish with it.
+    #[cfg(feature = "wasm")]
+    pub fn remove(&self, name: &str) {
+        self.handlers.write().unwrap().remove(name);
+    }
+
+    /// What gets advertised to clients when they connect, ordered by name
+    pub fn describe(&self) -> Vec<OperationInfo> {
+        self.handlers
+            .read()
+            .unwrap()
+            .values()
+            .map(|handler| OperationInfo {
+                name: handler.name().to_string(),
+                arity: handler.arity() as u32,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for OperationRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set()
+            .entries(self.handlers.read().unwrap().keys())
+            .finish()
+    }
+}
+
+impl RegistryBuilder {
+    pub fn register<H: OperationHandler + 'static>(self, handler: H) -> io::Result<Self> {
+        self.registry.insert(Arc::new(handler))?;
+
+        Ok(self)
+    }
+
+    /// Registers a closure, for operations that don't need a type of their own
+    pub fn register_fn<F>(self, name: &str, arity: usize, evaluate: F) -> io::Result<Self>
+    where
+        F: Fn(&[f64]) -> Result<f64, CalcError> + Send + Sync + 'static,
+    {
+        self.register(FnHandler {
+            name: name.to_string(),
+            arity,
+            evaluate,
+        })
+    }
+
+    /// Registers every operation in the plugin library at `path`. The library gets to run
+    /// whatever it likes inside the server, so only load ones you'd trust with the server itself.
+    /// That includes panicking: a panic can't unwind out of an `extern "C"` function, so one in
+    /// a plugin aborts the whole server.
+    pub fn load_library(self, path: &Path) -> io::Result<Self> {
+        let describe = |e| io::Error::other(format!("{:?}: {}", path, e));
+
+        // Safety: running the library's initialisers and entry point is what loading a plugin
+        // means. Checking the ABI version is as much as we can do to make sure it's one.
+        let library = Arc::new(unsafe { Library::new(path) }.map_err(describe)?);
+
+        let table = unsafe {
+            let entry: Symbol<unsafe extern "C" fn() -> *const PluginTable> = library
+                .get(PLUGIN_ENTRY_POINT.as_bytes())
+                .map_err(describe)?;
+
+            entry().as_ref()
+        };
+
+        let table = table.ok_or_else(|| invalid(format!("{:?} has no operations table", path)))?;
+
+        // Safety: the table lives in the library, which every handler keeps loaded
+        unsafe { self.register_table(table, path, Some(library.clone())) }
+    }
+
+    /// Registers the operations in `table`, which came from `origin`. Everything the table
+    /// points to has to stay valid for as long as `library` is loaded, or for good without one.
+    unsafe fn register_table(
+        mut self,
+        table: &PluginTable,
+        origin: &Path,
+        library: Option<Arc<Library>>,
+    ) -> io::Result<Self> {
+        if table.abi_version != PLUGIN_ABI_VERSION {
+            let msg = format!(
+                "{:?} is built for plugin ABI {}, not {}",
+                origin, table.abi_version, PLUGIN_ABI_VERSION
+            );
+
+            return Err(invalid(msg));
+        }
+
+        let operations = if table.len == 0 {
+            &[]
+        } else {
+            slice::from_raw_parts(table.operations, table.len)
+        };
+
+        for operation in operations {
+            let name = CStr::from_ptr(operation.name).to_str().map_err(|_| {
+                invalid(format!(
+                    "{:?} has an operation name that's not UTF-8",
+                    origin
+                ))
+            })?;
+
+            self = self.register(LibraryHandler {
+                name: name.to_string(),
+                arity: usize::try_from(operation.arity).unwrap_or(usize::MAX),
+                evaluate: operation.evaluate,
+                _library: library.clone(),
+            })?;
+        }
+
+        Ok(self)
+    }
+
+    pub fn build(self) -> OperationRegistry {
+        self.registry
+    }
+}
+
+impl<F> OperationHandler for FnHandler<F>
+where
+    F: Fn(&[f64]) -> Result<f64, CalcError> + Send + Sync,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn evaluate(&self, arguments: &[f64], _: &Budget) -> Result<f64, CalcError> {
+        (self.evaluate)(arguments)
+    }
+}
+
+impl OperationHandler for LibraryHandler {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn evaluate(&self, arguments: &[f64], budget: &Budget) -> Result<f64, CalcError> {
+        let mut res = 0.0;
+
+        // Safety: `call` made sure there are exactly as many arguments as the plugin asked for
+        let status = unsafe { (self.evaluate)(arguments.as_ptr(), &mut res) };
+
+        if status != 0 {
+            let msg = format!("`{}` failed with error code {}", self.name, status);
+            return Err(CalcError::InvalidArgument(msg));
+        }
+
+        // The budget is cooperative and native code can't take part, so this is the first we
+        // get to find out it ran over. A plugin that never returns ties up its worker for good.
+        budget.check()?;
+
+        Ok(res)
+    }
+}
+
+/// Runs `handler`, as long as it got the right number of arguments
+pub fn call(
+    handler: &dyn OperationHandler,
+    arguments: &[f64],
+    budget: &Budget,
+) -> Result<f64, CalcError> {
+    let arity = handler.arity();
+
+    if arguments.len() != arity {
+        let plural = if arity == 1 { "" } else { "s" };
+        let msg = format!(
+            "`{}` takes {} argument{}, not {}",
+            handler.name(),
+            arity,
+            plural,
+            arguments.len()
+        );
+
+        return Err(CalcError::InvalidArgument(msg));
+    }
+
+    handler.evaluate(arguments, budget)
+}
+
+/// The operations every server has, registered the same way anyone else's would be
+pub fn bundled() -> io::Result<RegistryBuilder> {
+    OperationRegistry::builder()
+        .register_fn("hypot", 2, |args| Ok(args[0].hypot(args[1])))?
+        .register_fn("atan2", 2, |args| Ok(args[0].atan2(args[1])))?
+        .register_fn("clamp", 3, |args| {
+            let (x, min, max) = (args[0], args[1], args[2]);
+
+            if min > max || min.is_nan() || max.is_nan() {
+                let msg = format!("can't clamp between {} and {}", min, max);
+                return Err(CalcError::InvalidArgument(msg));
+            }
+
+            Ok(x.max(min).min(max))
+        })
+}
+
+/// Names are what clients ask for operations by, so they stick to letters, digits and `_`
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::raw::c_char;
+    use std::ptr;
+    use std::time::Duration;
+
+    use calc_utils::PluginOperation;
+
+    use super::*;
+
+    unsafe extern "C" fn add(arguments: *const f64, result: *mut f64) -> i32 {
+        *result = *arguments + *arguments.add(1);
+        0
+    }
+
+    unsafe extern "C" fn fail(_: *const f64, _: *mut f64) -> i32 {
+        7
+    }
+
+    static OPERATIONS: [PluginOperation; 2] = [
+        PluginOperation {
+            name: b"plugin_add\0".as_ptr() as *const c_char,
+            arity: 2,
+            evaluate: add,
+        },
+        PluginOperation {
+            name: b"plugin_fail\0".as_ptr() as *const c_char,
+            arity: 0,
+            evaluate: fail,
+        },
+    ];
+
+    static TABLE: PluginTable = PluginTable {
+        abi_version: PLUGIN_ABI_VERSION,
+        len: 2,
+        operations: &OPERATIONS as *const PluginOperation,
+    };
+
+    static FUTURE_TABLE: PluginTable = PluginTable {
+        abi_version: PLUGIN_ABI_VERSION + 1,
+        len: 0,
+        operations: ptr::null(),
+    };
+
+    static NOT_UTF8: [PluginOperation; 1] = [PluginOperation {
+        name: b"\xff\0".as_ptr() as *const c_char,
+        arity: 0,
+        evaluate: fail,
+    }];
+
+    static NOT_UTF8_TABLE: PluginTable = PluginTable {
+        abi_version: PLUGIN_ABI_VERSION,
+        len: 1,
+        operations: &NOT_UTF8 as *const PluginOperation,
+    };
+
+    fn register(table: &'static PluginTable) -> io::Result<OperationRegistry> {
+        // Safety: the tables are statics, so they're valid for good
+        let builder =
+            unsafe { OperationRegistry::builder().register_table(table, Path::new("test"), None) };
+        builder.map(RegistryBuilder::build)
+    }
+
+    fn run(registry: &OperationRegistry, name: &str, arguments: &[f64]) -> Result<f64, CalcError> {
+        let handler = registry.get(name).expect("operation missing");
+        call(&*handler, arguments, &Budget::new(Duration::from_secs(5)))
+    }
+
+    #[test]
+    fn load() {
+        let registry = register(&TABLE).unwrap();
+
+        let names: Vec<_> = registry.describe().into_iter().map(|op| op.name).collect();
+        assert_eq!(names, ["plugin_add", "plugin_fail"]);
+
+        assert_eq!(run(&registry, "plugin_add", &[1.5, 2.0]).unwrap(), 3.5);
+    }
+
+    #[test]
+    fn error_code() {
+        let registry = register(&TABLE).unwrap();
+
+        match run(&registry, "plugin_fail", &[]) {
+            Err(CalcError::InvalidArgument(msg)) => assert!(msg.contains("code 7"), "{}", msg),
+            other => panic!("expected an error code, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn wrong_arity() {
+        let registry = register(&TABLE).unwrap();
+
+        // The plugin would read past the arguments it got, so it can't be called at all
+        match run(&registry, "plugin_add", &[1.0]) {
+            Err(CalcError::InvalidArgument(msg)) => assert!(msg.contains("takes 2"), "{}", msg),
+            other => panic!("expected an arity error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn abi_version() {
+        let e = register(&FUTURE_TABLE).unwrap_err();
+        assert!(e.to_string().contains("plugin ABI 2"), "{}", e);
+    }
+
+    #[test]
+    fn name_not_utf8() {
+        let e = register(&NOT_UTF8_TABLE).unwrap_err();
+        assert!(e.to_string().contains("not UTF-8"), "{}", e);
+    }
+
+    #[test]
+    fn registered_twice() {
+        let builder =
+            unsafe { OperationRegistry::builder().register_table(&TABLE, Path::new("test"), None) };
+        let builder = unsafe {
+            builder
+                .unwrap()
+                .register_table(&TABLE, Path::new("again"), None)
+        };
+
+        assert!(builder
+            .unwrap_err()
+            .to_string()
+            .contains("registered twice"));
+    }
+}
@@ -0,0 +1,480 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::cmp::Ordering;
+use std::f64::consts::PI;
+
+use calc_utils::{CalcError, Complex, Convergence, Expr, RootMethod};
+
+use crate::calculator::function_of;
+use crate::iterative::Reporter;
+use crate::worker_pool::Budget;
+
+/// Highest degree polynomial we'll look for the roots of. Every iteration costs the square of the
+/// degree, so this keeps a single request from eating a worker's whole budget in one go.
+const MAX_DEGREE: usize = 256;
+
+/// Finds a value of `variable` that makes `expression` zero, reporting every guess along the way
+pub fn find(
+    expression: &str,
+    variable: &str,
+    method: &RootMethod,
+    convergence: &Convergence,
+    budget: &Budget,
+    reporter: &mut Reporter,
+) -> Result<f64, CalcError> {
+    check_convergence(convergence)?;
+
+    let expr = Expr::parse(expression)?;
+    let f = function_of(&expr, variable, budget);
+
+    match *method {
+        RootMethod::Bisection { lower, upper } => bisection(f, lower, upper, convergence, reporter),
+        RootMethod::Brent { lower, upper } => brent(f, lower, upper, convergence, reporter),
+        RootMethod::Newton { guess } => newton(f, guess, convergence, reporter),
+    }
+}
+
+/// All the roots of the polynomial with `coefficients`, highest power first, found together with
+/// the Durand–Kerner method. A real polynomial's complex roots come in conjugate pairs, so a root
+/// without a partner is taken to be real, however far off the real line the iteration left it.
+/// One with a partner keeps its imaginary part, however small.
+pub fn polynomial(
+    coefficients: &[f64],
+    convergence: &Convergence,
+    budget: &Budget,
+) -> Result<Vec<Complex>, CalcError> {
+    check_convergence(convergence)?;
+
+    if coefficients.iter().any(|c| !c.is_finite()) {
+        return Err(invalid("coefficients have to be finite".to_string()));
+    }
+
+    let coefficients = match coefficients.iter().position(|&c| c != 0.0) {
+        Some(leading) => &coefficients[leading..],
+        None => return Err(invalid("every number is a root of zero".to_string())),
+    };
+
+    if coefficients.len() - 1 > MAX_DEGREE {
+        return Err(invalid(format!(
+            "polynomials can have a degree of at most {}",
+            MAX_DEGREE
+        )));
+    }
+
+    // Zeros at the end are roots at zero. Taking them out first means we never start looking
+    // from, or divide by, a root of zero.
+    let zeros = coefficients.iter().rev().take_while(|&&c| c == 0.0).count();
+    let coefficients = &coefficients[..coefficients.len() - zeros];
+
+    let mut roots = vec![Complex::from(0.0); zeros];
+    let degree = coefficients.len() - 1;
+
+    if degree > 0 {
+        let monic: Vec<f64> = coefficients.iter().map(|c| c / coefficients[0]).collect();
+        roots.extend(durand_kerner(&monic, convergence, budget)?);
+    }
+
+    // Partners only have to match as closely as the iteration can pin them down, which is the
+    // square root of the tolerance for repeated roots, relative to how big they are
+    let close = convergence.tolerance.sqrt();
+    let mut paired = vec![false; roots.len()];
+
+    for i in 0..roots.len() {
+        if paired[i] || roots[i].im == 0.0 {
+            continue;
+        }
+
+        let conj = roots[i].conj();
+        let partner = (i + 1..roots.len())
+            .find(|&j| !paired[j] && (roots[j] - conj).abs() <= close * conj.abs());
+
+        match partner {
+            Some(j) => {
+                paired[i] = true;
+                paired[j] = true;
+            }
+
+            None => roots[i].im = 0.0,
+        }
+    }
+
+    roots.sort_by(|a, b| match a.re.total_cmp(&b.re) {
+        Ordering::Equal => a.im.total_cmp(&b.im),
+        ordering => ordering,
+    });
+
+    Ok(roots)
+}
+
+fn bisection<F>(
+    f: F,
+    lower: f64,
+    upper: f64,
+    convergence: &Convergence,
+    reporter: &mut Reporter,
+) -> Result<f64, CalcError>
+where
+    F: Fn(f64) -> Result<f64, CalcError>,
+{
+    let (mut a, mut fa, mut b) = match bracket(&f, lower, upper)? {
+        Bracket::Root(root) => return Ok(root),
+        Bracket::Between { a, fa, b, .. } => (a, fa, b),
+    };
+
+    for iteration in 1..=convergence.max_iterations {
+        let mid = a + (b - a) / 2.0;
+        let fmid = f(mid)?;
+
+        reporter.report(iteration, mid);
+
+        if fmid == 0.0 || (b - a) / 2.0 <= close_enough(mid, convergence) {
+            return Ok(mid);
+        }
+
+        if fmid.is_sign_negative() == fa.is_sign_negative() {
+            a = mid;
+            fa = fmid;
+        } else {
+            b = mid;
+        }
+    }
+
+    Err(CalcError::DidNotConverge {
+        iterations: convergence.max_iterations,
+    })
+}
+
+/// Brent's method as Numerical Recipes' `zbrent` has it. It tries inverse quadratic
+/// interpolation or the secant method, and falls back to bisection whenever those aren't
+/// shrinking the bracket fast enough, so it's never much slower than bisecting.
+fn brent<F>(
+    f: F,
+    lower: f64,
+    upper: f64,
+    convergence: &Convergence,
+    reporter: &mut Reporter,
+) -> Result<f64, CalcError>
+where
+    F: Fn(f64) -> Result<f64, CalcError>,
+{
+    let (mut a, mut fa, mut b, mut fb) = match bracket(&f, lower, upper)? {
+        Bracket::Root(root) => return Ok(root),
+        Bracket::Between { a, fa, b, fb } => (a, fa, b, fb),
+    };
+
+    // `b` is always the best guess so far, with the root between it and `c`
+    let (mut c, mut fc) = (b, fb);
+
+    // The last two steps we took
+    let mut d = 0.0;
+    let mut e = 0.0;
+
+    for iteration in 1..=convergence.max_iterations {
+        if fb.is_sign_negative() == fc.is_sign_negative() {
+            c = a;
+            fc = fa;
+            d = b - a;
+            e = d;
+        }
+
+        if fc.abs() < fb.abs() {
+            a = b;
+            b = c;
+            c = a;
+            fa = fb;
+            fb = fc;
+            fc = fa;
+        }
+
+        let tolerance = close_enough(b, convergence) / 2.0;
+        let half = (c - b) / 2.0;
+
+        if half.abs() <= tolerance || fb == 0.0 {
+            return Ok(b);
+        }
+
+        if e.abs() >= tolerance && fa.abs() > fb.abs() {
+            let s = fb / fa;
+
+            let (mut p, mut q) = if a == c {
+                (2.0 * half * s, 1.0 - s)
+            } else {
+                let q = fa / fc;
+                let r = fb / fc;
+
+                (
+                    s * (2.0 * half * q * (q - r) - (b - a) * (r - 1.0)),
+                    (q - 1.0) * (r - 1.0) * (s - 1.0),
+                )
+            };
+
+            if p > 0.0 {
+                q = -q;
+            }
+
+            p = p.abs();
+
+            // Only interpolate if it lands inside the bracket and beats the step before last
+            if 2.0 * p < (3.0 * half * q - (tolerance * q).abs()).min((e * q).abs()) {
+                e = d;
+                d = p / q;
+            } else {
+                d = half;
+                e = d;
+            }
+        } else {
+            d = half;
+            e = d;
+        }
+
+        a = b;
+        fa = fb;
+
+        b += if d.abs() > tolerance {
+            d
+        } else {
+            tolerance.copysign(half)
+        };
+        fb = f(b)?;
+
+        reporter.report(iteration, b);
+    }
+
+    Err(CalcError::DidNotConverge {
+        iterations: convergence.max_iterations,
+    })
+}
+
+/// Newton's method, with the derivative estimated by central differences since we only have the
+/// expression to go on
+fn newton<F>(
+    f: F,
+    guess: f64,
+    convergence: &Convergence,
+    reporter: &mut Reporter,
+) -> Result<f64, CalcError>
+where
+    F: Fn(f64) -> Result<f64, CalcError>,
+{
+    if !guess.is_finite() {
+        return Err(invalid(format!("guess {} is not finite", guess)));
+    }
+
+    let mut x = guess;
+
+    for iteration in 1..=convergence.max_iterations {
+        let fx = f(x)?;
+
+        if fx == 0.0 {
+            return Ok(x);
+        }
+
+        // The cube root of the machine epsilon balances the error from the step being too big
+        // against rounding from it being too small
+        let h = f64::EPSILON.cbrt() * x.abs().max(1.0);
+        let slope = (f(x + h)? - f(x - h)?) / (2.0 * h);
+
+        let next = x - fx / slope;
+
+        // A flat spot sends us off to infinity, and there's no coming back from there
+        if !next.is_finite() {
+            return Err(CalcError::DidNotConverge {
+                iterations: iteration,
+            });
+        }
+
+        reporter.report(iteration, next);
+
+        if (next - x).abs() <= close_enough(next, convergence) {
+            return Ok(next);
+        }
+
+        x = next;
+    }
+
+    Err(CalcError::DidNotConverge {
+        iterations: convergence.max_iterations,
+    })
+}
+
+fn durand_kerner(
+    monic: &[f64],
+    convergence: &Convergence,
+    budget: &Budget,
+) -> Result<Vec<Complex>, CalcError> {
+    let degree = monic.len() - 1;
+
+    // Every root is within Cauchy's bound of zero, so we start from points spread around a
+    // circle that size. The angle is offset so none of them start out on the real line, where
+    // they'd never be able to leave for a complex root.
+    let radius = 1.0 + monic[1..].iter().fold(0.0_f64, |max, c| max.max(c.abs()));
+
+    let mut roots: Vec<Complex> = (0..degree)
+        .map(|k| {
+            let angle = 2.0 * PI * k as f64 / degree as f64 + 0.4;
+            Complex::new(radius * angle.cos(), radius * angle.sin())
+        })
+        .collect();
+
+    for _ in 0..convergence.max_iterations {
+        budget.check()?;
+
+        let mut biggest_step = 0.0_f64;
+
+        for i in 0..degree {
+            let value = monic.iter().fold(Complex::from(0.0), |acc, &c| {
+                acc * roots[i] + Complex::from(c)
+            });
+
+            let others = (0..degree)
+                .filter(|&j| j != i)
+                .fold(Complex::from(1.0), |acc, j| acc * (roots[i] - roots[j]));
+
+            let step = value / others;
+
+            if !step.re.is_finite() || !step.im.is_finite() {
+                return Err(CalcError::DidNotConverge {
+                    iterations: convergence.max_iterations,
+                });
+            }
+
+            roots[i] = roots[i] - step;
+            biggest_step = biggest_step.max(step.abs() / roots[i].abs().max(1.0));
+        }
+
+        if biggest_step <= convergence.tolerance {
+            return Ok(roots);
+        }
+    }
+
+    Err(CalcError::DidNotConverge {
+        iterations: convergence.max_iterations,
+    })
+}
+
+enum Bracket {
+    /// One of the ends was a root already
+    Root(f64),
+
+    Between {
+        a: f64,
+        fa: f64,
+        b: f64,
+        fb: f64,
+    },
+}
+
+/// Checks there's a root between `lower` and `upper`, which there is as long as the expression
+/// has a different sign at each end and doesn't jump in between
+fn bracket<F>(f: F, lower: f64, upper: f64) -> Result<Bracket, CalcError>
+where
+    F: Fn(f64) -> Result<f64, CalcError>,
+{
+    if !lower.is_finite() || !upper.is_finite() || lower >= upper {
+        return Err(invalid(format!("[{}, {}] is not a bracket", lower, upper)));
+    }
+
+    let (fa, fb) = (f(lower)?, f(upper)?);
+
+    if fa == 0.0 {
+        return Ok(Bracket::Root(lower));
+    }
+
+    if fb == 0.0 {
+        return Ok(Bracket::Root(upper));
+    }
+
+    if fa.is_sign_negative() == fb.is_sign_negative() {
+        let msg = format!("the sign doesn't change between {} and {}", lower, upper);
+        return Err(invalid(msg));
+    }
+
+    Ok(Bracket::Between {
+        a: lower,
+        fa,
+        b: upper,
+        fb,
+    })
+}
+
+pub fn check_convergence(convergence: &Convergence) -> Result<(), CalcError> {
+    if !(convergence.tolerance > 0.0 && convergence.tolerance.is_finite()) {
+        return Err(invalid(format!(
+            "tolerance {} is not positive",
+            convergence.tolerance
+        )));
+    }
+
+    if convergence.max_iterations == 0 {
+        return Err(invalid(
+            "there has to be at least one iteration".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// How close to `x` is close enough. Far from zero the numbers near `x` are further apart than
+/// the tolerance, so we can't ask for better than a few of those gaps.
+fn close_enough(x: f64, convergence: &Convergence) -> f64 {
+    convergence.tolerance + 4.0 * f64::EPSILON * x.abs()
+}
+
+fn invalid(msg: String) -> CalcError {
+    CalcError::InvalidArgument(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn roots_of(coefficients: &[f64]) -> Vec<Complex> {
+        let budget = Budget::new(Duration::from_secs(5));
+        polynomial(coefficients, &Convergence::default(), &budget).unwrap()
+    }
+
+    #[test]
+    fn real_roots() {
+        // (x - 1)(x - 2)(x - 3)
+        let roots = roots_of(&[1.0, -6.0, 11.0, -6.0]);
+
+        for (root, expected) in roots.iter().zip(&[1.0, 2.0, 3.0]) {
+            assert_eq!(root.im, 0.0);
+            assert!((root.re - expected).abs() < 1e-9, "{:?}", roots);
+        }
+    }
+
+    #[test]
+    fn small_imaginary_parts() {
+        // x² + 1e-20 has roots at ±1e-10 i, which are tiny but not real
+        let mut roots = roots_of(&[1.0, 0.0, 1e-20]);
+
+        // Their real parts are both just noise around zero, so that's no way to order them
+        roots.sort_by(|a, b| a.im.total_cmp(&b.im));
+
+        assert_eq!(roots.len(), 2);
+        assert!(
+            roots.iter().all(|root| root.re.abs() < 1e-18),
+            "{:?}",
+            roots
+        );
+        assert!((roots[0].im + 1e-10).abs() < 1e-18, "{:?}", roots);
+        assert!((roots[1].im - 1e-10).abs() < 1e-18, "{:?}", roots);
+    }
+
+    #[test]
+    fn mixed_roots() {
+        // (x - 2)(x² + 1)
+        let roots = roots_of(&[1.0, -2.0, 1.0, -2.0]);
+
+        assert_eq!(roots.len(), 3);
+        assert!((roots[0].im + 1.0).abs() < 1e-9, "{:?}", roots);
+        assert!((roots[1].im - 1.0).abs() < 1e-9, "{:?}", roots);
+        assert_eq!(roots[2].im, 0.0);
+        assert!((roots[2].re - 2.0).abs() < 1e-9, "{:?}", roots);
+    }
+}
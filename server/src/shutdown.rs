@@ -0,0 +1,203 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::channel::{mpsc, oneshot};
+use futures::future::{self, FutureExt, Shared};
+use futures::task::{Context, Poll};
+use futures::StreamExt;
+use tokio::net::signal::ctrl_c;
+use tokio::prelude::*;
+use tokio::timer::Timeout;
+
+/// Resolves once the process has been asked to stop with SIGINT (ctrl-c) or SIGTERM
+pub async fn signal() -> io::Result<()> {
+    let mut interrupt = ctrl_c()?;
+
+    #[cfg(unix)]
+    {
+        use tokio_net::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        future::select(interrupt.next(), terminate.next()).await;
+    }
+
+    #[cfg(not(unix))]
+    interrupt.next().await;
+
+    Ok(())
+}
+
+/// Owned by the accept loop. Every connection gets a `ShutdownSignal` from it, and once the
+/// shutdown is triggered we can wait for all of those signals to be dropped.
+#[derive(Debug)]
+pub struct Shutdown {
+    trigger: oneshot::Sender<()>,
+    signal: Shared<oneshot::Receiver<()>>,
+    counters: Arc<Counters>,
+    guard: mpsc::Sender<()>,
+    guards: mpsc::Receiver<()>,
+}
+
+/// Handed to each connection so it knows when to stop reading requests
+#[derive(Debug)]
+pub struct ShutdownSignal {
+    signal: Shared<oneshot::Receiver<()>>,
+    counters: Arc<Counters>,
+
+    /// Whether this is a connection's signal, rather than a listener's that counts its
+    /// requests but isn't a connection itself
+    connection: bool,
+
+    // Never sent on, the receiving end just waits for every clone to be dropped
+    _guard: mpsc::Sender<()>,
+}
+
+/// What happened to the connections that were open when the shutdown started
+#[derive(Debug)]
+pub struct DrainSummary {
+    pub connections: usize,
+    pub requests: usize,
+    pub abandoned: usize,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    open_connections: AtomicUsize,
+    drained_requests: AtomicUsize,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        let (trigger, signal) = oneshot::channel();
+        let (guard, guards) = mpsc::channel(0);
+
+        Shutdown {
+            trigger,
+            signal: FutureExt::shared(signal),
+            counters: Arc::new(Counters::default()),
+            guard,
+            guards,
+        }
+    }
+
+    /// Creates the signal for a newly accepted connection
+    pub fn signal(&self) -> ShutdownSignal {
+        self.counters
+            .open_connections
+            .fetch_add(1, Ordering::SeqCst);
+
+        ShutdownSignal {
+            signal: self.signal.clone(),
+            counters: self.counters.clone(),
+            connection: true,
+            _guard: self.guard.clone(),
+        }
+    }
+
+    /// Creates the signal for a listener that answers its clients itself, like the UDP one. We
+    /// still wait for it to finish what it's doing, but it doesn't count as a connection.
+    pub fn listener_signal(&self) -> ShutdownSignal {
+        ShutdownSignal {
+            signal: self.signal.clone(),
+            counters: self.counters.clone(),
+            connection: false,
+            _guard: self.guard.clone(),
+        }
+    }
+
+    /// Tells every connection to say goodbye, then waits up to `grace` for them to finish the
+    /// requests they already read
+    pub async fn drain(self, grace: Duration) -> DrainSummary {
+        let Shutdown {
+            trigger,
+            counters,
+            guard,
+            mut guards,
+            ..
+        } = self;
+
+        let connections = counters.open_connections.load(Ordering::SeqCst);
+
+        // The connections might have all closed by themselves already, which is fine
+        let _ = trigger.send(());
+
+        // Drop our own guard so the receiver ends once the last connection is gone
+        drop(guard);
+        let _ = Timeout::new(guards.next(), grace).await;
+
+        let abandoned = counters.open_connections.load(Ordering::SeqCst);
+
+        DrainSummary {
+            connections: connections - abandoned,
+            requests: counters.drained_requests.load(Ordering::SeqCst),
+            abandoned,
+        }
+    }
+}
+
+impl ShutdownSignal {
+    /// Whether the server has started shutting down
+    pub fn is_triggered(&self) -> bool {
+        FutureExt::now_or_never(self.signal.clone()).is_some()
+    }
+
+    /// Records a request that was completed while the server was shutting down
+    pub fn request_drained(&self) {
+        self.counters
+            .drained_requests
+            .fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl Future for ShutdownSignal {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        // A dropped trigger means the server is going away as well
+        Pin::new(&mut self.signal).poll(cx).map(|_| ())
+    }
+}
+
+impl Drop for ShutdownSignal {
+    fn drop(&mut self) {
+        if self.connection {
+            self.counters
+                .open_connections
+                .fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn listeners_arent_connections() {
+        let shutdown = Shutdown::new();
+
+        let connection = shutdown.signal();
+        let listener = shutdown.listener_signal();
+
+        // Both are still around when the drain starts, and finish once it's told them to
+        let finish = async move {
+            connection.await;
+            listener.request_drained();
+            drop(listener);
+        };
+
+        let (summary, ()) = futures::join!(shutdown.drain(Duration::from_secs(1)), finish);
+
+        assert_eq!(summary.connections, 1);
+        assert_eq!(summary.requests, 1);
+        assert_eq!(summary.abandoned, 0);
+    }
+}
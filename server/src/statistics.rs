@@ -0,0 +1,495 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::f64::consts::{PI, SQRT_2};
+
+use calc_utils::{CalcError, Distribution, StatisticsOperation};
+
+use crate::special::{self, beta_i, erfc, gamma_p, gamma_q, ln_gamma};
+use crate::worker_pool::Budget;
+
+pub fn evaluate(operation: &StatisticsOperation, budget: &Budget) -> Result<f64, CalcError> {
+    match *operation {
+        StatisticsOperation::Pdf(distribution, x) => {
+            check_distribution(distribution)?;
+            check_finite(x)?;
+            pdf(distribution, x)
+        }
+
+        StatisticsOperation::Cdf(distribution, x) => {
+            check_distribution(distribution)?;
+            check_finite(x)?;
+            cdf(distribution, x, budget)
+        }
+
+        StatisticsOperation::Quantile(distribution, p) => {
+            check_distribution(distribution)?;
+
+            if !(0.0..=1.0).contains(&p) {
+                return Err(invalid(format!("{} is not a probability", p)));
+            }
+
+            quantile(distribution, p, budget)
+        }
+
+        StatisticsOperation::Factorial(n) => {
+            permutations(n, n).map_err(|_| too_big(format!("{}!", n)))
+        }
+
+        StatisticsOperation::Choose(n, k) => choose(n, k),
+        StatisticsOperation::Permutations(n, k) => permutations(n, k),
+        StatisticsOperation::Gamma(x) => special::gamma(x),
+        StatisticsOperation::Beta(a, b) => special::beta(a, b),
+    }
+}
+
+fn pdf(distribution: Distribution, x: f64) -> Result<f64, CalcError> {
+    let density = match distribution {
+        Distribution::Normal { mean, std_dev } => {
+            let z = (x - mean) / std_dev;
+            (-z * z / 2.0).exp() / (std_dev * (2.0 * PI).sqrt())
+        }
+
+        // Anything that's not a whole number of successes can't happen at all
+        Distribution::Binomial {
+            trials,
+            probability,
+        } => match count(x) {
+            Some(k) if k <= trials as f64 => {
+                let n = trials as f64;
+
+                if probability == 0.0 || probability == 1.0 {
+                    let certain = if probability == 0.0 { 0.0 } else { n };
+                    return Ok(if k == certain { 1.0 } else { 0.0 });
+                }
+
+                (ln_choose(n, k) + k * probability.ln() + (n - k) * (-probability).ln_1p()).exp()
+            }
+
+            _ => 0.0,
+        },
+
+        Distribution::Poisson { rate } => match count(x) {
+            Some(k) => (k * rate.ln() - rate - ln_gamma(k + 1.0)).exp(),
+            None => 0.0,
+        },
+
+        Distribution::StudentT {
+            degrees_of_freedom: v,
+        } => {
+            let ln_front = ln_gamma((v + 1.0) / 2.0) - ln_gamma(v / 2.0) - (v * PI).ln() / 2.0;
+            (ln_front - (v + 1.0) / 2.0 * (x * x / v).ln_1p()).exp()
+        }
+
+        Distribution::ChiSquared {
+            degrees_of_freedom: k,
+        } => {
+            if x < 0.0 {
+                0.0
+            } else if x == 0.0 {
+                // Where it starts depends on the shape, and it starts at infinity below 2
+                if k < 2.0 {
+                    let msg = format!("the density at 0 is infinite for {} degrees of freedom", k);
+                    return Err(invalid(msg));
+                }
+
+                if k == 2.0 {
+                    0.5
+                } else {
+                    0.0
+                }
+            } else {
+                let half = k / 2.0;
+                ((half - 1.0) * x.ln() - x / 2.0 - half * 2f64.ln() - ln_gamma(half)).exp()
+            }
+        }
+    };
+
+    Ok(density)
+}
+
+fn cdf(distribution: Distribution, x: f64, budget: &Budget) -> Result<f64, CalcError> {
+    match distribution {
+        Distribution::Normal { mean, std_dev } => {
+            let z = (x - mean) / std_dev;
+            Ok(erfc(-z / SQRT_2, budget)? / 2.0)
+        }
+
+        Distribution::Binomial {
+            trials,
+            probability,
+        } => {
+            let (n, k) = (trials as f64, x.floor());
+
+            if k < 0.0 {
+                Ok(0.0)
+            } else if k >= n || probability == 0.0 {
+                Ok(1.0)
+            } else {
+                beta_i(n - k, k + 1.0, 1.0 - probability, budget)
+            }
+        }
+
+        Distribution::Poisson { rate } => {
+            let k = x.floor();
+
+            if k < 0.0 {
+                Ok(0.0)
+            } else {
+                gamma_q(k + 1.0, rate, budget)
+            }
+        }
+
+        // Both tails are the same, so we work out the one below `-|x|` and flip it over for
+        // positive `x`
+        Distribution::StudentT {
+            degrees_of_freedom: v,
+        } => {
+            let tail = beta_i(v / 2.0, 0.5, v / (v + x * x), budget)? / 2.0;
+            Ok(if x > 0.0 { 1.0 - tail } else { tail })
+        }
+
+        Distribution::ChiSquared {
+            degrees_of_freedom: k,
+        } => {
+            if x <= 0.0 {
+                Ok(0.0)
+            } else {
+                gamma_p(k / 2.0, x / 2.0, budget)
+            }
+        }
+    }
+}
+
+/// None of these have a quantile we can write down, except through functions we'd have to
+/// invert numerically anyway, so they all get searched for on the cdf. Only the binomial
+/// distribution has an end on both sides, the rest run off to infinity at one or both.
+fn quantile(distribution: Distribution, p: f64, budget: &Budget) -> Result<f64, CalcError> {
+    let cdf_at = |x: f64| cdf(distribution, x, budget);
+
+    match distribution {
+        Distribution::Normal { mean, std_dev } => {
+            if p == 0.0 || p == 1.0 {
+                return Err(infinite(p));
+            }
+
+            let z = invert(|z| cdf_at(mean + std_dev * z), p, -1.0, 1.0, budget)?;
+            finite(mean + std_dev * z, p)
+        }
+
+        Distribution::StudentT { .. } => {
+            if p == 0.0 || p == 1.0 {
+                return Err(infinite(p));
+            }
+
+            invert(cdf_at, p, -1.0, 1.0, budget)
+        }
+
+        // Starting from zero keeps the search from ever widening below it
+        Distribution::ChiSquared {
+            degrees_of_freedom: k,
+        } => {
+            if p == 0.0 {
+                return Ok(0.0);
+            }
+
+            if p == 1.0 {
+                return Err(infinite(p));
+            }
+
+            invert(cdf_at, p, 0.0, k.max(1.0), budget)
+        }
+
+        Distribution::Binomial { trials, .. } => {
+            invert_count(|k| cdf_at(k as f64), p, trials, budget).map(|k| k as f64)
+        }
+
+        Distribution::Poisson { rate } => {
+            if p == 1.0 {
+                return Err(infinite(p));
+            }
+
+            let mut upper = rate.ceil().max(1.0) as u64;
+
+            while cdf_at(upper as f64)? < p {
+                upper = upper.checked_mul(2).ok_or_else(|| infinite(p))?;
+            }
+
+            invert_count(|k| cdf_at(k as f64), p, upper, budget).map(|k| k as f64)
+        }
+    }
+}
+
+/// Finds where an increasing `cdf` gets to `p` by widening `[lower, upper]` until it's in there
+/// and then halving it until there's no float left between its ends. That's around 60 halvings,
+/// or up to about a thousand for answers right next to zero.
+fn invert<F>(
+    cdf: F,
+    p: f64,
+    mut lower: f64,
+    mut upper: f64,
+    budget: &Budget,
+) -> Result<f64, CalcError>
+where
+    F: Fn(f64) -> Result<f64, CalcError>,
+{
+    while cdf(lower)? > p {
+        lower -= upper - lower;
+        finite(lower, p)?;
+    }
+
+    while cdf(upper)? < p {
+        upper += upper - lower;
+        finite(upper, p)?;
+    }
+
+    loop {
+        budget.check()?;
+
+        let mid = lower + (upper - lower) / 2.0;
+
+        if mid <= lower || mid >= upper {
+            return Ok(mid);
+        }
+
+        if cdf(mid)? < p {
+            lower = mid;
+        } else {
+            upper = mid;
+        }
+    }
+}
+
+/// The smallest count up to `upper` with a cdf of at least `p`, by bisecting on whole numbers
+fn invert_count<F>(cdf: F, p: f64, mut upper: u64, budget: &Budget) -> Result<u64, CalcError>
+where
+    F: Fn(u64) -> Result<f64, CalcError>,
+{
+    if cdf(0)? >= p {
+        return Ok(0);
+    }
+
+    let mut lower = 0;
+
+    while upper - lower > 1 {
+        budget.check()?;
+
+        let mid = lower + (upper - lower) / 2;
+
+        if cdf(mid)? < p {
+            lower = mid;
+        } else {
+            upper = mid;
+        }
+    }
+
+    Ok(upper)
+}
+
+/// Ways to pick `k` out of `n`, which stays exact for as long as it fits in a `u128`. After
+/// that it has to go through the gamma function, and gets less exact the bigger `n` is.
+fn choose(n: u64, k: u64) -> Result<f64, CalcError> {
+    if k > n {
+        return Ok(0.0);
+    }
+
+    let k = k.min(n - k);
+    let mut res: u128 = 1;
+
+    for i in 0..k {
+        // Every step is itself a binomial coefficient, so the division is always exact
+        match res.checked_mul(u128::from(n - i)) {
+            Some(product) => res = product / u128::from(i + 1),
+            None => {
+                let res = ln_choose(n as f64, k as f64).exp();
+
+                if res.is_infinite() {
+                    return Err(too_big(format!("{} choose {}", n, k)));
+                }
+
+                return Ok(res);
+            }
+        }
+    }
+
+    Ok(res as f64)
+}
+
+/// `n! / (n - k)!`, which is always at least `k!`. That overflows a float by 171, so the loop
+/// never gets far before either finishing or giving up.
+fn permutations(n: u64, k: u64) -> Result<f64, CalcError> {
+    if k > n {
+        return Ok(0.0);
+    }
+
+    let mut res = 1.0;
+
+    for i in 0..k {
+        res *= (n - i) as f64;
+
+        if res.is_infinite() {
+            return Err(too_big(format!("the permutations of {} out of {}", k, n)));
+        }
+    }
+
+    Ok(res)
+}
+
+fn ln_choose(n: f64, k: f64) -> f64 {
+    ln_gamma(n + 1.0) - ln_gamma(k + 1.0) - ln_gamma(n - k + 1.0)
+}
+
+/// `x` as a count, if it's a whole number that's not negative
+fn count(x: f64) -> Option<f64> {
+    if x >= 0.0 && x.fract() == 0.0 {
+        Some(x)
+    } else {
+        None
+    }
+}
+
+fn check_distribution(distribution: Distribution) -> Result<(), CalcError> {
+    let valid = match distribution {
+        Distribution::Normal { mean, std_dev } => {
+            mean.is_finite() && std_dev > 0.0 && std_dev.is_finite()
+        }
+
+        Distribution::Binomial { probability, .. } => (0.0..=1.0).contains(&probability),
+        Distribution::Poisson { rate } => rate > 0.0 && rate.is_finite(),
+
+        Distribution::StudentT { degrees_of_freedom }
+        | Distribution::ChiSquared { degrees_of_freedom } => {
+            degrees_of_freedom > 0.0 && degrees_of_freedom.is_finite()
+        }
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(invalid(format!(
+            "{:?} is not a valid distribution",
+            distribution
+        )))
+    }
+}
+
+fn check_finite(x: f64) -> Result<(), CalcError> {
+    if x.is_finite() {
+        Ok(())
+    } else {
+        Err(invalid(format!("{} is not finite", x)))
+    }
+}
+
+/// Quantiles that run off to infinity can't be sent as a float over every protocol we have, so
+/// those are errors instead
+fn finite(x: f64, p: f64) -> Result<f64, CalcError> {
+    if x.is_finite() {
+        Ok(x)
+    } else {
+        Err(infinite(p))
+    }
+}
+
+fn too_big(what: String) -> CalcError {
+    invalid(format!("{} is too big for a float", what))
+}
+
+fn infinite(p: f64) -> CalcError {
+    invalid(format!("the quantile at {} is infinite", p))
+}
+
+fn invalid(msg: String) -> CalcError {
+    CalcError::InvalidArgument(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::special::tests::{assert_close, budget};
+
+    fn cdf_at(distribution: Distribution, x: f64) -> f64 {
+        evaluate(&StatisticsOperation::Cdf(distribution, x), &budget()).unwrap()
+    }
+
+    fn quantile_at(distribution: Distribution, p: f64) -> f64 {
+        evaluate(&StatisticsOperation::Quantile(distribution, p), &budget()).unwrap()
+    }
+
+    const STANDARD_NORMAL: Distribution = Distribution::Normal {
+        mean: 0.0,
+        std_dev: 1.0,
+    };
+
+    // The reference values come from mpmath with 30 digits
+
+    #[test]
+    fn normal() {
+        assert_close(cdf_at(STANDARD_NORMAL, 1.96), 0.9750021048517795, 1e-14);
+        assert_close(
+            cdf_at(STANDARD_NORMAL, -1.96),
+            1.0 - 0.9750021048517795,
+            1e-14,
+        );
+        assert_close(
+            quantile_at(STANDARD_NORMAL, 0.975),
+            1.959963984540054,
+            1e-14,
+        );
+
+        let normal = Distribution::Normal {
+            mean: 10.0,
+            std_dev: 2.0,
+        };
+        assert_close(
+            quantile_at(normal, 0.975),
+            10.0 + 2.0 * 1.959963984540054,
+            1e-14,
+        );
+    }
+
+    #[test]
+    fn student_t() {
+        let t = Distribution::StudentT {
+            degrees_of_freedom: 10.0,
+        };
+
+        assert_close(quantile_at(t, 0.975), 2.228138851986275, 1e-14);
+        assert_close(quantile_at(t, 0.025), -2.228138851986275, 1e-14);
+        assert_close(cdf_at(t, 0.0), 0.5, 1e-15);
+    }
+
+    #[test]
+    fn chi_squared() {
+        let chi_squared = Distribution::ChiSquared {
+            degrees_of_freedom: 3.0,
+        };
+
+        assert_close(quantile_at(chi_squared, 0.95), 7.814727903251178, 1e-14);
+        assert_close(cdf_at(chi_squared, 7.814727903251178), 0.95, 1e-14);
+    }
+
+    #[test]
+    fn binomial() {
+        let binomial = Distribution::Binomial {
+            trials: 10,
+            probability: 0.3,
+        };
+
+        assert_close(cdf_at(binomial, 3.0), 0.6496107184, 1e-14);
+        assert_close(cdf_at(binomial, 3.5), 0.6496107184, 1e-14);
+        assert_eq!(cdf_at(binomial, 10.0), 1.0);
+        assert_eq!(quantile_at(binomial, 0.6496), 3.0);
+        assert_eq!(quantile_at(binomial, 0.6497), 4.0);
+    }
+
+    #[test]
+    fn poisson() {
+        let poisson = Distribution::Poisson { rate: 4.0 };
+
+        assert_close(cdf_at(poisson, 5.0), 0.7851303870304052, 1e-14);
+        assert_eq!(quantile_at(poisson, 0.785), 5.0);
+        assert_eq!(quantile_at(poisson, 0.786), 6.0);
+    }
+}
@@ -0,0 +1,676 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use calc_utils::{Builtin, CalcError, Expr, Operation, Symbolic, SymbolicRequest, MAX_EXPR_NODES};
+
+use crate::worker_pool::Budget;
+
+/// Most nodes we build along the way. Derivatives copy parts of the expression around, so they
+/// can get a lot bigger than what they came from before simplifying shrinks them back down.
+const MAX_WORK: usize = 64 * MAX_EXPR_NODES;
+
+/// Builds expressions, counting every node so a request can't have us build without limit. The
+/// methods named after operations tidy up as they go, so `x * 1` never gets built in the first
+/// place.
+#[derive(Debug)]
+struct Builder<'a> {
+    nodes: usize,
+    budget: &'a Budget,
+}
+
+/// Part of a sum, `coefficient * rest`, or just the coefficient when there's no rest
+#[derive(Debug)]
+struct Term {
+    coefficient: f64,
+    rest: Option<Expr>,
+}
+
+/// Part of a product, `base^exponent`
+#[derive(Debug)]
+struct Factor {
+    base: Expr,
+    exponent: f64,
+}
+
+pub fn evaluate(request: &SymbolicRequest, budget: &Budget) -> Result<Expr, CalcError> {
+    let expr = Expr::parse(&request.expression)?;
+    let mut builder = Builder { nodes: 0, budget };
+
+    let res = match &request.operation {
+        Symbolic::Derivative { variable } => {
+            let derivative = builder.derivative(&expr, variable)?;
+            builder.simplify(&derivative)?
+        }
+
+        Symbolic::Simplify => builder.simplify(&expr)?,
+    };
+
+    // It has to fit in a frame the client is willing to read
+    if size(&res) > MAX_EXPR_NODES {
+        return Err(too_big());
+    }
+
+    Ok(res)
+}
+
+impl<'a> Builder<'a> {
+    fn derivative(&mut self, expr: &Expr, variable: &str) -> Result<Expr, CalcError> {
+        match expr {
+            Expr::Number(_) => self.number(0.0),
+            Expr::Variable(name) => self.number(if name == variable { 1.0 } else { 0.0 }),
+
+            Expr::Negate(u) => {
+                let du = self.derivative(u, variable)?;
+                self.neg(du)
+            }
+
+            Expr::Binary(operation, u, v) => {
+                let du = self.derivative(u, variable)?;
+
+                match operation {
+                    Operation::Addition => {
+                        let dv = self.derivative(v, variable)?;
+                        self.add(du, dv)
+                    }
+
+                    Operation::Subtraction => {
+                        let dv = self.derivative(v, variable)?;
+                        self.sub(du, dv)
+                    }
+
+                    Operation::Multiplication => {
+                        let dv = self.derivative(v, variable)?;
+                        let (u, v) = (self.copy(u)?, self.copy(v)?);
+
+                        let lhs = self.mul(du, v)?;
+                        let rhs = self.mul(u, dv)?;
+                        self.add(lhs, rhs)
+                    }
+
+                    Operation::Division => {
+                        let dv = self.derivative(v, variable)?;
+                        let (u, v, denominator) = (self.copy(u)?, self.copy(v)?, self.copy(v)?);
+
+                        let lhs = self.mul(du, v)?;
+                        let rhs = self.mul(u, dv)?;
+                        let numerator = self.sub(lhs, rhs)?;
+
+                        let two = self.number(2.0)?;
+                        let denominator = self.pow(denominator, two)?;
+                        self.div(numerator, denominator)
+                    }
+
+                    // The power rule, plus the chain rule for whatever is in the base
+                    Operation::Power if !contains(v, variable) => {
+                        let (u, v, coefficient) = (self.copy(u)?, self.copy(v)?, self.copy(v)?);
+
+                        let one = self.number(1.0)?;
+                        let exponent = self.sub(v, one)?;
+                        let power = self.pow(u, exponent)?;
+                        let scaled = self.mul(coefficient, power)?;
+                        self.mul(scaled, du)
+                    }
+
+                    // A constant base only needs its logarithm, a^v' = a^v * ln(a) * v'
+                    Operation::Power if !contains(u, variable) => {
+                        let dv = self.derivative(v, variable)?;
+                        let power = self.copy(expr)?;
+                        let ln = self.call(Builtin::Ln, u)?;
+
+                        let scaled = self.mul(power, ln)?;
+                        self.mul(scaled, dv)
+                    }
+
+                    // Otherwise it's u^v * (v' * ln(u) + v * u' / u), from writing it as
+                    // exp(v * ln(u))
+                    Operation::Power => {
+                        let dv = self.derivative(v, variable)?;
+                        let (power, ln) = (self.copy(expr)?, self.call(Builtin::Ln, u)?);
+                        let (v, base) = (self.copy(v)?, self.copy(u)?);
+
+                        let lhs = self.mul(dv, ln)?;
+                        let rhs = self.mul(v, du)?;
+                        let rhs = self.div(rhs, base)?;
+                        let sum = self.add(lhs, rhs)?;
+                        self.mul(power, sum)
+                    }
+                }
+            }
+
+            Expr::Call(..) if !contains(expr, variable) => self.number(0.0),
+
+            // The chain rule, with the derivative of the function itself worked out at `u`
+            Expr::Call(name, arguments) => {
+                let builtin = match (Builtin::lookup(name), arguments.len()) {
+                    (Some(builtin), 1) => builtin,
+
+                    (Some(_), len) => {
+                        let msg = format!("`{}` takes 1 argument, not {}", name, len);
+                        return Err(CalcError::InvalidExpression(msg));
+                    }
+
+                    (None, _) => {
+                        let msg = format!("`{}` isn't a built-in function", name);
+                        return Err(CalcError::InvalidArgument(msg));
+                    }
+                };
+
+                let u = &arguments[0];
+                let du = self.derivative(u, variable)?;
+
+                let outer = match builtin {
+                    Builtin::Sqrt => {
+                        let (one, two) = (self.number(1.0)?, self.number(2.0)?);
+                        let root = self.call(Builtin::Sqrt, u)?;
+                        let twice = self.mul(two, root)?;
+                        self.div(one, twice)?
+                    }
+
+                    Builtin::Exp => self.call(Builtin::Exp, u)?,
+
+                    Builtin::Ln => {
+                        let (one, u) = (self.number(1.0)?, self.copy(u)?);
+                        self.div(one, u)?
+                    }
+
+                    Builtin::Sin => self.call(Builtin::Cos, u)?,
+
+                    Builtin::Cos => {
+                        let sin = self.call(Builtin::Sin, u)?;
+                        self.neg(sin)?
+                    }
+
+                    Builtin::Tan => {
+                        let (one, two) = (self.number(1.0)?, self.number(2.0)?);
+                        let cos = self.call(Builtin::Cos, u)?;
+                        let squared = self.pow(cos, two)?;
+                        self.div(one, squared)?
+                    }
+
+                    // The sign of `u`, which leaves out the point where there's no derivative
+                    Builtin::Abs => {
+                        let (u, abs) = (self.copy(u)?, self.call(Builtin::Abs, u)?);
+                        self.div(u, abs)?
+                    }
+                };
+
+                self.mul(outer, du)
+            }
+        }
+    }
+
+    /// Simplifies from the bottom up. Sums and products get taken apart all the way down to
+    /// what's not a sum or a product, so like terms and factors can be collected however they
+    /// were spread out.
+    fn simplify(&mut self, expr: &Expr) -> Result<Expr, CalcError> {
+        match expr {
+            Expr::Number(_) | Expr::Variable(_) => self.copy(expr),
+
+            Expr::Negate(u) => {
+                let u = self.simplify(u)?;
+                self.neg(u)
+            }
+
+            Expr::Binary(Operation::Addition, ..) | Expr::Binary(Operation::Subtraction, ..) => {
+                let mut terms = Vec::new();
+                self.terms(expr, 1.0, false, &mut terms)?;
+                self.sum(terms)
+            }
+
+            Expr::Binary(Operation::Multiplication, ..) | Expr::Binary(Operation::Division, ..) => {
+                let mut coefficient = 1.0;
+                let mut factors = Vec::new();
+                self.factors(expr, 1.0, false, &mut coefficient, &mut factors)?;
+                self.product(coefficient, factors)
+            }
+
+            Expr::Binary(Operation::Power, base, exponent) => {
+                let base = self.simplify(base)?;
+                let exponent = self.simplify(exponent)?;
+                self.pow(base, exponent)
+            }
+
+            Expr::Call(name, arguments) => {
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.simplify(argument))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                self.node(Expr::Call(name.clone(), arguments))
+            }
+        }
+    }
+
+    /// Takes a sum apart into its terms, simplifying each of them first unless `simplified` says
+    /// it already is
+    fn terms(
+        &mut self,
+        expr: &Expr,
+        sign: f64,
+        simplified: bool,
+        terms: &mut Vec<Term>,
+    ) -> Result<(), CalcError> {
+        match expr {
+            Expr::Binary(Operation::Addition, lhs, rhs) => {
+                self.terms(lhs, sign, simplified, terms)?;
+                self.terms(rhs, sign, simplified, terms)
+            }
+
+            Expr::Binary(Operation::Subtraction, lhs, rhs) => {
+                self.terms(lhs, sign, simplified, terms)?;
+                self.terms(rhs, -sign, simplified, terms)
+            }
+
+            Expr::Negate(operand) => self.terms(operand, -sign, simplified, terms),
+
+            // Simplifying can turn it into a sum, like `(x + 1) * 1`, so it gets another look
+            _ if !simplified => {
+                let expr = self.simplify(expr)?;
+                self.terms(&expr, sign, true, terms)
+            }
+
+            _ => {
+                let (coefficient, rest) = split_coefficient(expr.clone());
+                let coefficient = sign * coefficient;
+
+                // Terms that only differ in their coefficient are like terms, as long as adding
+                // up the coefficients doesn't overflow
+                let like = terms
+                    .iter_mut()
+                    .find(|term| term.rest == rest && (term.coefficient + coefficient).is_finite());
+
+                match like {
+                    Some(term) => term.coefficient += coefficient,
+                    None => terms.push(Term { coefficient, rest }),
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Puts the terms back together, with the constant last
+    fn sum(&mut self, terms: Vec<Term>) -> Result<Expr, CalcError> {
+        let (constants, others): (Vec<_>, Vec<_>) =
+            terms.into_iter().partition(|term| term.rest.is_none());
+
+        let mut sum = None;
+
+        for Term { coefficient, rest } in others.into_iter().chain(constants) {
+            if coefficient == 0.0 {
+                continue;
+            }
+
+            let magnitude = self.term(coefficient.abs(), rest)?;
+
+            sum = Some(match sum {
+                None if coefficient < 0.0 => self.neg(magnitude)?,
+                None => magnitude,
+
+                Some(sum) => {
+                    let operation = if coefficient < 0.0 {
+                        Operation::Subtraction
+                    } else {
+                        Operation::Addition
+                    };
+
+                    self.binary(operation, sum, magnitude)?
+                }
+            });
+        }
+
+        match sum {
+            Some(sum) => Ok(sum),
+            None => self.number(0.0),
+        }
+    }
+
+    fn term(&mut self, coefficient: f64, rest: Option<Expr>) -> Result<Expr, CalcError> {
+        match rest {
+            None => self.number(coefficient),
+            Some(rest) if coefficient == 1.0 => Ok(rest),
+
+            // `2 * (x / y)` reads better as `2 * x / y`, and `2 * (1 / y)` as `2 / y`
+            Some(Expr::Binary(Operation::Division, numerator, denominator)) => {
+                let coefficient = self.number(coefficient)?;
+
+                let numerator = if is(&numerator, 1.0) {
+                    coefficient
+                } else {
+                    self.binary(Operation::Multiplication, coefficient, *numerator)?
+                };
+
+                self.binary(Operation::Division, numerator, *denominator)
+            }
+
+            Some(rest) => {
+                let coefficient = self.number(coefficient)?;
+                self.binary(Operation::Multiplication, coefficient, rest)
+            }
+        }
+    }
+
+    /// Takes a product apart into its factors, multiplying every number into `coefficient`.
+    /// `sign` is -1 for what's being divided by.
+    fn factors(
+        &mut self,
+        expr: &Expr,
+        sign: f64,
+        simplified: bool,
+        coefficient: &mut f64,
+        factors: &mut Vec<Factor>,
+    ) -> Result<(), CalcError> {
+        match expr {
+            Expr::Binary(Operation::Multiplication, lhs, rhs) => {
+                self.factors(lhs, sign, simplified, coefficient, factors)?;
+                self.factors(rhs, sign, simplified, coefficient, factors)
+            }
+
+            Expr::Binary(Operation::Division, lhs, rhs) => {
+                self.factors(lhs, sign, simplified, coefficient, factors)?;
+                self.factors(rhs, -sign, simplified, coefficient, factors)
+            }
+
+            Expr::Negate(operand) => {
+                *coefficient = -*coefficient;
+                self.factors(operand, sign, simplified, coefficient, factors)
+            }
+
+            _ if !simplified => {
+                let expr = self.simplify(expr)?;
+                self.factors(&expr, sign, true, coefficient, factors)
+            }
+
+            Expr::Number(num) => {
+                let folded = if sign > 0.0 {
+                    *coefficient * num
+                } else {
+                    *coefficient / num
+                };
+
+                // Anything that would overflow, or divides by zero, stays where it is
+                if folded.is_finite() {
+                    *coefficient = folded;
+                } else {
+                    add_factor(factors, expr.clone(), sign);
+                }
+
+                Ok(())
+            }
+
+            Expr::Binary(Operation::Power, base, exponent) => match **exponent {
+                Expr::Number(exponent) => {
+                    add_factor(factors, (**base).clone(), sign * exponent);
+                    Ok(())
+                }
+
+                _ => {
+                    add_factor(factors, expr.clone(), sign);
+                    Ok(())
+                }
+            },
+
+            _ => {
+                add_factor(factors, expr.clone(), sign);
+                Ok(())
+            }
+        }
+    }
+
+    /// Puts the factors back together, as the coefficient times whatever has a positive exponent,
+    /// divided by whatever has a negative one
+    fn product(&mut self, coefficient: f64, factors: Vec<Factor>) -> Result<Expr, CalcError> {
+        if coefficient == 0.0 {
+            return self.number(0.0);
+        }
+
+        let mut numerator = None;
+        let mut denominator = None;
+
+        for Factor { base, exponent } in factors {
+            if exponent == 0.0 {
+                continue;
+            }
+
+            let (side, exponent) = if exponent > 0.0 {
+                (&mut numerator, exponent)
+            } else {
+                (&mut denominator, -exponent)
+            };
+
+            let factor = if exponent == 1.0 {
+                base
+            } else {
+                let exponent = self.number(exponent)?;
+                self.binary(Operation::Power, base, exponent)?
+            };
+
+            *side = Some(match side.take() {
+                Some(product) => self.binary(Operation::Multiplication, product, factor)?,
+                None => factor,
+            });
+        }
+
+        let numerator = match numerator {
+            None => self.number(coefficient)?,
+            Some(numerator) if coefficient == 1.0 => numerator,
+            Some(numerator) if coefficient == -1.0 => self.neg(numerator)?,
+
+            Some(numerator) => {
+                let coefficient = self.number(coefficient)?;
+                self.binary(Operation::Multiplication, coefficient, numerator)?
+            }
+        };
+
+        match denominator {
+            Some(denominator) => self.binary(Operation::Division, numerator, denominator),
+            None => Ok(numerator),
+        }
+    }
+
+    fn neg(&mut self, operand: Expr) -> Result<Expr, CalcError> {
+        match operand {
+            Expr::Number(num) => self.number(-num),
+            Expr::Negate(operand) => Ok(*operand),
+            operand => self.node(Expr::Negate(Box::new(operand))),
+        }
+    }
+
+    fn add(&mut self, lhs: Expr, rhs: Expr) -> Result<Expr, CalcError> {
+        match (lhs, rhs) {
+            (Expr::Number(a), Expr::Number(b)) if (a + b).is_finite() => self.number(a + b),
+            (lhs, rhs) if is(&lhs, 0.0) => Ok(rhs),
+            (lhs, rhs) if is(&rhs, 0.0) => Ok(lhs),
+            (lhs, Expr::Negate(rhs)) => self.sub(lhs, *rhs),
+            (lhs, Expr::Number(b)) if b < 0.0 => self.sub(lhs, Expr::Number(-b)),
+            (lhs, rhs) => self.binary(Operation::Addition, lhs, rhs),
+        }
+    }
+
+    fn sub(&mut self, lhs: Expr, rhs: Expr) -> Result<Expr, CalcError> {
+        match (lhs, rhs) {
+            (Expr::Number(a), Expr::Number(b)) if (a - b).is_finite() => self.number(a - b),
+            (lhs, rhs) if is(&rhs, 0.0) => Ok(lhs),
+            (lhs, rhs) if is(&lhs, 0.0) => self.neg(rhs),
+            (lhs, rhs) if lhs == rhs => self.number(0.0),
+            (lhs, Expr::Negate(rhs)) => self.add(lhs, *rhs),
+            (lhs, rhs) => self.binary(Operation::Subtraction, lhs, rhs),
+        }
+    }
+
+    fn mul(&mut self, lhs: Expr, rhs: Expr) -> Result<Expr, CalcError> {
+        match (lhs, rhs) {
+            (Expr::Number(a), Expr::Number(b)) if (a * b).is_finite() => self.number(a * b),
+            (lhs, rhs) if is(&lhs, 0.0) || is(&rhs, 0.0) => self.number(0.0),
+            (lhs, rhs) if is(&lhs, 1.0) => Ok(rhs),
+            (lhs, rhs) if is(&rhs, 1.0) => Ok(lhs),
+            (lhs, rhs) if is(&lhs, -1.0) => self.neg(rhs),
+            (lhs, rhs) if is(&rhs, -1.0) => self.neg(lhs),
+
+            // Keeps the numbers in front, where the simplifier looks for them
+            (lhs, Expr::Number(b)) => self.binary(Operation::Multiplication, Expr::Number(b), lhs),
+
+            (lhs, rhs) => self.binary(Operation::Multiplication, lhs, rhs),
+        }
+    }
+
+    fn div(&mut self, lhs: Expr, rhs: Expr) -> Result<Expr, CalcError> {
+        match (lhs, rhs) {
+            (Expr::Number(a), Expr::Number(b)) if (a / b).is_finite() => self.number(a / b),
+            (lhs, rhs) if is(&rhs, 1.0) => Ok(lhs),
+            (lhs, _) if is(&lhs, 0.0) => self.number(0.0),
+            (lhs, rhs) => self.binary(Operation::Division, lhs, rhs),
+        }
+    }
+
+    fn pow(&mut self, base: Expr, exponent: Expr) -> Result<Expr, CalcError> {
+        match (base, exponent) {
+            (Expr::Number(a), Expr::Number(b)) if a.powf(b).is_finite() => self.number(a.powf(b)),
+            (base, exponent) if is(&exponent, 0.0) || is(&base, 1.0) => self.number(1.0),
+            (base, exponent) if is(&exponent, 1.0) => Ok(base),
+            (base, exponent) => self.binary(Operation::Power, base, exponent),
+        }
+    }
+
+    /// `builtin(argument)`, with a copy of the argument
+    fn call(&mut self, builtin: Builtin, argument: &Expr) -> Result<Expr, CalcError> {
+        let argument = self.copy(argument)?;
+        self.node(Expr::Call(builtin.name().to_string(), vec![argument]))
+    }
+
+    fn number(&mut self, num: f64) -> Result<Expr, CalcError> {
+        self.node(Expr::Number(num))
+    }
+
+    fn binary(&mut self, operation: Operation, lhs: Expr, rhs: Expr) -> Result<Expr, CalcError> {
+        self.node(Expr::Binary(operation, Box::new(lhs), Box::new(rhs)))
+    }
+
+    /// A copy of part of an expression we need in more than one place, counted like we'd built
+    /// it ourselves
+    fn copy(&mut self, expr: &Expr) -> Result<Expr, CalcError> {
+        self.nodes += size(expr) - 1;
+        self.node(expr.clone())
+    }
+
+    /// Counts another node towards the limit, and checks we've still got time for it
+    fn node(&mut self, expr: Expr) -> Result<Expr, CalcError> {
+        self.nodes += 1;
+
+        if self.nodes > MAX_WORK {
+            return Err(too_big());
+        }
+
+        self.budget.check()?;
+
+        Ok(expr)
+    }
+}
+
+/// Splits the number off the front of a term, like the 2 off `2 * x`
+fn split_coefficient(expr: Expr) -> (f64, Option<Expr>) {
+    match expr {
+        Expr::Number(num) => (num, None),
+
+        Expr::Binary(Operation::Multiplication, lhs, rhs) => match *lhs {
+            Expr::Number(num) => (num, Some(*rhs)),
+            lhs => (
+                1.0,
+                Some(Expr::Binary(Operation::Multiplication, Box::new(lhs), rhs)),
+            ),
+        },
+
+        // `2 * x / y` is `2` times `x / y`, and `2 / y` is `2` times `1 / y`
+        Expr::Binary(Operation::Division, numerator, denominator) => {
+            let (num, numerator) = split_coefficient(*numerator);
+            let numerator = numerator.unwrap_or(Expr::Number(1.0));
+
+            (
+                num,
+                Some(Expr::Binary(
+                    Operation::Division,
+                    Box::new(numerator),
+                    denominator,
+                )),
+            )
+        }
+
+        expr => (1.0, Some(expr)),
+    }
+}
+
+fn add_factor(factors: &mut Vec<Factor>, base: Expr, exponent: f64) {
+    let like = factors
+        .iter_mut()
+        .find(|factor| factor.base == base && (factor.exponent + exponent).is_finite());
+
+    match like {
+        Some(factor) => factor.exponent += exponent,
+        None => factors.push(Factor { base, exponent }),
+    }
+}
+
+fn is(expr: &Expr, num: f64) -> bool {
+    matches!(expr, Expr::Number(n) if *n == num)
+}
+
+fn contains(expr: &Expr, variable: &str) -> bool {
+    match expr {
+        Expr::Number(_) => false,
+        Expr::Variable(name) => name == variable,
+        Expr::Negate(operand) => contains(operand, variable),
+        Expr::Binary(_, lhs, rhs) => contains(lhs, variable) || contains(rhs, variable),
+        Expr::Call(_, arguments) => arguments
+            .iter()
+            .any(|argument| contains(argument, variable)),
+    }
+}
+
+fn size(expr: &Expr) -> usize {
+    match expr {
+        Expr::Number(_) | Expr::Variable(_) => 1,
+        Expr::Negate(operand) => 1 + size(operand),
+        Expr::Binary(_, lhs, rhs) => 1 + size(lhs) + size(rhs),
+        Expr::Call(_, arguments) => 1 + arguments.iter().map(size).sum::<usize>(),
+    }
+}
+
+fn too_big() -> CalcError {
+    CalcError::InvalidArgument(format!(
+        "the result would have more than {} nodes",
+        MAX_EXPR_NODES
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calculator::evaluate_expression;
+    use crate::special::tests::{assert_close, budget};
+
+    /// The derivative of `expression` with respect to `x`, worked out at `at`
+    fn derivative_at(expression: &str, at: f64) -> f64 {
+        let operation = Symbolic::Derivative {
+            variable: "x".to_string(),
+        };
+
+        let derivative = evaluate(&SymbolicRequest::new(expression, operation), &budget()).unwrap();
+        evaluate_expression(&derivative, &[("x", at)], &budget()).unwrap()
+    }
+
+    #[test]
+    fn power_rule() {
+        assert_close(derivative_at("x^3", 2.0), 12.0, 1e-15);
+        assert_close(derivative_at("(2 * x + 1)^2", 1.0), 12.0, 1e-15);
+    }
+
+    #[test]
+    fn variable_exponent() {
+        let ln2 = 2f64.ln();
+
+        assert_close(derivative_at("2^x", 3.0), 8.0 * ln2, 1e-15);
+        assert_close(derivative_at("2^(3 * x)", 1.0), 24.0 * ln2, 1e-15);
+        assert_close(derivative_at("x^x", 2.0), 4.0 * (ln2 + 1.0), 1e-15);
+    }
+}
@@ -0,0 +1,144 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::io::{self, Cursor};
+use std::net::SocketAddr;
+
+use futures::channel::mpsc;
+use futures::future::{self, Either, FutureExt};
+use futures::StreamExt;
+use tokio::net::udp::split::UdpSocketSendHalf;
+use tokio::net::UdpSocket;
+
+use calc_utils::{Deserializer, MathRequest, MathResult, Serializer};
+
+use crate::calculator::{evaluate, isolated};
+use crate::limits::Limits;
+use crate::shutdown::ShutdownSignal;
+use crate::worker_pool::WorkerPool;
+
+/// Big enough for any UDP datagram, so nothing we read ever gets cut short
+const MAX_DATAGRAM_LEN: usize = 64 * 1024;
+
+enum Event {
+    Datagram(io::Result<(usize, SocketAddr)>),
+    Result(SocketAddr, MathResult),
+    Shutdown,
+}
+
+/// Answers every datagram holding a `MathRequest` with a datagram holding its `MathResult`, sent
+/// back to wherever the request came from. Both are encoded the same as in a packet, just without
+/// the length in front since the datagram already has one.
+///
+/// There's no challenge to answer, so this is only for servers that let anyone in. Every request
+/// counts against its address' rate limit, and anything we can't make sense of gets dropped
+/// without an answer, same as if it got lost on the way.
+pub async fn serve(
+    socket: UdpSocket,
+    pool: WorkerPool,
+    limits: Limits,
+    mut shutdown: ShutdownSignal,
+) -> io::Result<()> {
+    let (mut datagrams, mut replies) = socket.split();
+    let mut buf = vec![0; MAX_DATAGRAM_LEN];
+
+    // Results come back through here the same way they do for a connection
+    let (result_tx, mut result_rx) = mpsc::unbounded::<(SocketAddr, MathResult)>();
+
+    let mut in_flight = 0;
+    let mut reading = true;
+
+    while reading || in_flight > 0 {
+        let next_result = result_rx.next().map(|res| {
+            // We're holding on to a sender ourselves, so the channel can't end
+            let (peer, math_res) = res.expect("result channel closed");
+            Event::Result(peer, math_res)
+        });
+
+        let event = if reading {
+            let datagram = Box::pin(datagrams.recv_from(&mut buf));
+            let next_datagram = future::select(&mut shutdown, datagram);
+            let next_datagram = next_datagram.map(|either| match either {
+                Either::Left(((), _)) => Event::Shutdown,
+                Either::Right((datagram, _)) => Event::Datagram(datagram),
+            });
+
+            future::select(next_result, next_datagram)
+                .await
+                .factor_first()
+                .0
+        } else {
+            next_result.await
+        };
+
+        match event {
+            Event::Datagram(Ok((len, peer))) => {
+                let request: MathRequest = match Cursor::new(&buf[..len]).deserialize() {
+                    Ok(request) => request,
+
+                    Err(e) => {
+                        println!("Dropping datagram from {}: {}", peer, e);
+                        continue;
+                    }
+                };
+
+                println!("Math request from {}: {:?}", peer, &request);
+
+                let id = request.id;
+                let result_tx = result_tx.clone();
+
+                let queued = limits.check_datagram(peer.ip()).and_then(|()| {
+                    pool.execute(move |budget| {
+                        let math_res = MathResult {
+                            id: request.id,
+                            res: isolated(|| evaluate(&request, budget)),
+                        };
+
+                        let _ = result_tx.unbounded_send((peer, math_res));
+                    })
+                });
+
+                match queued {
+                    Ok(()) => in_flight += 1,
+                    Err(e) => send_result(&mut replies, peer, MathResult { id, res: Err(e) }).await,
+                }
+            }
+
+            // Errors on a UDP socket are about a single datagram, so there's no reason to stop
+            Event::Datagram(Err(e)) => println!("Error receiving datagram: {}", e),
+
+            Event::Result(peer, math_res) => {
+                println!("Result for {}: {:?}", peer, math_res);
+
+                in_flight -= 1;
+                send_result(&mut replies, peer, math_res).await;
+
+                if shutdown.is_triggered() {
+                    shutdown.request_drained();
+                }
+            }
+
+            // Nobody is waiting on a connection for us to say goodbye, so just stop reading and
+            // send out what's still being worked on
+            Event::Shutdown => reading = false,
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends a result off without waiting to hear whether it arrived. Not being able to send one is
+/// the same to the client as it getting lost, so it's not worth stopping for.
+async fn send_result(replies: &mut UdpSocketSendHalf, peer: SocketAddr, math_res: MathResult) {
+    let mut buf = Vec::new();
+
+    let sent = match buf.serialize(&math_res) {
+        Ok(()) => replies.send_to(&buf, &peer).await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = sent {
+        println!("Error sending result to {}: {}", peer, e);
+    }
+}
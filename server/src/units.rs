@@ -0,0 +1,118 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use calc_utils::{BaseUnits, CalcError, Operation, Quantity, Unit, UnitOperation};
+
+pub fn evaluate(operation: &UnitOperation) -> Result<Quantity, CalcError> {
+    match operation {
+        UnitOperation::Binary(operation, a, b) => binary(operation, a, b),
+
+        UnitOperation::Convert(quantity, unit) => {
+            let from = Unit::parse(&quantity.unit)?;
+            let to = Unit::parse(unit)?;
+
+            if from.base_units != to.base_units {
+                return Err(incompatible(&quantity.unit, unit));
+            }
+
+            Ok(Quantity {
+                value: to.from_si(from.to_si(quantity.value)),
+                unit: unit.clone(),
+            })
+        }
+    }
+}
+
+fn binary(operation: &Operation, a: &Quantity, b: &Quantity) -> Result<Quantity, CalcError> {
+    let (unit_a, unit_b) = (scale_only(&a.unit)?, scale_only(&b.unit)?);
+
+    match operation {
+        Operation::Addition | Operation::Subtraction => {
+            if unit_a.base_units != unit_b.base_units {
+                return Err(incompatible(&a.unit, &b.unit));
+            }
+
+            let b_value = b.value * unit_b.factor / unit_a.factor;
+
+            let value = match operation {
+                Operation::Addition => a.value + b_value,
+                _ => a.value - b_value,
+            };
+
+            Ok(Quantity {
+                value,
+                unit: a.unit.clone(),
+            })
+        }
+
+        Operation::Multiplication => {
+            let base_units = unit_a.base_units.multiply(unit_b.base_units)?;
+            let value = a.value * unit_a.factor * (b.value * unit_b.factor);
+
+            Ok(Quantity {
+                value,
+                unit: base_units.to_string(),
+            })
+        }
+
+        Operation::Division => {
+            let base_units = unit_a.base_units.divide(unit_b.base_units)?;
+            let value = a.value * unit_a.factor / (b.value * unit_b.factor);
+
+            Ok(Quantity {
+                value,
+                unit: base_units.to_string(),
+            })
+        }
+
+        Operation::Power => {
+            if !unit_b.base_units.is_none() {
+                return Err(incompatible(&b.unit, "1"));
+            }
+
+            let exponent = b.value * unit_b.factor;
+            let value = (a.value * unit_a.factor).powf(exponent);
+
+            if unit_a.base_units.is_none() {
+                return Ok(Quantity {
+                    value,
+                    unit: BaseUnits::NONE.to_string(),
+                });
+            }
+
+            // Half a metre squared is fine, a metre to the power of a half isn't a unit
+            if exponent.fract() != 0.0 || exponent.abs() > f64::from(i32::MAX) {
+                let msg = format!("{} can only be raised to whole powers", a.unit);
+                return Err(CalcError::InvalidArgument(msg));
+            }
+
+            let base_units = unit_a.base_units.pow(exponent as i32)?;
+
+            Ok(Quantity {
+                value,
+                unit: base_units.to_string(),
+            })
+        }
+    }
+}
+
+/// Parses a unit for arithmetic, which only works with units that start at zero. Twice 20°C is
+/// not 40°C, so those have to be converted to kelvins first.
+fn scale_only(unit: &str) -> Result<Unit, CalcError> {
+    let parsed = Unit::parse(unit)?;
+
+    if parsed.offset != 0.0 {
+        let msg = format!("{} doesn't start at zero, convert it to K first", unit);
+        return Err(CalcError::InvalidArgument(msg));
+    }
+
+    Ok(parsed)
+}
+
+fn incompatible(lhs: &str, rhs: &str) -> CalcError {
+    CalcError::IncompatibleUnits {
+        lhs: lhs.to_string(),
+        rhs: rhs.to_string(),
+    }
+}
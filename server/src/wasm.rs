@@ -0,0 +1,528 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use wasmtime::{Config, Engine, ExternType, InstancePre, Linker, Module, Store, Trap, Val};
+use wasmtime::{StoreLimits, StoreLimitsBuilder};
+
+use calc_utils::CalcError;
+
+use crate::operations::{valid_name, OperationHandler, OperationRegistry};
+use crate::worker_pool::Budget;
+
+/// How much fuel a single call gets, which is roughly how many WebAssembly instructions it can
+/// run. Running out counts the same as going over the time budget.
+const FUEL_PER_CALL: u64 = 50_000_000;
+
+/// Most linear memory a module can have while a call runs, across all of it. A module only gets
+/// one memory and one table, otherwise each of them would get this much.
+const MAX_MEMORY_BYTES: usize = 16 * 1024 * 1024;
+
+/// Most entries a module's tables can have
+const MAX_TABLE_ELEMENTS: usize = 10_000;
+
+/// How often a directory of modules gets checked for new, changed and removed ones
+const RESCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Compiles WebAssembly modules into operations. Modules don't get any imports, so all they can
+/// do is compute, and every call gets a fresh instance with its own fuel and memory limits.
+pub struct Sandbox {
+    engine: Engine,
+}
+
+/// Keeps the operations from a directory of modules in step with the files in it, so kernels
+/// can be added, replaced and taken away without restarting the server
+struct Watcher {
+    sandbox: Sandbox,
+    dir: PathBuf,
+    operations: Arc<OperationRegistry>,
+
+    /// Every module we've seen, with when it was last changed and what we registered from it
+    modules: HashMap<PathBuf, (SystemTime, Vec<String>)>,
+}
+
+/// A function exported by a module, taking and returning nothing but `f64`s
+pub struct WasmOperation {
+    name: String,
+    arity: usize,
+    module: Arc<InstancePre<StoreLimits>>,
+}
+
+impl Sandbox {
+    pub fn new() -> io::Result<Sandbox> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+
+        // Every memory would get the whole limit to itself
+        config.wasm_multi_memory(false);
+
+        let engine = Engine::new(&config).map_err(io::Error::other)?;
+
+        Ok(Sandbox { engine })
+    }
+
+    /// Compiles the module at `path`, which can be binary or text, and finds every function it
+    /// exports that we can call. Anything else it exports is left alone, and so is a function
+    /// whose name clients couldn't ask for.
+    pub fn load(&self, path: &Path) -> io::Result<Vec<WasmOperation>> {
+        let module = Module::from_file(&self.engine, path)
+            .map_err(|e| io::Error::other(format!("{:?}: {}", path, e)))?;
+
+        self.operations(module, path)
+    }
+
+    fn operations(&self, module: Module, path: &Path) -> io::Result<Vec<WasmOperation>> {
+        let describe = |e| io::Error::other(format!("{:?}: {}", path, e));
+
+        // An empty linker means any import at all fails here, instead of on the first call
+        let module = Linker::new(&self.engine)
+            .instantiate_pre(&module)
+            .map_err(describe)?;
+
+        // Instantiating it once up front catches modules that could never fit in the limits
+        instantiate(&module).map_err(describe)?;
+
+        let module = Arc::new(module);
+        let mut operations = Vec::new();
+
+        for export in module.module().exports() {
+            let ty = match export.ty() {
+                ExternType::Func(ty) => ty,
+                _ => continue,
+            };
+
+            if !(ty.params().all(|param| param.is_f64())
+                && ty.results().len() == 1
+                && ty.results().all(|result| result.is_f64()))
+            {
+                continue;
+            }
+
+            if !valid_name(export.name()) {
+                println!(
+                    "Skipping `{}` from {:?}, it's not a valid operation name",
+                    export.name(),
+                    path
+                );
+                continue;
+            }
+
+            operations.push(WasmOperation {
+                name: export.name().to_string(),
+                arity: ty.params().len(),
+                module: module.clone(),
+            });
+        }
+
+        if operations.is_empty() {
+            let msg = format!("{:?} doesn't export any functions on f64s", path);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+
+        Ok(operations)
+    }
+}
+
+/// Loads every module in `dir` into `operations`, then keeps checking it for changes on a
+/// thread of its own. Modules should be written somewhere else and moved in, otherwise one can
+/// get picked up half written. It'll fail to load then, and load fine once it's changed again.
+pub fn watch(sandbox: Sandbox, dir: PathBuf, operations: Arc<OperationRegistry>) -> io::Result<()> {
+    let mut watcher = Watcher {
+        sandbox,
+        dir,
+        operations,
+        modules: HashMap::new(),
+    };
+
+    // The first time round it happens before we start taking connections
+    watcher.scan();
+
+    thread::Builder::new()
+        .name("wasm watcher".to_string())
+        .spawn(move || loop {
+            thread::sleep(RESCAN_INTERVAL);
+            watcher.scan();
+        })?;
+
+    Ok(())
+}
+
+impl Watcher {
+    fn scan(&mut self) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                println!("Can't read modules from {:?}: {}", self.dir, e);
+                return;
+            }
+        };
+
+        let mut present = HashSet::new();
+
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+
+            match path.extension().and_then(|extension| extension.to_str()) {
+                Some("wasm") | Some("wat") => {}
+                _ => continue,
+            }
+
+            let modified = match entry.metadata().and_then(|metadata| metadata.modified()) {
+                Ok(modified) => modified,
+                Err(_) => continue,
+            };
+
+            present.insert(path.clone());
+
+            if self.modules.get(&path).map(|(seen, _)| *seen) == Some(modified) {
+                continue;
+            }
+
+            // A module that changed has its old operations taken away before the new ones go
+            // in, since it's likely to have the same names
+            self.unload(&path);
+
+            let names = self.load(&path);
+            self.modules.insert(path, (modified, names));
+        }
+
+        let removed: Vec<PathBuf> = self
+            .modules
+            .keys()
+            .filter(|path| !present.contains(*path))
+            .cloned()
+            .collect();
+
+        for path in removed {
+            self.unload(&path);
+            self.modules.remove(&path);
+        }
+    }
+
+    /// Registers what it can from the module at `path`. Nothing here stops the server, the worst
+    /// a bad module gets is a line in the log.
+    fn load(&self, path: &Path) -> Vec<String> {
+        let loaded = match self.sandbox.load(path) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                println!("Couldn't load module: {}", e);
+                return Vec::new();
+            }
+        };
+
+        let mut names = Vec::new();
+
+        for operation in loaded {
+            let name = operation.name.clone();
+
+            match self.operations.insert(Arc::new(operation)) {
+                Ok(()) => names.push(name),
+                Err(e) => println!("Skipping `{}` from {:?}: {}", name, path, e),
+            }
+        }
+
+        println!("Loaded {:?} from {:?}", names, path);
+
+        names
+    }
+
+    fn unload(&self, path: &Path) {
+        if let Some((_, names)) = self.modules.get(path) {
+            for name in names {
+                self.operations.remove(name);
+            }
+
+            println!("Unloaded {:?} from {:?}", names, path);
+        }
+    }
+}
+
+impl fmt::Debug for Sandbox {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sandbox").finish()
+    }
+}
+
+impl OperationHandler for WasmOperation {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn evaluate(&self, arguments: &[f64], budget: &Budget) -> Result<f64, CalcError> {
+        let (mut store, instance) = instantiate(&self.module).map_err(|e| self.failed(e))?;
+
+        let func = instance
+            .get_func(&mut store, &self.name)
+            .ok_or_else(|| CalcError::Internal(format!("`{}` isn't exported", self.name)))?;
+
+        let arguments = arguments
+            .iter()
+            .map(|&argument| Val::from(argument))
+            .collect::<Vec<_>>();
+        let mut results = [Val::F64(0)];
+
+        func.call(&mut store, &arguments, &mut results)
+            .map_err(|e| self.failed(e))?;
+
+        budget.check()?;
+
+        results[0]
+            .f64()
+            .ok_or_else(|| CalcError::Internal(format!("`{}` didn't return an f64", self.name)))
+    }
+}
+
+impl WasmOperation {
+    fn failed(&self, e: wasmtime::Error) -> CalcError {
+        match e.downcast_ref::<Trap>() {
+            Some(Trap::OutOfFuel) => CalcError::TimedOut,
+            Some(trap) => CalcError::InvalidArgument(format!("`{}` trapped: {}", self.name, trap)),
+            None => CalcError::InvalidArgument(format!("`{}` failed: {}", self.name, e)),
+        }
+    }
+}
+
+/// Makes a fresh instance with a full tank of fuel, so nothing one call does is seen by the next
+fn instantiate(
+    module: &InstancePre<StoreLimits>,
+) -> wasmtime::Result<(Store<StoreLimits>, wasmtime::Instance)> {
+    let limits = StoreLimitsBuilder::new()
+        .memory_size(MAX_MEMORY_BYTES)
+        .table_elements(MAX_TABLE_ELEMENTS)
+        .memories(1)
+        .tables(1)
+        .instances(1)
+        .build();
+
+    let mut store = Store::new(module.module().engine(), limits);
+    store.limiter(|limits| limits);
+    store.set_fuel(FUEL_PER_CALL)?;
+
+    let instance = module.instantiate(&mut store)?;
+
+    Ok((store, instance))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn load(wat: &str) -> io::Result<Vec<WasmOperation>> {
+        let sandbox = Sandbox::new()?;
+        let module = Module::new(&sandbox.engine, wat).map_err(io::Error::other)?;
+
+        sandbox.operations(module, Path::new("test.wat"))
+    }
+
+    fn run(wat: &str, arguments: &[f64]) -> Result<f64, CalcError> {
+        let operations = load(wat).unwrap();
+        operations[0].evaluate(arguments, &Budget::new(Duration::from_secs(60)))
+    }
+
+    fn trapped(res: Result<f64, CalcError>) -> String {
+        match res {
+            Err(CalcError::InvalidArgument(msg)) => msg,
+            other => panic!("expected a trap, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn call() {
+        let wat = r#"(module
+            (func (export "mul_add") (param f64 f64 f64) (result f64)
+                (f64.add (f64.mul (local.get 0) (local.get 1)) (local.get 2))))"#;
+
+        let operations = load(wat).unwrap();
+        assert_eq!(operations[0].name(), "mul_add");
+        assert_eq!(operations[0].arity(), 3);
+
+        assert_eq!(run(wat, &[2.0, 3.0, 1.0]).unwrap(), 7.0);
+    }
+
+    #[test]
+    fn infinite_loop() {
+        let wat = r#"(module
+            (func (export "spin") (result f64)
+                (loop (br 0))
+                (f64.const 0)))"#;
+
+        // It runs out of fuel long before the budget, which is what makes this quick
+        assert!(matches!(run(wat, &[]), Err(CalcError::TimedOut)));
+    }
+
+    #[test]
+    fn memory_limit() {
+        // Each page is 64KiB, so the first grows to exactly 16MiB and the second would go past
+        let wat = r#"(module
+            (memory 1)
+            (func (export "grow") (param f64) (result f64)
+                (f64.convert_i32_s (memory.grow (i32.trunc_f64_u (local.get 0))))))"#;
+
+        assert_eq!(run(wat, &[255.0]).unwrap(), 1.0);
+        assert_eq!(run(wat, &[256.0]).unwrap(), -1.0);
+
+        // One that needs more than that to begin with can't be loaded at all
+        let wat = r#"(module
+            (memory 257)
+            (func (export "big") (result f64) (f64.const 0)))"#;
+
+        assert!(load(wat).is_err());
+
+        // Nor can one that splits it up between memories that would each fit
+        let wat = r#"(module
+            (memory 256)
+            (memory 256)
+            (func (export "split") (result f64) (f64.const 0)))"#;
+
+        assert!(load(wat).is_err());
+    }
+
+    #[test]
+    fn traps() {
+        let wat = r#"(module
+            (func (export "crash") (result f64) unreachable))"#;
+
+        let msg = trapped(run(wat, &[]));
+        assert!(msg.contains("`crash` trapped"), "{}", msg);
+
+        let wat = r#"(module
+            (func $deep (export "deep") (result f64) (call $deep)))"#;
+
+        let msg = trapped(run(wat, &[]));
+        assert!(msg.contains("`deep` trapped"), "{}", msg);
+    }
+
+    #[test]
+    fn imports() {
+        let wat = r#"(module
+            (import "env" "log" (func $log (param f64)))
+            (func (export "noisy") (param f64) (result f64)
+                (call $log (local.get 0))
+                (local.get 0)))"#;
+
+        assert!(load(wat).is_err());
+    }
+
+    /// A directory of its own for each test, which goes away afterwards
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> TempDir {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+            let n = COUNT.fetch_add(1, Ordering::SeqCst);
+            let dir = std::env::temp_dir().join(format!("calc-wasm-{}-{}", process::id(), n));
+            fs::create_dir(&dir).unwrap();
+
+            TempDir(dir)
+        }
+
+        /// Writes a module, making sure it looks changed even if the last write was a moment ago
+        fn write(&self, name: &str, wat: &str, modified: u64) {
+            let path = self.0.join(name);
+            fs::write(&path, wat).unwrap();
+
+            let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(modified);
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .and_then(|file| file.set_modified(modified))
+                .unwrap();
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn watch_dir() {
+        let dir = TempDir::new();
+        let operations = Arc::new(OperationRegistry::default());
+
+        let mut watcher = Watcher {
+            sandbox: Sandbox::new().unwrap(),
+            dir: dir.0.clone(),
+            operations: operations.clone(),
+            modules: HashMap::new(),
+        };
+
+        let value = |name: &str| {
+            let handler = operations.get(name)?;
+            Some(
+                handler
+                    .evaluate(&[], &Budget::new(Duration::from_secs(5)))
+                    .unwrap(),
+            )
+        };
+
+        dir.write(
+            "one.wat",
+            r#"(module (func (export "one") (result f64) (f64.const 1)))"#,
+            1,
+        );
+        dir.write("notes.txt", "not a module", 1);
+        dir.write("broken.wat", "(module", 1);
+        watcher.scan();
+
+        assert_eq!(value("one"), Some(1.0));
+
+        // Changing a module replaces what it had, and a name someone else has is left to them
+        dir.write(
+            "one.wat",
+            r#"(module (func (export "uno") (result f64) (f64.const 1)))"#,
+            2,
+        );
+        dir.write(
+            "two.wat",
+            r#"(module
+                (func (export "two") (result f64) (f64.const 2))
+                (func (export "uno") (result f64) (f64.const 3)))"#,
+            2,
+        );
+        watcher.scan();
+
+        assert_eq!(value("one"), None);
+        assert_eq!(value("two"), Some(2.0));
+        assert!(value("uno").is_some());
+
+        fs::remove_file(dir.0.join("one.wat")).unwrap();
+        fs::remove_file(dir.0.join("two.wat")).unwrap();
+        watcher.scan();
+
+        assert!(operations.describe().is_empty());
+    }
+
+    #[test]
+    fn invalid_names() {
+        let wat = r#"(module
+            (func (export "not-valid") (result f64) (f64.const 1))
+            (func (export "valid") (result f64) (f64.const 2))
+            (func (export "integer") (result i32) (i32.const 3)))"#;
+
+        let names: Vec<_> = load(wat)
+            .unwrap()
+            .iter()
+            .map(|op| op.name.clone())
+            .collect();
+        assert_eq!(names, ["valid"]);
+    }
+}
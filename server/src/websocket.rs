@@ -0,0 +1,405 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::io;
+use std::pin::Pin;
+use std::time::Duration;
+
+use futures::sink::Sink;
+use futures::task::{Context, Poll};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::timer::Timeout;
+
+use calc_utils::{accept_websocket, CalcError, Message, Response, Serializer};
+
+use crate::calculator::{serve, Connection};
+
+/// Clients pick how we answer them with `Sec-WebSocket-Protocol`. Without either of these they
+/// get binary messages, the same as asking for `calc.binary`. What they send us can be either
+/// kind no matter what they picked.
+const BINARY_PROTOCOL: &str = "calc.binary";
+const JSON_PROTOCOL: &str = "calc.json";
+
+/// How long a client gets to send its upgrade request
+const UPGRADE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Turns responses into WebSocket messages, either with the same bytes a packet would have or
+/// as JSON
+#[derive(Debug)]
+struct ResponseSink<W> {
+    messages: W,
+    json: bool,
+}
+
+/// Serves a client that came in through the WebSocket listener. Every message carries one of
+/// the frames a plain connection would send, and the rest goes exactly like `process_client`.
+pub async fn process_websocket<S>(
+    stream: S,
+    admission: Result<Connection, CalcError>,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let upgrade = accept_websocket(stream, &[BINARY_PROTOCOL, JSON_PROTOCOL]);
+
+    let (websocket, protocol) = match Timeout::new(upgrade, UPGRADE_TIMEOUT).await {
+        Ok(upgraded) => upgraded?,
+        Err(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "no upgrade request sent",
+            ))
+        }
+    };
+
+    let limit = websocket.limit();
+    let (messages, frames) = websocket.split();
+
+    let mut response_sink = ResponseSink {
+        messages,
+        json: protocol == Some(JSON_PROTOCOL),
+    };
+
+    // Turning them away has to wait until the upgrade is done, otherwise a browser
+    // would just see a failed connection without a reason
+    let res = match admission {
+        Ok(connection) => serve(frames, &mut response_sink, limit, connection).await,
+        Err(e) => response_sink.send(&Response::Rejected(e)).await,
+    };
+
+    // Finish with a proper closing handshake, even if something went wrong
+    let closed = response_sink.close().await;
+
+    res.and(closed)
+}
+
+impl<W: Sink<Message, Error = io::Error> + Unpin> Sink<&Response> for ResponseSink<W> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.get_mut().messages).poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: &Response) -> Result<(), io::Error> {
+        let sink = self.get_mut();
+
+        let message = if sink.json {
+            let json = serde_json::to_string(item)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+            Message::Text(json)
+        } else {
+            let mut buf = Vec::new();
+            buf.serialize(item)?;
+
+            Message::Binary(buf)
+        };
+
+        Pin::new(&mut sink.messages).start_send(message)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.get_mut().messages).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.get_mut().messages).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::Arc;
+
+    use futures::join;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use calc_utils::{Credentials, Deserializer, MathRequest, Operation, Request};
+
+    use super::*;
+    use crate::auth::Authenticator;
+    use crate::limits::Limits;
+    use crate::shutdown::Shutdown;
+    use crate::worker_pool::WorkerPool;
+    use crate::{operations, EVALUATION_BUDGET, LIMITS, WORKER_QUEUE_LIMIT};
+
+    const OP_TEXT: u8 = 0x1;
+    const OP_BINARY: u8 = 0x2;
+    const OP_CLOSE: u8 = 0x8;
+    const OP_PING: u8 = 0x9;
+    const OP_PONG: u8 = 0xA;
+
+    /// Runs `client` against a server on the other end of a loopback connection, and hands
+    /// back what the server made of it
+    async fn serve_one<F, T>(client: impl FnOnce(TcpStream) -> F) -> (io::Result<()>, T)
+    where
+        F: std::future::Future<Output = T>,
+    {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Dropping it would tell the connection the server is going away
+        let shutdown = Shutdown::new();
+
+        let connection = Connection {
+            shutdown: shutdown.signal(),
+            permit: Limits::new(LIMITS)
+                .admit(IpAddr::V4(Ipv4Addr::LOCALHOST))
+                .unwrap(),
+            pool: WorkerPool::new(1, WORKER_QUEUE_LIMIT, EVALUATION_BUDGET),
+            auth: Arc::new(Authenticator::disabled()),
+            operations: Arc::new(operations::bundled().unwrap().build()),
+        };
+
+        let server = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            process_websocket(stream, Ok(connection)).await
+        };
+
+        let client = async { client(TcpStream::connect(addr).await.unwrap()).await };
+
+        join!(server, client)
+    }
+
+    /// Upgrades the connection, asking for `protocol` if there is one
+    async fn handshake(stream: &mut TcpStream, protocol: Option<&str>) {
+        let mut request = String::from(
+            "GET / HTTP/1.1\r\n\
+             Host: localhost\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+             Sec-WebSocket-Version: 13\r\n",
+        );
+
+        if let Some(protocol) = protocol {
+            request += &format!("Sec-WebSocket-Protocol: {}\r\n", protocol);
+        }
+
+        request += "\r\n";
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8];
+            stream.read_exact(&mut byte).await.unwrap();
+            response.push(byte[0]);
+        }
+
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101"), "{}", response);
+        assert!(
+            response.contains("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="),
+            "{}",
+            response
+        );
+    }
+
+    /// A frame the way a client has to send it, masked
+    fn frame(opcode: u8, fin: bool, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut buf = vec![if fin { 0x80 } else { 0 } | opcode];
+
+        match payload.len() {
+            len if len < 126 => buf.push(0x80 | len as u8),
+            len => {
+                buf.push(0x80 | 126);
+                buf.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+        }
+
+        buf.extend_from_slice(&mask);
+        buf.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        buf
+    }
+
+    fn binary<T: calc_utils::Serializable>(obj: &T) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.serialize(obj).unwrap();
+        frame(OP_BINARY, true, &payload)
+    }
+
+    fn text(json: &str) -> Vec<u8> {
+        frame(OP_TEXT, true, json.as_bytes())
+    }
+
+    /// Reads one unfragmented frame from the server
+    async fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut head = [0u8; 2];
+        stream.read_exact(&mut head).await.unwrap();
+        assert_eq!(head[0] & 0x80, 0x80, "fragmented frame");
+        assert_eq!(head[1] & 0x80, 0, "masked frame");
+
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0u8; 2];
+                stream.read_exact(&mut len).await.unwrap();
+                u16::from_be_bytes(len) as usize
+            }
+
+            127 => {
+                let mut len = [0u8; 8];
+                stream.read_exact(&mut len).await.unwrap();
+                u64::from_be_bytes(len) as usize
+            }
+
+            len => len as usize,
+        };
+
+        let mut payload = vec![0u8; len];
+        stream.read_exact(&mut payload).await.unwrap();
+
+        (head[0] & 0x0F, payload)
+    }
+
+    async fn read_binary(stream: &mut TcpStream) -> Response {
+        let (opcode, payload) = read_frame(stream).await;
+        assert_eq!(opcode, OP_BINARY);
+        Cursor::new(payload).deserialize().unwrap()
+    }
+
+    async fn read_text(stream: &mut TcpStream) -> Response {
+        let (opcode, payload) = read_frame(stream).await;
+        assert_eq!(opcode, OP_TEXT);
+        serde_json::from_slice(&payload).unwrap()
+    }
+
+    fn addition(id: u32) -> Request {
+        Request::Math(MathRequest {
+            id,
+            operation: Operation::Addition,
+            a: 1.0,
+            b: 2.0,
+        })
+    }
+
+    fn assert_sum(response: Response, id: u32) {
+        match response {
+            Response::Result(result) => {
+                assert_eq!(result.id, id);
+                assert_eq!(result.res.unwrap(), 3.0);
+            }
+
+            other => panic!("expected a result, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn binary_requests() {
+        let (served, ()) = serve_one(|mut stream| async move {
+            handshake(&mut stream, Some(BINARY_PROTOCOL)).await;
+
+            match read_binary(&mut stream).await {
+                Response::Challenge(_) => {}
+                other => panic!("expected a challenge, got {:?}", other),
+            }
+
+            stream
+                .write_all(&binary(&Credentials::Anonymous))
+                .await
+                .unwrap();
+
+            match read_binary(&mut stream).await {
+                Response::Operations(_) => {}
+                other => panic!("expected operations, got {:?}", other),
+            }
+
+            stream.write_all(&binary(&addition(1))).await.unwrap();
+            assert_sum(read_binary(&mut stream).await, 1);
+
+            stream
+                .write_all(&frame(OP_PING, true, b"still there?"))
+                .await
+                .unwrap();
+            assert_eq!(
+                read_frame(&mut stream).await,
+                (OP_PONG, b"still there?".to_vec())
+            );
+
+            // The request is still being worked on when the close arrives, but its result has
+            // to come before the server's close
+            let mut goodbye = binary(&addition(2));
+            goodbye.extend(frame(OP_CLOSE, true, &1001u16.to_be_bytes()));
+            stream.write_all(&goodbye).await.unwrap();
+
+            assert_sum(read_binary(&mut stream).await, 2);
+            assert_eq!(
+                read_frame(&mut stream).await,
+                (OP_CLOSE, 1001u16.to_be_bytes().to_vec())
+            );
+
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).await.unwrap();
+            assert!(rest.is_empty());
+        })
+        .await;
+
+        served.unwrap();
+    }
+
+    #[tokio::test]
+    async fn json_requests() {
+        let (served, ()) = serve_one(|mut stream| async move {
+            handshake(&mut stream, Some(JSON_PROTOCOL)).await;
+
+            match read_text(&mut stream).await {
+                Response::Challenge(_) => {}
+                other => panic!("expected a challenge, got {:?}", other),
+            }
+
+            stream.write_all(&text(r#""Anonymous""#)).await.unwrap();
+
+            match read_text(&mut stream).await {
+                Response::Operations(_) => {}
+                other => panic!("expected operations, got {:?}", other),
+            }
+
+            let request = r#"{"Math": {"id": 7, "operation": "Addition", "a": 1.0, "b": 2.0}}"#;
+            stream.write_all(&text(request)).await.unwrap();
+            assert_sum(read_text(&mut stream).await, 7);
+
+            stream.write_all(&frame(OP_CLOSE, true, &[])).await.unwrap();
+            assert_eq!(read_frame(&mut stream).await, (OP_CLOSE, Vec::new()));
+        })
+        .await;
+
+        served.unwrap();
+    }
+
+    #[tokio::test]
+    async fn oversized_ping() {
+        let (served, ()) = serve_one(|mut stream| async move {
+            handshake(&mut stream, None).await;
+            read_binary(&mut stream).await;
+
+            stream
+                .write_all(&frame(OP_PING, true, &[0; 126]))
+                .await
+                .unwrap();
+        })
+        .await;
+
+        assert_eq!(served.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn fragmented_ping() {
+        let (served, ()) = serve_one(|mut stream| async move {
+            handshake(&mut stream, None).await;
+            read_binary(&mut stream).await;
+
+            stream
+                .write_all(&frame(OP_PING, false, b"half"))
+                .await
+                .unwrap();
+        })
+        .await;
+
+        assert_eq!(served.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,101 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use calc_utils::CalcError;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of threads that do the actual number crunching, so a slow evaluation only ties up
+/// a worker instead of the reactor that every connection is sharing
+#[derive(Debug, Clone)]
+pub struct WorkerPool {
+    jobs: SyncSender<Job>,
+    budget: Duration,
+}
+
+/// How long a single job is allowed to run, as wall-clock time since a worker picked it up.
+/// There's no way to stop a thread from the outside, so this is cooperative: anything that loops
+/// or recurses has to `check` as it goes, and code that can't, like a plugin's, only finds out it
+/// ran over once it returns. A job that never checks is never cut short.
+#[derive(Debug)]
+pub struct Budget {
+    deadline: Instant,
+}
+
+impl WorkerPool {
+    /// Starts `workers` threads. At most `queue_limit` jobs can be waiting for a free worker, and
+    /// each job gets `budget` to run before it should give up.
+    pub fn new(workers: usize, queue_limit: usize, budget: Duration) -> WorkerPool {
+        let (jobs, queue) = mpsc::sync_channel::<Job>(queue_limit);
+        let queue = Arc::new(Mutex::new(queue));
+
+        for i in 0..workers {
+            let queue = queue.clone();
+
+            thread::Builder::new()
+                .name(format!("calc-worker-{}", i))
+                .spawn(move || run_worker(&queue))
+                .expect("failed to spawn worker thread");
+        }
+
+        WorkerPool { jobs, budget }
+    }
+
+    /// Queues up a job, or gives it right back as an overloaded error if the queue is full
+    pub fn execute<F>(&self, job: F) -> Result<(), CalcError>
+    where
+        F: FnOnce(&Budget) + Send + 'static,
+    {
+        let limit = self.budget;
+
+        // The budget only starts once a worker actually picks the job up
+        let job: Job = Box::new(move || job(&Budget::new(limit)));
+
+        match self.jobs.try_send(job) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(CalcError::Overloaded),
+            Err(TrySendError::Disconnected(_)) => {
+                Err(CalcError::Internal("worker pool has shut down".to_string()))
+            }
+        }
+    }
+}
+
+impl Budget {
+    pub fn new(limit: Duration) -> Budget {
+        Budget {
+            deadline: Instant::now() + limit,
+        }
+    }
+
+    /// Anything that loops should call this as it goes and bail out with the error once the
+    /// budget is spent. Calling it once at the end only turns a slow answer into an error, so
+    /// that's for work that's bounded anyway.
+    pub fn check(&self) -> Result<(), CalcError> {
+        if Instant::now() > self.deadline {
+            Err(CalcError::TimedOut)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn run_worker(queue: &Mutex<Receiver<Job>>) {
+    loop {
+        // Only hold the lock while waiting for a job, not while running it
+        let job = queue.lock().unwrap().recv();
+
+        match job {
+            Ok(job) => job(),
+
+            // Everyone who could send us work is gone
+            Err(_) => break,
+        }
+    }
+}
@@ -0,0 +1,28 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How many random bytes the server puts in a challenge
+pub const NONCE_LEN: usize = 32;
+
+/// Proves we know `secret` without sending it, by signing the nonce from the server's challenge
+pub fn sign_challenge(secret: &[u8], nonce: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_varkey(secret).expect("HMAC takes keys of any length");
+    mac.input(nonce);
+
+    mac.result().code().to_vec()
+}
+
+/// Checks a signature made with `sign_challenge`, in constant time so the comparison doesn't
+/// leak how much of it was right
+pub fn verify_challenge(secret: &[u8], nonce: &[u8], signature: &[u8]) -> bool {
+    let mut mac = HmacSha256::new_varkey(secret).expect("HMAC takes keys of any length");
+    mac.input(nonce);
+
+    mac.verify(signature).is_ok()
+}
@@ -0,0 +1,185 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use serde::{Deserialize, Serialize};
+
+/// A complex number, `re + im i`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    /// The distance from zero
+    pub fn abs(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    /// The angle from the positive real axis, between -π and π
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn conj(self) -> Complex {
+        Complex::new(self.re, -self.im)
+    }
+
+    pub fn to_polar(self) -> Polar {
+        Polar {
+            magnitude: self.abs(),
+            angle: self.arg(),
+        }
+    }
+
+    pub fn from_polar(polar: Polar) -> Complex {
+        Complex::new(
+            polar.magnitude * polar.angle.cos(),
+            polar.magnitude * polar.angle.sin(),
+        )
+    }
+
+    /// The principal square root, the one with a real part that's not negative. Going through
+    /// `(|re| + abs) / 2` instead of `(abs - re) / 2` keeps from losing every digit to
+    /// cancellation when `im` is tiny. The sign of a zero `im` picks the side of the cut along
+    /// the negative reals, so `sqrt(-1)` is `i` and `sqrt(-1 - 0i)` is `-i`.
+    pub fn sqrt(self) -> Complex {
+        if self.re == 0.0 && self.im == 0.0 {
+            return Complex::new(0.0, self.im);
+        }
+
+        let t = ((self.re.abs() + self.abs()) / 2.0).sqrt();
+
+        if self.re >= 0.0 {
+            Complex::new(t, self.im / (2.0 * t))
+        } else {
+            Complex::new(self.im.abs() / (2.0 * t), t.copysign(self.im))
+        }
+    }
+
+    pub fn exp(self) -> Complex {
+        Complex::from_polar(Polar {
+            magnitude: self.re.exp(),
+            angle: self.im,
+        })
+    }
+
+    /// The principal logarithm, with an imaginary part between -π and π
+    pub fn ln(self) -> Complex {
+        Complex::new(self.abs().ln(), self.arg())
+    }
+
+    /// The principal value of `self^exponent`. Zero to any power with a positive real part is
+    /// zero, which going through the logarithm would turn into a NaN.
+    pub fn powc(self, exponent: Complex) -> Complex {
+        if self.re == 0.0 && self.im == 0.0 {
+            if exponent.re == 0.0 && exponent.im == 0.0 {
+                return Complex::from(1.0);
+            }
+
+            if exponent.re > 0.0 {
+                return Complex::from(0.0);
+            }
+        }
+
+        (self.ln() * exponent).exp()
+    }
+}
+
+/// A complex number as how far it is from zero and in what direction
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Polar {
+    pub magnitude: f64,
+    pub angle: f64,
+}
+
+impl From<f64> for Complex {
+    fn from(re: f64) -> Complex {
+        Complex { re, im: 0.0 }
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+
+    /// Smith's algorithm, which scales by the bigger part of `other` first so squaring it can't
+    /// overflow when the answer itself would fit
+    fn div(self, other: Complex) -> Complex {
+        if other.re.abs() >= other.im.abs() {
+            let ratio = other.im / other.re;
+            let denom = other.re + other.im * ratio;
+
+            Complex::new(
+                (self.re + self.im * ratio) / denom,
+                (self.im - self.re * ratio) / denom,
+            )
+        } else {
+            let ratio = other.re / other.im;
+            let denom = other.re * ratio + other.im;
+
+            Complex::new(
+                (self.re * ratio + self.im) / denom,
+                (self.im * ratio - self.re) / denom,
+            )
+        }
+    }
+}
+
+impl Neg for Complex {
+    type Output = Complex;
+
+    fn neg(self) -> Complex {
+        Complex::new(-self.re, -self.im)
+    }
+}
+
+impl fmt::Display for Polar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}∠{}", self.magnitude, self.angle)
+    }
+}
+
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.im.is_sign_negative() {
+            write!(f, "{} - {}i", self.re, -self.im)
+        } else {
+            write!(f, "{} + {}i", self.re, self.im)
+        }
+    }
+}
@@ -3,10 +3,23 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
 use std::io::{self, Read};
+use std::time::Duration;
 
 use byteorder::{ReadBytesExt, LE};
 
-use crate::{MathRequest, MathResult, Operation};
+use crate::MAX_EXPR_NODES;
+use crate::{Aggregate, AggregateResult, BatchItem, BatchRequest, BatchResult, CalcError};
+use crate::{Complex, Convergence, PolynomialRequest, Progress, Request, Response, RootMethod};
+use crate::{ComplexOperation, ComplexRequest, ComplexResult, ComplexValue, Polar};
+use crate::{Computation, Credentials, IterativeRequest, MathRequest, MathResult, Operation};
+use crate::{CustomRequest, OperationInfo};
+use crate::{DerivativeRequest, Estimate, EstimateResult, IntegralRequest, RootsResult};
+use crate::{Dimensions, Matrix, MatrixOperation, MatrixRequest, MatrixResult, MatrixValue};
+use crate::{Distribution, StatisticsOperation, StatisticsRequest};
+use crate::{EvaluateRequest, Function, FunctionOperation, FunctionRequest, FunctionsResult};
+use crate::{Expr, ExpressionResult, Symbolic, SymbolicRequest};
+use crate::{Integer, IntegerOperation, IntegerRequest, IntegerResult, Overflow};
+use crate::{Quantity, QuantityResult, UnitOperation, UnitRequest};
 
 pub trait Deserializable: Sized {
     fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Self>;
@@ -20,18 +33,139 @@ pub trait Deserializer: Read + Sized {
 
 impl<T> Deserializer for T where T: Read + Sized {}
 
+/// The error for an enum tag we don't know about, probably from a newer or broken peer
+fn unknown_tag(what: &str, tag: u32) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unknown {} tag {}", what, tag),
+    )
+}
+
+impl Deserializable for u8 {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<u8> {
+        buf.read_u8()
+    }
+}
+
 impl Deserializable for u32 {
     fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<u32> {
         buf.read_u32::<LE>()
     }
 }
 
+impl Deserializable for u64 {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<u64> {
+        buf.read_u64::<LE>()
+    }
+}
+
+impl Deserializable for i64 {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<i64> {
+        buf.read_i64::<LE>()
+    }
+}
+
+impl Deserializable for i128 {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<i128> {
+        buf.read_i128::<LE>()
+    }
+}
+
 impl Deserializable for f64 {
     fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<f64> {
         buf.read_f64::<LE>()
     }
 }
 
+impl Deserializable for Duration {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Duration> {
+        Ok(Duration::from_millis(buf.deserialize()?))
+    }
+}
+
+impl Deserializable for String {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<String> {
+        let len = buf.deserialize::<u32>()? as usize;
+
+        // Don't trust the length enough to allocate it up front, the packet might be lying
+        let mut bytes = Vec::new();
+        buf.take(len as u64).read_to_end(&mut bytes)?;
+
+        if bytes.len() != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl<D: Deserializable> Deserializable for Vec<D> {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Vec<D>> {
+        let len = buf.deserialize::<u32>()? as usize;
+
+        // Same as with strings, let the items run out instead of trusting the length
+        let mut items = Vec::new();
+
+        for _ in 0..len {
+            items.push(buf.deserialize()?);
+        }
+
+        Ok(items)
+    }
+}
+
+impl<D: Deserializable, E: Deserializable> Deserializable for Result<D, E> {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Result<D, E>> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => Ok(buf.deserialize()?),
+            1 => Err(buf.deserialize()?),
+
+            tag => return Err(unknown_tag("result", tag)),
+        })
+    }
+}
+
+impl Deserializable for CalcError {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<CalcError> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => CalcError::Internal(buf.deserialize()?),
+            1 => CalcError::Overloaded,
+            2 => CalcError::TimedOut,
+            3 => CalcError::RateLimited {
+                retry_after: buf.deserialize()?,
+            },
+            4 => CalcError::TooManyConnections,
+            5 => CalcError::Unauthenticated,
+            6 => CalcError::InvalidExpression(buf.deserialize()?),
+            7 => CalcError::BatchTooLarge {
+                max: buf.deserialize()?,
+            },
+            8 => CalcError::InvalidAggregation(buf.deserialize()?),
+            9 => CalcError::DidNotConverge {
+                iterations: buf.deserialize()?,
+            },
+            10 => CalcError::InvalidArgument(buf.deserialize()?),
+
+            11 => CalcError::DimensionMismatch {
+                lhs: buf.deserialize()?,
+                rhs: buf.deserialize()?,
+            },
+
+            12 => CalcError::Singular,
+            13 => CalcError::Overflow,
+
+            14 => CalcError::IncompatibleUnits {
+                lhs: buf.deserialize()?,
+                rhs: buf.deserialize()?,
+            },
+
+            15 => CalcError::UnknownOperation(buf.deserialize()?),
+
+            tag => return Err(unknown_tag("error", tag)),
+        })
+    }
+}
+
 impl Deserializable for Operation {
     fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Operation> {
         Ok(match buf.deserialize::<u32>()? {
@@ -39,8 +173,9 @@ impl Deserializable for Operation {
             1 => Operation::Subtraction,
             2 => Operation::Multiplication,
             3 => Operation::Division,
+            4 => Operation::Power,
 
-            _ => unreachable!(),
+            tag => return Err(unknown_tag("operation", tag)),
         })
     }
 }
@@ -64,3 +199,709 @@ impl Deserializable for MathResult {
         })
     }
 }
+
+impl Deserializable for BatchItem {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<BatchItem> {
+        Ok(BatchItem {
+            operation: buf.deserialize()?,
+            a: buf.deserialize()?,
+            b: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for BatchRequest {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<BatchRequest> {
+        Ok(BatchRequest {
+            id: buf.deserialize()?,
+            items: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for BatchResult {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<BatchResult> {
+        Ok(BatchResult {
+            id: buf.deserialize()?,
+            res: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for Aggregate {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Aggregate> {
+        Ok(Aggregate {
+            count: buf.deserialize()?,
+            sum: buf.deserialize()?,
+            mean: buf.deserialize()?,
+            variance: buf.deserialize()?,
+            min: buf.deserialize()?,
+            max: buf.deserialize()?,
+            percentiles: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for AggregateResult {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<AggregateResult> {
+        Ok(AggregateResult {
+            id: buf.deserialize()?,
+            res: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for Computation {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Computation> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => Computation::Sqrt(buf.deserialize()?),
+
+            1 => Computation::Root {
+                expression: buf.deserialize()?,
+                variable: buf.deserialize()?,
+                method: buf.deserialize()?,
+                convergence: buf.deserialize()?,
+            },
+
+            tag => return Err(unknown_tag("computation", tag)),
+        })
+    }
+}
+
+impl Deserializable for RootMethod {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<RootMethod> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => RootMethod::Bisection {
+                lower: buf.deserialize()?,
+                upper: buf.deserialize()?,
+            },
+
+            1 => RootMethod::Brent {
+                lower: buf.deserialize()?,
+                upper: buf.deserialize()?,
+            },
+
+            2 => RootMethod::Newton {
+                guess: buf.deserialize()?,
+            },
+
+            tag => return Err(unknown_tag("root method", tag)),
+        })
+    }
+}
+
+impl Deserializable for Convergence {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Convergence> {
+        Ok(Convergence {
+            tolerance: buf.deserialize()?,
+            max_iterations: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for Complex {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Complex> {
+        Ok(Complex {
+            re: buf.deserialize()?,
+            im: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for Polar {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Polar> {
+        Ok(Polar {
+            magnitude: buf.deserialize()?,
+            angle: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for ComplexOperation {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<ComplexOperation> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => {
+                ComplexOperation::Binary(buf.deserialize()?, buf.deserialize()?, buf.deserialize()?)
+            }
+
+            1 => ComplexOperation::Sqrt(buf.deserialize()?),
+            2 => ComplexOperation::Exp(buf.deserialize()?),
+            3 => ComplexOperation::Ln(buf.deserialize()?),
+            4 => ComplexOperation::Abs(buf.deserialize()?),
+            5 => ComplexOperation::Arg(buf.deserialize()?),
+            6 => ComplexOperation::Conjugate(buf.deserialize()?),
+            7 => ComplexOperation::ToPolar(buf.deserialize()?),
+            8 => ComplexOperation::FromPolar(buf.deserialize()?),
+
+            tag => return Err(unknown_tag("complex operation", tag)),
+        })
+    }
+}
+
+impl Deserializable for ComplexValue {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<ComplexValue> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => ComplexValue::Real(buf.deserialize()?),
+            1 => ComplexValue::Complex(buf.deserialize()?),
+            2 => ComplexValue::Polar(buf.deserialize()?),
+
+            tag => return Err(unknown_tag("complex value", tag)),
+        })
+    }
+}
+
+impl Deserializable for ComplexRequest {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<ComplexRequest> {
+        Ok(ComplexRequest {
+            id: buf.deserialize()?,
+            operation: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for ComplexResult {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<ComplexResult> {
+        Ok(ComplexResult {
+            id: buf.deserialize()?,
+            res: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for Distribution {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Distribution> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => Distribution::Normal {
+                mean: buf.deserialize()?,
+                std_dev: buf.deserialize()?,
+            },
+
+            1 => Distribution::Binomial {
+                trials: buf.deserialize()?,
+                probability: buf.deserialize()?,
+            },
+
+            2 => Distribution::Poisson {
+                rate: buf.deserialize()?,
+            },
+            3 => Distribution::StudentT {
+                degrees_of_freedom: buf.deserialize()?,
+            },
+            4 => Distribution::ChiSquared {
+                degrees_of_freedom: buf.deserialize()?,
+            },
+
+            tag => return Err(unknown_tag("distribution", tag)),
+        })
+    }
+}
+
+impl Deserializable for StatisticsOperation {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<StatisticsOperation> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => StatisticsOperation::Pdf(buf.deserialize()?, buf.deserialize()?),
+            1 => StatisticsOperation::Cdf(buf.deserialize()?, buf.deserialize()?),
+            2 => StatisticsOperation::Quantile(buf.deserialize()?, buf.deserialize()?),
+            3 => StatisticsOperation::Factorial(buf.deserialize()?),
+            4 => StatisticsOperation::Choose(buf.deserialize()?, buf.deserialize()?),
+            5 => StatisticsOperation::Permutations(buf.deserialize()?, buf.deserialize()?),
+            6 => StatisticsOperation::Gamma(buf.deserialize()?),
+            7 => StatisticsOperation::Beta(buf.deserialize()?, buf.deserialize()?),
+
+            tag => return Err(unknown_tag("statistics operation", tag)),
+        })
+    }
+}
+
+impl Deserializable for StatisticsRequest {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<StatisticsRequest> {
+        Ok(StatisticsRequest {
+            id: buf.deserialize()?,
+            operation: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for Quantity {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Quantity> {
+        Ok(Quantity {
+            value: buf.deserialize()?,
+            unit: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for UnitOperation {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<UnitOperation> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => UnitOperation::Binary(buf.deserialize()?, buf.deserialize()?, buf.deserialize()?),
+
+            1 => UnitOperation::Convert(buf.deserialize()?, buf.deserialize()?),
+
+            tag => return Err(unknown_tag("unit operation", tag)),
+        })
+    }
+}
+
+impl Deserializable for UnitRequest {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<UnitRequest> {
+        Ok(UnitRequest {
+            id: buf.deserialize()?,
+            operation: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for QuantityResult {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<QuantityResult> {
+        Ok(QuantityResult {
+            id: buf.deserialize()?,
+            res: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for Integer {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Integer> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => Integer::I64(buf.deserialize()?),
+            1 => Integer::U64(buf.deserialize()?),
+            2 => Integer::I128(buf.deserialize()?),
+
+            tag => return Err(unknown_tag("integer", tag)),
+        })
+    }
+}
+
+impl Deserializable for Overflow {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Overflow> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => Overflow::Checked,
+            1 => Overflow::Wrapping,
+            2 => Overflow::Saturating,
+
+            tag => return Err(unknown_tag("overflow", tag)),
+        })
+    }
+}
+
+impl Deserializable for IntegerOperation {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<IntegerOperation> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => IntegerOperation::Addition,
+            1 => IntegerOperation::Subtraction,
+            2 => IntegerOperation::Multiplication,
+            3 => IntegerOperation::Division,
+            4 => IntegerOperation::Remainder,
+            5 => IntegerOperation::Power,
+            6 => IntegerOperation::Gcd,
+            7 => IntegerOperation::Lcm,
+            8 => IntegerOperation::ShiftLeft,
+            9 => IntegerOperation::ShiftRight,
+            10 => IntegerOperation::And,
+            11 => IntegerOperation::Or,
+            12 => IntegerOperation::Xor,
+
+            tag => return Err(unknown_tag("integer operation", tag)),
+        })
+    }
+}
+
+impl Deserializable for IntegerRequest {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<IntegerRequest> {
+        Ok(IntegerRequest {
+            id: buf.deserialize()?,
+            operation: buf.deserialize()?,
+            a: buf.deserialize()?,
+            b: buf.deserialize()?,
+            overflow: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for IntegerResult {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<IntegerResult> {
+        Ok(IntegerResult {
+            id: buf.deserialize()?,
+            res: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for PolynomialRequest {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<PolynomialRequest> {
+        Ok(PolynomialRequest {
+            id: buf.deserialize()?,
+            coefficients: buf.deserialize()?,
+            convergence: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for RootsResult {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<RootsResult> {
+        Ok(RootsResult {
+            id: buf.deserialize()?,
+            res: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for IntegralRequest {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<IntegralRequest> {
+        Ok(IntegralRequest {
+            id: buf.deserialize()?,
+            expression: buf.deserialize()?,
+            variable: buf.deserialize()?,
+            lower: buf.deserialize()?,
+            upper: buf.deserialize()?,
+            convergence: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for DerivativeRequest {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<DerivativeRequest> {
+        Ok(DerivativeRequest {
+            id: buf.deserialize()?,
+            expression: buf.deserialize()?,
+            variable: buf.deserialize()?,
+            at: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for Estimate {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Estimate> {
+        Ok(Estimate {
+            value: buf.deserialize()?,
+            error: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for EstimateResult {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<EstimateResult> {
+        Ok(EstimateResult {
+            id: buf.deserialize()?,
+            res: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for Expr {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Expr> {
+        expr_from(buf, &mut 0)
+    }
+}
+
+/// Reads an expression, counting its nodes as it goes so a peer can't make us recurse, or
+/// allocate, without limit
+fn expr_from<T: Read>(buf: &mut T, nodes: &mut usize) -> io::Result<Expr> {
+    *nodes += 1;
+
+    if *nodes > MAX_EXPR_NODES {
+        let msg = format!("expression has more than {} nodes", MAX_EXPR_NODES);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+    }
+
+    Ok(match buf.deserialize::<u32>()? {
+        0 => Expr::Number(buf.deserialize()?),
+        1 => Expr::Variable(buf.deserialize()?),
+        2 => Expr::Negate(Box::new(expr_from(buf, nodes)?)),
+
+        3 => {
+            let operation = buf.deserialize()?;
+            let lhs = expr_from(buf, nodes)?;
+            let rhs = expr_from(buf, nodes)?;
+
+            Expr::Binary(operation, Box::new(lhs), Box::new(rhs))
+        }
+
+        // Every argument is at least one node, so the count keeps the length honest too
+        4 => {
+            let name = buf.deserialize()?;
+            let len = buf.deserialize::<u32>()?;
+            let mut arguments = Vec::new();
+
+            for _ in 0..len {
+                arguments.push(expr_from(buf, nodes)?);
+            }
+
+            Expr::Call(name, arguments)
+        }
+
+        tag => return Err(unknown_tag("expression", tag)),
+    })
+}
+
+impl Deserializable for Function {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Function> {
+        Ok(Function {
+            name: buf.deserialize()?,
+            parameters: buf.deserialize()?,
+            body: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for EvaluateRequest {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<EvaluateRequest> {
+        Ok(EvaluateRequest {
+            id: buf.deserialize()?,
+            expression: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for CustomRequest {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<CustomRequest> {
+        Ok(CustomRequest {
+            id: buf.deserialize()?,
+            operation: buf.deserialize()?,
+            arguments: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for OperationInfo {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<OperationInfo> {
+        Ok(OperationInfo {
+            name: buf.deserialize()?,
+            arity: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for FunctionOperation {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<FunctionOperation> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => FunctionOperation::Define(buf.deserialize()?),
+            1 => FunctionOperation::Remove(buf.deserialize()?),
+            2 => FunctionOperation::List,
+
+            tag => return Err(unknown_tag("function operation", tag)),
+        })
+    }
+}
+
+impl Deserializable for FunctionRequest {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<FunctionRequest> {
+        Ok(FunctionRequest {
+            id: buf.deserialize()?,
+            operation: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for FunctionsResult {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<FunctionsResult> {
+        Ok(FunctionsResult {
+            id: buf.deserialize()?,
+            res: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for Symbolic {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Symbolic> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => Symbolic::Derivative {
+                variable: buf.deserialize()?,
+            },
+            1 => Symbolic::Simplify,
+
+            tag => return Err(unknown_tag("symbolic operation", tag)),
+        })
+    }
+}
+
+impl Deserializable for SymbolicRequest {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<SymbolicRequest> {
+        Ok(SymbolicRequest {
+            id: buf.deserialize()?,
+            expression: buf.deserialize()?,
+            operation: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for ExpressionResult {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<ExpressionResult> {
+        Ok(ExpressionResult {
+            id: buf.deserialize()?,
+            res: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for Dimensions {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Dimensions> {
+        Ok(Dimensions {
+            rows: buf.deserialize()?,
+            columns: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for Matrix {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Matrix> {
+        let matrix = Matrix {
+            rows: buf.deserialize()?,
+            columns: buf.deserialize()?,
+            data: buf.deserialize()?,
+        };
+
+        // Better to find out now than when something indexes past the end of it
+        matrix
+            .check()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        Ok(matrix)
+    }
+}
+
+impl Deserializable for MatrixOperation {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<MatrixOperation> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => MatrixOperation::Add(buf.deserialize()?, buf.deserialize()?),
+            1 => MatrixOperation::Multiply(buf.deserialize()?, buf.deserialize()?),
+            2 => MatrixOperation::Transpose(buf.deserialize()?),
+            3 => MatrixOperation::Determinant(buf.deserialize()?),
+            4 => MatrixOperation::Inverse(buf.deserialize()?),
+            5 => MatrixOperation::Solve(buf.deserialize()?, buf.deserialize()?),
+            6 => MatrixOperation::Dot(buf.deserialize()?, buf.deserialize()?),
+            7 => MatrixOperation::Cross(buf.deserialize()?, buf.deserialize()?),
+
+            tag => return Err(unknown_tag("matrix operation", tag)),
+        })
+    }
+}
+
+impl Deserializable for MatrixValue {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<MatrixValue> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => MatrixValue::Scalar(buf.deserialize()?),
+            1 => MatrixValue::Vector(buf.deserialize()?),
+            2 => MatrixValue::Matrix(buf.deserialize()?),
+
+            tag => return Err(unknown_tag("matrix value", tag)),
+        })
+    }
+}
+
+impl Deserializable for MatrixRequest {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<MatrixRequest> {
+        Ok(MatrixRequest {
+            id: buf.deserialize()?,
+            operation: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for MatrixResult {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<MatrixResult> {
+        Ok(MatrixResult {
+            id: buf.deserialize()?,
+            res: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for IterativeRequest {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<IterativeRequest> {
+        Ok(IterativeRequest {
+            id: buf.deserialize()?,
+            computation: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for Progress {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Progress> {
+        Ok(Progress {
+            id: buf.deserialize()?,
+            iteration: buf.deserialize()?,
+            approximation: buf.deserialize()?,
+        })
+    }
+}
+
+impl Deserializable for Request {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Request> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => Request::Math(buf.deserialize()?),
+            1 => Request::Batch(buf.deserialize()?),
+            2 => Request::AggregateOpen {
+                id: buf.deserialize()?,
+            },
+
+            3 => Request::AggregatePush {
+                id: buf.deserialize()?,
+                values: buf.deserialize()?,
+            },
+
+            4 => Request::AggregateFinish {
+                id: buf.deserialize()?,
+                percentiles: buf.deserialize()?,
+            },
+
+            5 => Request::Iterate(buf.deserialize()?),
+            6 => Request::PolynomialRoots(buf.deserialize()?),
+            7 => Request::Integrate(buf.deserialize()?),
+            8 => Request::Differentiate(buf.deserialize()?),
+            9 => Request::Symbolic(buf.deserialize()?),
+            10 => Request::Matrix(buf.deserialize()?),
+            11 => Request::Complex(buf.deserialize()?),
+            12 => Request::Integer(buf.deserialize()?),
+            13 => Request::Units(buf.deserialize()?),
+            14 => Request::Statistics(buf.deserialize()?),
+            15 => Request::Functions(buf.deserialize()?),
+            16 => Request::Evaluate(buf.deserialize()?),
+            17 => Request::Custom(buf.deserialize()?),
+
+            tag => return Err(unknown_tag("request", tag)),
+        })
+    }
+}
+
+impl Deserializable for Response {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Response> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => Response::Result(buf.deserialize()?),
+            1 => Response::Goodbye,
+            2 => Response::Rejected(buf.deserialize()?),
+            3 => Response::Challenge(buf.deserialize()?),
+            4 => Response::Batch(buf.deserialize()?),
+            5 => Response::Aggregate(buf.deserialize()?),
+            6 => Response::Progress(buf.deserialize()?),
+            7 => Response::Roots(buf.deserialize()?),
+            8 => Response::Estimate(buf.deserialize()?),
+            9 => Response::Expression(buf.deserialize()?),
+            10 => Response::Matrix(buf.deserialize()?),
+            11 => Response::Complex(buf.deserialize()?),
+            12 => Response::Integer(buf.deserialize()?),
+            13 => Response::Quantity(buf.deserialize()?),
+            14 => Response::Functions(buf.deserialize()?),
+            15 => Response::Operations(buf.deserialize()?),
+
+            tag => return Err(unknown_tag("response", tag)),
+        })
+    }
+}
+
+impl Deserializable for Credentials {
+    fn deserialize_from<T: Read>(buf: &mut T) -> io::Result<Credentials> {
+        Ok(match buf.deserialize::<u32>()? {
+            0 => Credentials::Anonymous,
+            1 => Credentials::Token(buf.deserialize()?),
+
+            2 => Credentials::Hmac {
+                key_id: buf.deserialize()?,
+                signature: buf.deserialize()?,
+            },
+
+            tag => return Err(unknown_tag("credentials", tag)),
+        })
+    }
+}
@@ -0,0 +1,110 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Dimensions;
+
+/// Why the server couldn't give us a result for a request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CalcError {
+    /// Something went wrong on the server while evaluating the request, the message is whatever
+    /// the server panicked with
+    Internal(String),
+
+    /// The server has too much work queued up already, try again later
+    Overloaded,
+
+    /// The evaluation ran longer than the server allows a single request to take
+    TimedOut,
+
+    /// We're sending requests faster than the server allows, it's worth trying again after
+    /// `retry_after` has passed
+    RateLimited { retry_after: Duration },
+
+    /// The server won't take any more connections, either from anyone or just from us
+    TooManyConnections,
+
+    /// The server didn't accept our credentials
+    Unauthenticated,
+
+    /// An expression couldn't be parsed, the message says what's wrong and where
+    InvalidExpression(String),
+
+    /// A batch had more operations in it than the server takes at once
+    BatchTooLarge { max: u32 },
+
+    /// An aggregation couldn't be opened or finished, the message says why
+    InvalidAggregation(String),
+
+    /// An iterative method used up all its iterations without getting within its tolerance
+    DidNotConverge { iterations: u32 },
+
+    /// Something in the request makes no sense for what it asks for, like a bracket that
+    /// doesn't hold a root
+    InvalidArgument(String),
+
+    /// Two matrices or vectors that don't fit together for what was asked of them. Vectors count
+    /// as having a single column.
+    DimensionMismatch { lhs: Dimensions, rhs: Dimensions },
+
+    /// A matrix that can't be inverted, or a linear system without exactly one solution
+    Singular,
+
+    /// An integer result didn't fit in its type, and the request asked for that to be an error
+    Overflow,
+
+    /// Two quantities don't measure the same thing, like metres and seconds, so they can't be
+    /// added together or converted into each other
+    IncompatibleUnits { lhs: String, rhs: String },
+
+    /// The server has no operation by this name. It tells us which ones it does have when we
+    /// connect.
+    UnknownOperation(String),
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CalcError::Internal(msg) => write!(f, "internal server error: {}", msg),
+            CalcError::Overloaded => write!(f, "server is overloaded"),
+            CalcError::TimedOut => write!(f, "evaluation exceeded its time budget"),
+
+            CalcError::RateLimited { retry_after } => {
+                write!(f, "rate limited, retry after {:?}", retry_after)
+            }
+
+            CalcError::TooManyConnections => write!(f, "too many connections"),
+            CalcError::Unauthenticated => write!(f, "authentication failed"),
+            CalcError::InvalidExpression(msg) => write!(f, "invalid expression: {}", msg),
+            CalcError::BatchTooLarge { max } => write!(f, "batch has more than {} operations", max),
+            CalcError::InvalidAggregation(msg) => write!(f, "invalid aggregation: {}", msg),
+
+            CalcError::DidNotConverge { iterations } => {
+                write!(f, "did not converge after {} iterations", iterations)
+            }
+
+            CalcError::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+
+            CalcError::DimensionMismatch { lhs, rhs } => {
+                write!(f, "dimensions {} and {} don't match", lhs, rhs)
+            }
+
+            CalcError::Singular => write!(f, "matrix is singular"),
+            CalcError::Overflow => write!(f, "integer overflow"),
+
+            CalcError::IncompatibleUnits { lhs, rhs } => {
+                write!(f, "units {} and {} are incompatible", lhs, rhs)
+            }
+
+            CalcError::UnknownOperation(name) => write!(f, "unknown operation `{}`", name),
+        }
+    }
+}
+
+impl Error for CalcError {}
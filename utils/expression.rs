@@ -0,0 +1,542 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{CalcError, Operation};
+
+/// How deep parentheses and unary signs can nest before we refuse, so a silly input can't blow
+/// the stack
+const MAX_DEPTH: usize = 256;
+
+/// Most numbers and operators an expression can have. Long chains like `1 + 1 + 1 ...` make for
+/// deep trees too, which we don't want to walk or drop recursively without a limit. Expressions
+/// that come in some other way than being parsed are held to this too.
+pub const MAX_EXPR_NODES: usize = 4096;
+
+/// An arithmetic expression like `2 * (x + -4)^2`, parsed into a tree
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Expr {
+    Number(f64),
+
+    /// A name like `x`, which gets its value from whoever evaluates the expression
+    Variable(String),
+
+    Negate(Box<Expr>),
+    Binary(Operation, Box<Expr>, Box<Expr>),
+
+    /// A call like `f(x, 2)`, to either a `Builtin` or a function whoever evaluates the
+    /// expression knows about
+    Call(String, Vec<Expr>),
+}
+
+/// The functions every expression can call without defining them first. They all take a single
+/// argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    Sqrt,
+    Exp,
+    Ln,
+    Sin,
+    Cos,
+    Tan,
+    Abs,
+}
+
+/// A function like `f(x, y) = x^2 + y`, for expressions to call by name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Function {
+    pub name: String,
+    pub parameters: Vec<String>,
+    pub body: Expr,
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    depth: usize,
+    nodes: usize,
+}
+
+impl Expr {
+    /// Parses the usual infix notation, with `*` and `/` binding tighter than `+` and `-`, and
+    /// everything on the same level going left to right. `^` binds tightest of all and goes right
+    /// to left, so `-2^3^2` is `-(2^(3^2))`.
+    pub fn parse(input: &str) -> Result<Expr, CalcError> {
+        let mut parser = Parser {
+            input,
+            chars: input.char_indices().peekable(),
+            depth: 0,
+            nodes: 0,
+        };
+
+        let expr = parser.sum()?;
+
+        match parser.peek() {
+            None => Ok(expr),
+            Some((pos, c)) => Err(parser.error(pos, &format!("unexpected `{}`", c))),
+        }
+    }
+}
+
+impl Builtin {
+    pub fn lookup(name: &str) -> Option<Builtin> {
+        Some(match name {
+            "sqrt" => Builtin::Sqrt,
+            "exp" => Builtin::Exp,
+            "ln" => Builtin::Ln,
+            "sin" => Builtin::Sin,
+            "cos" => Builtin::Cos,
+            "tan" => Builtin::Tan,
+            "abs" => Builtin::Abs,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Sqrt => "sqrt",
+            Builtin::Exp => "exp",
+            Builtin::Ln => "ln",
+            Builtin::Sin => "sin",
+            Builtin::Cos => "cos",
+            Builtin::Tan => "tan",
+            Builtin::Abs => "abs",
+        }
+    }
+
+    pub fn apply(self, x: f64) -> f64 {
+        match self {
+            Builtin::Sqrt => x.sqrt(),
+            Builtin::Exp => x.exp(),
+            Builtin::Ln => x.ln(),
+            Builtin::Sin => x.sin(),
+            Builtin::Cos => x.cos(),
+            Builtin::Tan => x.tan(),
+            Builtin::Abs => x.abs(),
+        }
+    }
+}
+
+impl Function {
+    /// Parses a definition like `f(x, y) = x^2 + y`. The body can call functions that aren't
+    /// defined yet, including this one, but it can only use the function's own parameters and
+    /// the name can't be taken by a `Builtin`.
+    pub fn parse(input: &str) -> Result<Function, CalcError> {
+        let mut parser = Parser {
+            input,
+            chars: input.char_indices().peekable(),
+            depth: 0,
+            nodes: 0,
+        };
+
+        let head = parser.sum()?;
+
+        match parser.peek() {
+            Some((_, '=')) => {
+                parser.chars.next();
+            }
+
+            Some((pos, _)) => return Err(parser.error(pos, "expected `=`")),
+            None => return Err(parser.error(input.len(), "expected `=`")),
+        }
+
+        let body = parser.sum()?;
+
+        if let Some((pos, c)) = parser.peek() {
+            return Err(parser.error(pos, &format!("unexpected `{}`", c)));
+        }
+
+        let (name, arguments) = match head {
+            Expr::Call(name, arguments) => (name, arguments),
+            head => return Err(invalid(format!("`{}` doesn't look like `f(x)`", head))),
+        };
+
+        if Builtin::lookup(&name).is_some() {
+            return Err(invalid(format!("`{}` is a built-in function", name)));
+        }
+
+        let mut parameters = Vec::with_capacity(arguments.len());
+
+        for argument in arguments {
+            match argument {
+                Expr::Variable(parameter) if !parameters.contains(&parameter) => {
+                    parameters.push(parameter)
+                }
+
+                Expr::Variable(parameter) => {
+                    return Err(invalid(format!("`{}` is a parameter twice", parameter)));
+                }
+
+                argument => return Err(invalid(format!("`{}` is not a parameter", argument))),
+            }
+        }
+
+        let function = Function {
+            name,
+            parameters,
+            body,
+        };
+        function.check_variables(&function.body)?;
+
+        Ok(function)
+    }
+
+    /// Every variable in `expr` has to be one of the parameters, since there's nowhere else for
+    /// it to get a value from
+    fn check_variables(&self, expr: &Expr) -> Result<(), CalcError> {
+        match expr {
+            Expr::Number(_) => Ok(()),
+
+            Expr::Variable(name) if self.parameters.contains(name) => Ok(()),
+            Expr::Variable(name) => Err(invalid(format!(
+                "`{}` is not a parameter of `{}`",
+                name, self.name
+            ))),
+
+            Expr::Negate(operand) => self.check_variables(operand),
+
+            Expr::Binary(_, lhs, rhs) => {
+                self.check_variables(lhs)?;
+                self.check_variables(rhs)
+            }
+
+            Expr::Call(_, arguments) => arguments
+                .iter()
+                .try_for_each(|argument| self.check_variables(argument)),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}({}) = {}",
+            self.name,
+            self.parameters.join(", "),
+            self.body
+        )
+    }
+}
+
+/// Writes the expression back out the way `Expr::parse` reads it, with only the parentheses it
+/// needs
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, Precedence::Sum)
+    }
+}
+
+/// How tightly each kind of expression binds, from loosest to tightest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    Sum,
+    Product,
+    Unary,
+    Power,
+    Primary,
+}
+
+impl Expr {
+    fn precedence(&self) -> Precedence {
+        match self {
+            // A negative number reads the same as a negated one
+            Expr::Number(num) if num.is_sign_negative() => Precedence::Unary,
+            Expr::Number(_) | Expr::Variable(_) | Expr::Call(..) => Precedence::Primary,
+            Expr::Negate(_) => Precedence::Unary,
+
+            Expr::Binary(operation, ..) => match operation {
+                Operation::Addition | Operation::Subtraction => Precedence::Sum,
+                Operation::Multiplication | Operation::Division => Precedence::Product,
+                Operation::Power => Precedence::Power,
+            },
+        }
+    }
+
+    /// Writes the expression where something binding at least as tightly as `at` is expected,
+    /// putting it in parentheses if it doesn't
+    fn write(&self, f: &mut fmt::Formatter, at: Precedence) -> fmt::Result {
+        if self.precedence() < at {
+            write!(f, "(")?;
+            self.write(f, Precedence::Sum)?;
+            return write!(f, ")");
+        }
+
+        match self {
+            Expr::Number(num) => write!(f, "{}", num),
+            Expr::Variable(name) => write!(f, "{}", name),
+
+            Expr::Call(name, arguments) => {
+                write!(f, "{}(", name)?;
+
+                for (i, argument) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    argument.write(f, Precedence::Sum)?;
+                }
+
+                write!(f, ")")
+            }
+
+            Expr::Negate(operand) => {
+                write!(f, "-")?;
+                operand.write(f, Precedence::Unary)
+            }
+
+            // The base can't have a sign of its own without parentheses, but the exponent can
+            Expr::Binary(Operation::Power, base, exponent) => {
+                base.write(f, Precedence::Primary)?;
+                write!(f, "^")?;
+                exponent.write(f, Precedence::Unary)
+            }
+
+            // Everything else goes left to right, so the right side has to bind tighter to keep
+            // `a - (b - c)` from coming out as `a - b - c`
+            Expr::Binary(operation, lhs, rhs) => {
+                let (left, right) = match operation {
+                    Operation::Addition | Operation::Subtraction => {
+                        (Precedence::Sum, Precedence::Product)
+                    }
+
+                    _ => (Precedence::Product, Precedence::Unary),
+                };
+
+                lhs.write(f, left)?;
+                write!(f, " {} ", operation)?;
+                rhs.write(f, right)
+            }
+        }
+    }
+}
+
+impl<'a> Parser<'a> {
+    /// The next character that isn't whitespace, without taking it
+    fn peek(&mut self) -> Option<(usize, char)> {
+        while let Some(&(_, c)) = self.chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+
+            self.chars.next();
+        }
+
+        self.chars.peek().copied()
+    }
+
+    /// Takes the next character if it's one of `ops`
+    fn next_if(&mut self, ops: &[char]) -> Option<char> {
+        match self.peek() {
+            Some((_, c)) if ops.contains(&c) => {
+                self.chars.next();
+                Some(c)
+            }
+
+            _ => None,
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expr, CalcError> {
+        let mut expr = self.product()?;
+
+        while let Some(c) = self.next_if(&['+', '-']) {
+            let operation = if c == '+' {
+                Operation::Addition
+            } else {
+                Operation::Subtraction
+            };
+            let rhs = self.product()?;
+
+            expr = self.node(Expr::Binary(operation, Box::new(expr), Box::new(rhs)))?;
+        }
+
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, CalcError> {
+        let mut expr = self.unary()?;
+
+        while let Some(c) = self.next_if(&['*', '/']) {
+            let operation = if c == '*' {
+                Operation::Multiplication
+            } else {
+                Operation::Division
+            };
+            let rhs = self.unary()?;
+
+            expr = self.node(Expr::Binary(operation, Box::new(expr), Box::new(rhs)))?;
+        }
+
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, CalcError> {
+        match self.next_if(&['-', '+']) {
+            Some(c) => {
+                let operand = self.nested(Parser::unary)?;
+
+                if c == '-' {
+                    self.node(Expr::Negate(Box::new(operand)))
+                } else {
+                    Ok(operand)
+                }
+            }
+
+            None => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Result<Expr, CalcError> {
+        let base = self.primary()?;
+
+        match self.next_if(&['^']) {
+            Some(_) => {
+                // The exponent can have a sign of its own, like in `2^-1`
+                let exponent = self.nested(Parser::unary)?;
+                let expr = Expr::Binary(Operation::Power, Box::new(base), Box::new(exponent));
+
+                self.node(expr)
+            }
+
+            None => Ok(base),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, CalcError> {
+        match self.peek() {
+            Some((_, '(')) => {
+                self.chars.next();
+                let expr = self.nested(Parser::sum)?;
+
+                match self.peek() {
+                    Some((_, ')')) => {
+                        self.chars.next();
+                        Ok(expr)
+                    }
+
+                    Some((pos, _)) => Err(self.error(pos, "expected `)`")),
+                    None => Err(self.error(self.input.len(), "expected `)`")),
+                }
+            }
+
+            Some((pos, c)) if c.is_ascii_digit() || c == '.' => self.number(pos),
+            Some((pos, c)) if c.is_ascii_alphabetic() || c == '_' => self.name(pos),
+            Some((pos, c)) => Err(self.error(pos, &format!("unexpected `{}`", c))),
+            None => Err(self.error(self.input.len(), "expected a number")),
+        }
+    }
+
+    /// Reads a number like `12`, `0.5`, `.5` or `6.02e23`
+    fn number(&mut self, start: usize) -> Result<Expr, CalcError> {
+        let mut end = start;
+        let mut prev = None;
+
+        while let Some(&(pos, c)) = self.chars.peek() {
+            let exponent_sign = (c == '-' || c == '+') && (prev == Some('e') || prev == Some('E'));
+
+            if !(c.is_ascii_alphanumeric() || c == '.' || exponent_sign) {
+                break;
+            }
+
+            self.chars.next();
+            end = pos + c.len_utf8();
+            prev = Some(c);
+        }
+
+        let text = &self.input[start..end];
+
+        match text.parse() {
+            Ok(num) => self.node(Expr::Number(num)),
+            Err(_) => Err(self.error(start, &format!("`{}` is not a number", text))),
+        }
+    }
+
+    /// Reads a variable like `x` or `rate_2`, or a call like `f(x, 2)` if it's followed by
+    /// parentheses
+    fn name(&mut self, start: usize) -> Result<Expr, CalcError> {
+        let mut end = start;
+
+        while let Some(&(pos, c)) = self.chars.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+
+            self.chars.next();
+            end = pos + c.len_utf8();
+        }
+
+        let name = self.input[start..end].to_string();
+
+        if self.next_if(&['(']).is_none() {
+            return self.node(Expr::Variable(name));
+        }
+
+        let mut arguments = Vec::new();
+
+        if self.next_if(&[')']).is_none() {
+            loop {
+                arguments.push(self.nested(Parser::sum)?);
+
+                match self.peek() {
+                    Some((_, ',')) => {
+                        self.chars.next();
+                    }
+
+                    Some((_, ')')) => {
+                        self.chars.next();
+                        break;
+                    }
+
+                    Some((pos, _)) => return Err(self.error(pos, "expected `,` or `)`")),
+                    None => return Err(self.error(self.input.len(), "expected `)`")),
+                }
+            }
+        }
+
+        self.node(Expr::Call(name, arguments))
+    }
+
+    /// Runs `parse` one level deeper, as long as we're not too deep already
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Expr, CalcError>,
+    ) -> Result<Expr, CalcError> {
+        if self.depth >= MAX_DEPTH {
+            let pos = self.peek().map_or(self.input.len(), |(pos, _)| pos);
+            return Err(self.error(pos, "expression is nested too deeply"));
+        }
+
+        self.depth += 1;
+        let res = parse(self);
+        self.depth -= 1;
+
+        res
+    }
+
+    /// Counts another node towards the limit
+    fn node(&mut self, expr: Expr) -> Result<Expr, CalcError> {
+        self.nodes += 1;
+
+        if self.nodes > MAX_EXPR_NODES {
+            let pos = self.peek().map_or(self.input.len(), |(pos, _)| pos);
+            return Err(self.error(pos, "expression is too long"));
+        }
+
+        Ok(expr)
+    }
+
+    fn error(&self, pos: usize, msg: &str) -> CalcError {
+        CalcError::InvalidExpression(format!("{} at position {}", msg, pos))
+    }
+}
+
+fn invalid(msg: String) -> CalcError {
+    CalcError::InvalidExpression(msg)
+}
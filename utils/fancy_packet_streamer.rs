@@ -27,4 +27,4 @@ async fn get_packets<A: AsyncReadExt + Unpin>(stream: &mut A) -> Vec<u8> {
     }
 }
 */
-// the fork that has async_stream updated to .await so its broken until the update is out
\ No newline at end of file
+// the fork that has async_stream updated to .await so its broken until the update is out
@@ -0,0 +1,43 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::io::{self, Cursor};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+
+use crate::deserialize::{Deserializable, Deserializer};
+
+/// One message from the other end of a connection, however it was framed on the wire. We don't
+/// know what's in it until we decode it as whatever we expect them to send next.
+pub trait Frame {
+    fn decode<D: Deserializable + DeserializeOwned>(self) -> io::Result<D>;
+}
+
+/// How long a frame can be, shared between whatever reads the frames and whoever decides how
+/// much to trust the other end. Lowering it only affects frames we haven't started reading yet.
+#[derive(Debug, Clone)]
+pub struct FrameLimit(Arc<AtomicUsize>);
+
+impl FrameLimit {
+    pub fn new(max_len: usize) -> FrameLimit {
+        FrameLimit(Arc::new(AtomicUsize::new(max_len)))
+    }
+
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, max_len: usize) {
+        self.0.store(max_len, Ordering::Relaxed);
+    }
+}
+
+/// A packet from a `PacketStreamer`
+impl Frame for Vec<u8> {
+    fn decode<D: Deserializable + DeserializeOwned>(self) -> io::Result<D> {
+        Cursor::new(self).deserialize()
+    }
+}
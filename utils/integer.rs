@@ -0,0 +1,55 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// An integer, along with which type it is. Both sides of an `IntegerRequest` have to be the
+/// same type, and that's the type the result comes back as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Integer {
+    I64(i64),
+    U64(u64),
+    I128(i128),
+}
+
+impl Integer {
+    /// The name of the type, the way Rust spells it
+    pub fn type_name(self) -> &'static str {
+        match self {
+            Integer::I64(_) => "i64",
+            Integer::U64(_) => "u64",
+            Integer::I128(_) => "i128",
+        }
+    }
+}
+
+impl From<i64> for Integer {
+    fn from(num: i64) -> Integer {
+        Integer::I64(num)
+    }
+}
+
+impl From<u64> for Integer {
+    fn from(num: u64) -> Integer {
+        Integer::U64(num)
+    }
+}
+
+impl From<i128> for Integer {
+    fn from(num: i128) -> Integer {
+        Integer::I128(num)
+    }
+}
+
+impl fmt::Display for Integer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Integer::I64(num) => write!(f, "{}", num),
+            Integer::U64(num) => write!(f, "{}", num),
+            Integer::I128(num) => write!(f, "{}", num),
+        }
+    }
+}
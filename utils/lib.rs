@@ -4,32 +4,62 @@
 
 use std::fmt;
 
+use serde::{Deserialize, Serialize};
+
+pub use crate::auth::{sign_challenge, verify_challenge, NONCE_LEN};
+pub use crate::complex::{Complex, Polar};
 pub use crate::deserialize::{Deserializable, Deserializer};
+pub use crate::error::CalcError;
+pub use crate::expression::{Builtin, Expr, Function, MAX_EXPR_NODES};
+pub use crate::frame::{Frame, FrameLimit};
+pub use crate::integer::Integer;
+pub use crate::matrix::{Dimensions, Matrix, MAX_MATRIX_LEN};
 pub use crate::serialize::{Serializable, Serializer};
 
-pub use crate::packet_streamer::PacketStreamer;
 pub use crate::packet_sink::PacketSink;
+pub use crate::packet_streamer::{PacketStreamer, MAX_FRAME_LEN};
+
+pub use crate::plugin::{PluginOperation, PluginTable, PLUGIN_ABI_VERSION, PLUGIN_ENTRY_POINT};
 
-pub use crate::sereal_streamer::SerealStreamer;
 pub use crate::sereal_sink::SerealSink;
+pub use crate::sereal_streamer::SerealStreamer;
+
+pub use crate::units::{BaseUnits, Quantity, Unit};
 
+pub use crate::tls::{accept_tls, client_tls_config, connect_tls, server_tls_config, TlsStream};
+pub use rustls::{ClientConfig, ServerConfig};
+
+pub use crate::websocket::{accept_websocket, Message, WebSocket};
+
+mod auth;
+mod complex;
 mod deserialize;
-mod serialize;
+mod error;
+mod expression;
 mod fancy_packet_streamer;
-mod packet_streamer;
+mod frame;
+mod integer;
+mod matrix;
 mod packet_sink;
-mod sereal_streamer;
+mod packet_streamer;
+mod plugin;
 mod sereal_sink;
+mod sereal_streamer;
+mod serialize;
+mod tls;
+mod units;
+mod websocket;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Operation {
     Addition,
     Subtraction,
     Multiplication,
     Division,
+    Power,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MathRequest {
     pub id: u32,
     pub operation: Operation,
@@ -37,10 +67,563 @@ pub struct MathRequest {
     pub b: f64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct MathResult {
     pub id: u32,
-    pub res: f64,
+    pub res: Result<f64, CalcError>,
+}
+
+/// One operation in a batch. It doesn't need an id of its own, its place in the batch says which
+/// result is its.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchItem {
+    pub operation: Operation,
+    pub a: f64,
+    pub b: f64,
+}
+
+/// Lots of operations sent in one frame and answered with one `BatchResult`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchRequest {
+    pub id: u32,
+    pub items: Vec<BatchItem>,
+}
+
+/// A result for every item of a batch in the same order as the items, or a single error when the
+/// batch as a whole got turned away
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub id: u32,
+    pub res: Result<Vec<Result<f64, CalcError>>, CalcError>,
+}
+
+/// Everything we know about the numbers pushed to an aggregation once it's finished
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Aggregate {
+    pub count: u64,
+    pub sum: f64,
+
+    /// NaN when nothing was pushed, same as `min` and `max`
+    pub mean: f64,
+
+    /// The sample variance, NaN with fewer than two numbers
+    pub variance: f64,
+
+    pub min: f64,
+    pub max: f64,
+
+    /// Estimates for each of the percentiles asked for when finishing, in the same order
+    pub percentiles: Vec<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AggregateResult {
+    pub id: u32,
+    pub res: Result<Aggregate, CalcError>,
+}
+
+/// Something the server works out a step at a time, telling us how it's going along the way
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Computation {
+    /// The square root, with Newton's method
+    Sqrt(f64),
+
+    /// A value of `variable` that makes `expression` zero
+    Root {
+        expression: String,
+        variable: String,
+        method: RootMethod,
+        convergence: Convergence,
+    },
+}
+
+/// How to look for a root, and where
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RootMethod {
+    /// Halves the bracket until it's small enough. Slow, but it can't miss when the expression
+    /// has opposite signs at the two ends.
+    Bisection { lower: f64, upper: f64 },
+
+    /// Needs the same kind of bracket as bisection, but gets there a lot faster on smooth
+    /// expressions
+    Brent { lower: f64, upper: f64 },
+
+    /// Follows the slope from `guess`. Fastest of all when it works, but it can wander off
+    /// without a good guess.
+    Newton { guess: f64 },
+}
+
+/// When an iterative method can stop, either because it got close enough or because it's not
+/// getting anywhere
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Convergence {
+    pub tolerance: f64,
+    pub max_iterations: u32,
+}
+
+/// All the roots of a polynomial, complex ones included
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolynomialRequest {
+    pub id: u32,
+
+    /// From the highest power down to the constant, so `[1, 0, -2]` is `x^2 - 2`
+    pub coefficients: Vec<f64>,
+
+    pub convergence: Convergence,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RootsResult {
+    pub id: u32,
+    pub res: Result<Vec<Complex>, CalcError>,
+}
+
+/// The definite integral of `expression` over `variable` going from `lower` to `upper`. The
+/// interval keeps getting split up until the estimated error is within the tolerance, relative to
+/// the integral itself once that's bigger than one, and `max_iterations` limits how many pieces
+/// it can be split into.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntegralRequest {
+    pub id: u32,
+    pub expression: String,
+    pub variable: String,
+    pub lower: f64,
+    pub upper: f64,
+    pub convergence: Convergence,
+}
+
+/// The derivative of `expression` with respect to `variable`, at `at`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DerivativeRequest {
+    pub id: u32,
+    pub expression: String,
+    pub variable: String,
+    pub at: f64,
+}
+
+/// A number we could only approximate, along with about how far off it might be
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Estimate {
+    pub value: f64,
+    pub error: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EstimateResult {
+    pub id: u32,
+    pub res: Result<Estimate, CalcError>,
+}
+
+/// Something to do with an expression itself, rather than with its value
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Symbolic {
+    /// The derivative with respect to `variable`, simplified
+    Derivative { variable: String },
+
+    /// Folds constants, drops things like `+ 0` and `* 1`, and collects like terms
+    Simplify,
+}
+
+/// Something to work out with complex numbers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ComplexOperation {
+    /// Any of the operations we do on real numbers
+    Binary(Operation, Complex, Complex),
+
+    Sqrt(Complex),
+    Exp(Complex),
+    Ln(Complex),
+    Abs(Complex),
+    Arg(Complex),
+    Conjugate(Complex),
+    ToPolar(Complex),
+    FromPolar(Polar),
+}
+
+/// What a `ComplexOperation` comes out as. `Abs` and `Arg` are always real, and `ToPolar` is the
+/// only one that gives back a `Polar`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ComplexValue {
+    Real(f64),
+    Complex(Complex),
+    Polar(Polar),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComplexRequest {
+    pub id: u32,
+    pub operation: ComplexOperation,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ComplexResult {
+    pub id: u32,
+    pub res: Result<ComplexValue, CalcError>,
+}
+
+/// A probability distribution along with its parameters
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Distribution {
+    Normal { mean: f64, std_dev: f64 },
+    Binomial { trials: u64, probability: f64 },
+    Poisson { rate: f64 },
+    StudentT { degrees_of_freedom: f64 },
+    ChiSquared { degrees_of_freedom: f64 },
+}
+
+/// Probabilities, counting, and the special functions they're built on. These all come back as
+/// a single number. The distribution functions are good to about 1e-14 for parameters up to a
+/// few hundred, and lose about a digit for every tenfold past that.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum StatisticsOperation {
+    /// The density at `x`, or for discrete distributions the probability of exactly `x`
+    Pdf(Distribution, f64),
+
+    /// The probability of at most `x`
+    Cdf(Distribution, f64),
+
+    /// The smallest `x` with a cdf of at least the given probability
+    Quantile(Distribution, f64),
+
+    /// Exact up to 22!, which is as far as a float holds every digit
+    Factorial(u64),
+
+    /// Ways to pick `k` of `n` things, ignoring order and then counting it
+    Choose(u64, u64),
+    Permutations(u64, u64),
+
+    Gamma(f64),
+    Beta(f64, f64),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatisticsRequest {
+    pub id: u32,
+    pub operation: StatisticsOperation,
+}
+
+/// Something to work out with quantities that have units
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum UnitOperation {
+    /// Adding and subtracting need both sides to measure the same thing, and give their answer in
+    /// the units of the left side. Everything else gives its answer in SI base units, and powers
+    /// need a plain number on the right.
+    Binary(Operation, Quantity, Quantity),
+
+    /// Converts a quantity into other units that measure the same thing
+    Convert(Quantity, String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnitRequest {
+    pub id: u32,
+    pub operation: UnitOperation,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QuantityResult {
+    pub id: u32,
+    pub res: Result<Quantity, CalcError>,
+}
+
+/// What to do when the answer to an `IntegerRequest` doesn't fit in its type
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Overflow {
+    /// Fail with `CalcError::Overflow`
+    Checked,
+
+    /// Keep whatever bits fit, like two's complement hardware does
+    Wrapping,
+
+    /// Stop at the biggest or smallest number the type has
+    Saturating,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum IntegerOperation {
+    Addition,
+    Subtraction,
+    Multiplication,
+
+    /// Rounds towards zero, like Rust's `/`
+    Division,
+
+    /// Takes the sign of the left side, like Rust's `%`
+    Remainder,
+
+    /// The right side can't be negative
+    Power,
+
+    /// Both are never negative, and zero when both sides are zero
+    Gcd,
+    Lcm,
+
+    /// Shifting left is the same as multiplying by a power of two, so it overflows the same way.
+    /// Shifting right never overflows, and keeps the sign of signed types.
+    ShiftLeft,
+    ShiftRight,
+
+    And,
+    Or,
+    Xor,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntegerRequest {
+    pub id: u32,
+    pub operation: IntegerOperation,
+    pub a: Integer,
+    pub b: Integer,
+    pub overflow: Overflow,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IntegerResult {
+    pub id: u32,
+    pub res: Result<Integer, CalcError>,
+}
+
+/// Something to work out with matrices and vectors
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MatrixOperation {
+    Add(Matrix, Matrix),
+    Multiply(Matrix, Matrix),
+    Transpose(Matrix),
+    Determinant(Matrix),
+    Inverse(Matrix),
+
+    /// The `x` that makes `A x = b`, for a square `A`
+    Solve(Matrix, Vec<f64>),
+
+    Dot(Vec<f64>, Vec<f64>),
+
+    /// Only for vectors with three numbers each
+    Cross(Vec<f64>, Vec<f64>),
+}
+
+/// What a `MatrixOperation` comes out as
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MatrixValue {
+    Scalar(f64),
+    Vector(Vec<f64>),
+    Matrix(Matrix),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatrixRequest {
+    pub id: u32,
+    pub operation: MatrixOperation,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MatrixResult {
+    pub id: u32,
+    pub res: Result<MatrixValue, CalcError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SymbolicRequest {
+    pub id: u32,
+    pub expression: String,
+    pub operation: Symbolic,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExpressionResult {
+    pub id: u32,
+    pub res: Result<Expr, CalcError>,
+}
+
+/// Works out an expression, which can call any of the functions defined on the same connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvaluateRequest {
+    pub id: u32,
+    pub expression: String,
+}
+
+/// Changes or looks at the functions defined on a connection. They're only there for that
+/// connection, and go away with it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FunctionOperation {
+    /// Defines a function like `f(x, y) = x^2 + y`, replacing any other function with the same
+    /// name. Answered with the function as it was parsed.
+    Define(String),
+
+    /// Answered with the function that got removed
+    Remove(String),
+
+    /// Answered with every function there is, ordered by name
+    List,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionRequest {
+    pub id: u32,
+    pub operation: FunctionOperation,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FunctionsResult {
+    pub id: u32,
+    pub res: Result<Vec<Function>, CalcError>,
+}
+
+/// Asks for one of the operations the server has registered on top of the built-in ones. Which
+/// ones it has are advertised with `Response::Operations` as soon as we're let in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomRequest {
+    pub id: u32,
+    pub operation: String,
+    pub arguments: Vec<f64>,
+}
+
+/// An operation a client can ask for with a `CustomRequest`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OperationInfo {
+    pub name: String,
+    pub arity: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IterativeRequest {
+    pub id: u32,
+    pub computation: Computation,
+}
+
+/// How far along an iterative computation is. Any number of these can arrive before its result.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Progress {
+    pub id: u32,
+    pub iteration: u32,
+    pub approximation: f64,
+}
+
+/// Everything a client can send once it has answered the challenge
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    Math(MathRequest),
+    Batch(BatchRequest),
+
+    /// Starts an aggregation. Numbers get pushed to it in as many chunks as we like, and only
+    /// finishing it gets an answer, unless it couldn't be opened in the first place.
+    AggregateOpen {
+        id: u32,
+    },
+
+    AggregatePush {
+        id: u32,
+        values: Vec<f64>,
+    },
+
+    /// Ends an aggregation and asks for its `Aggregate`, with percentiles from 0 to 100
+    AggregateFinish {
+        id: u32,
+        percentiles: Vec<f64>,
+    },
+
+    /// Answered with any number of `Response::Progress` and then a `Response::Result`
+    Iterate(IterativeRequest),
+
+    PolynomialRoots(PolynomialRequest),
+
+    /// Both answered with a `Response::Estimate`
+    Integrate(IntegralRequest),
+    Differentiate(DerivativeRequest),
+
+    /// Answered with a `Response::Expression`
+    Symbolic(SymbolicRequest),
+
+    /// Answered with a `Response::Matrix`
+    Matrix(MatrixRequest),
+
+    /// Answered with a `Response::Complex`
+    Complex(ComplexRequest),
+
+    /// Answered with a `Response::Integer`
+    Integer(IntegerRequest),
+
+    /// Answered with a `Response::Quantity`
+    Units(UnitRequest),
+
+    /// Answered with a `Response::Result`
+    Statistics(StatisticsRequest),
+
+    /// Answered with a `Response::Functions`
+    Functions(FunctionRequest),
+
+    /// Answered with a `Response::Result`
+    Evaluate(EvaluateRequest),
+
+    /// Answered with a `Response::Result`
+    Custom(CustomRequest),
+}
+
+/// Everything the server can send back down a connection
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Response {
+    Result(MathResult),
+
+    /// The answer to a `Request::Batch`
+    Batch(BatchResult),
+
+    /// The answer to a `Request::AggregateFinish`, or to a `Request::AggregateOpen` that failed
+    Aggregate(AggregateResult),
+
+    /// Where a `Request::Iterate` has got to so far
+    Progress(Progress),
+
+    /// The answer to a `Request::PolynomialRoots`
+    Roots(RootsResult),
+
+    /// The answer to a `Request::Integrate` or `Request::Differentiate`
+    Estimate(EstimateResult),
+
+    /// The answer to a `Request::Symbolic`
+    Expression(ExpressionResult),
+
+    /// The answer to a `Request::Matrix`
+    Matrix(MatrixResult),
+
+    /// The answer to a `Request::Complex`
+    Complex(ComplexResult),
+
+    /// The answer to a `Request::Integer`
+    Integer(IntegerResult),
+
+    /// The answer to a `Request::Units`
+    Quantity(QuantityResult),
+
+    /// The answer to a `Request::Functions`
+    Functions(FunctionsResult),
+
+    /// Sent once the server has accepted our credentials, before it answers anything else. It
+    /// lists every operation we can ask for with a `Request::Custom`.
+    Operations(Vec<OperationInfo>),
+
+    /// Always the first thing the server sends. It holds a random nonce that the client can sign
+    /// to prove it knows a key, and no requests get read until the client answers it with its
+    /// `Credentials`.
+    Challenge(Vec<u8>),
+
+    /// The server is shutting down and won't read any more requests. Results for requests it
+    /// already read will still arrive before the connection closes.
+    Goodbye,
+
+    /// The server won't serve this connection at all and is about to close it
+    Rejected(CalcError),
+}
+
+/// How a client proves who it is, sent in answer to the server's `Response::Challenge`
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Credentials {
+    /// Only accepted by servers that don't have any keys configured
+    Anonymous,
+
+    /// The secret of one of the server's keys, as is
+    Token(String),
+
+    /// The challenge nonce signed with the secret of the key named `key_id`
+    Hmac { key_id: String, signature: Vec<u8> },
 }
 
 impl MathRequest {
@@ -79,6 +662,209 @@ impl MathRequest {
             b,
         }
     }
+
+    pub fn power(a: f64, b: f64) -> MathRequest {
+        MathRequest {
+            id: rand::random(),
+            operation: Operation::Power,
+            a,
+            b,
+        }
+    }
+}
+
+impl BatchItem {
+    pub fn new(operation: Operation, a: f64, b: f64) -> BatchItem {
+        BatchItem { operation, a, b }
+    }
+}
+
+impl BatchRequest {
+    pub fn new(items: Vec<BatchItem>) -> BatchRequest {
+        BatchRequest {
+            id: rand::random(),
+            items,
+        }
+    }
+}
+
+impl IterativeRequest {
+    pub fn new(computation: Computation) -> IterativeRequest {
+        IterativeRequest {
+            id: rand::random(),
+            computation,
+        }
+    }
+}
+
+impl PolynomialRequest {
+    pub fn new(coefficients: Vec<f64>, convergence: Convergence) -> PolynomialRequest {
+        PolynomialRequest {
+            id: rand::random(),
+            coefficients,
+            convergence,
+        }
+    }
+}
+
+impl IntegralRequest {
+    pub fn new(
+        expression: &str,
+        variable: &str,
+        lower: f64,
+        upper: f64,
+        convergence: Convergence,
+    ) -> IntegralRequest {
+        IntegralRequest {
+            id: rand::random(),
+            expression: expression.to_string(),
+            variable: variable.to_string(),
+            lower,
+            upper,
+            convergence,
+        }
+    }
+}
+
+impl DerivativeRequest {
+    pub fn new(expression: &str, variable: &str, at: f64) -> DerivativeRequest {
+        DerivativeRequest {
+            id: rand::random(),
+            expression: expression.to_string(),
+            variable: variable.to_string(),
+            at,
+        }
+    }
+}
+
+impl SymbolicRequest {
+    pub fn new(expression: &str, operation: Symbolic) -> SymbolicRequest {
+        SymbolicRequest {
+            id: rand::random(),
+            expression: expression.to_string(),
+            operation,
+        }
+    }
+}
+
+impl EvaluateRequest {
+    pub fn new(expression: &str) -> EvaluateRequest {
+        EvaluateRequest {
+            id: rand::random(),
+            expression: expression.to_string(),
+        }
+    }
+}
+
+impl CustomRequest {
+    pub fn new(operation: &str, arguments: &[f64]) -> CustomRequest {
+        CustomRequest {
+            id: rand::random(),
+            operation: operation.to_string(),
+            arguments: arguments.to_vec(),
+        }
+    }
+}
+
+impl FunctionRequest {
+    pub fn new(operation: FunctionOperation) -> FunctionRequest {
+        FunctionRequest {
+            id: rand::random(),
+            operation,
+        }
+    }
+}
+
+impl ComplexRequest {
+    pub fn new(operation: ComplexOperation) -> ComplexRequest {
+        ComplexRequest {
+            id: rand::random(),
+            operation,
+        }
+    }
+}
+
+impl StatisticsRequest {
+    pub fn new(operation: StatisticsOperation) -> StatisticsRequest {
+        StatisticsRequest {
+            id: rand::random(),
+            operation,
+        }
+    }
+}
+
+impl UnitRequest {
+    pub fn new(operation: UnitOperation) -> UnitRequest {
+        UnitRequest {
+            id: rand::random(),
+            operation,
+        }
+    }
+}
+
+impl IntegerRequest {
+    pub fn new<I: Into<Integer>>(
+        operation: IntegerOperation,
+        a: I,
+        b: I,
+        overflow: Overflow,
+    ) -> IntegerRequest {
+        IntegerRequest {
+            id: rand::random(),
+            operation,
+            a: a.into(),
+            b: b.into(),
+            overflow,
+        }
+    }
+}
+
+impl MatrixRequest {
+    pub fn new(operation: MatrixOperation) -> MatrixRequest {
+        MatrixRequest {
+            id: rand::random(),
+            operation,
+        }
+    }
+}
+
+/// Tight enough for most things, with enough iterations for even bisection to get there
+impl Default for Convergence {
+    fn default() -> Convergence {
+        Convergence {
+            tolerance: 1e-12,
+            max_iterations: 1000,
+        }
+    }
+}
+
+impl Request {
+    pub fn aggregate_open() -> Request {
+        Request::AggregateOpen { id: rand::random() }
+    }
+
+    pub fn id(&self) -> u32 {
+        match self {
+            Request::Math(req) => req.id,
+            Request::Batch(req) => req.id,
+            Request::AggregateOpen { id } => *id,
+            Request::AggregatePush { id, .. } => *id,
+            Request::AggregateFinish { id, .. } => *id,
+            Request::Iterate(req) => req.id,
+            Request::PolynomialRoots(req) => req.id,
+            Request::Integrate(req) => req.id,
+            Request::Differentiate(req) => req.id,
+            Request::Symbolic(req) => req.id,
+            Request::Matrix(req) => req.id,
+            Request::Complex(req) => req.id,
+            Request::Integer(req) => req.id,
+            Request::Units(req) => req.id,
+            Request::Statistics(req) => req.id,
+            Request::Functions(req) => req.id,
+            Request::Evaluate(req) => req.id,
+            Request::Custom(req) => req.id,
+        }
+    }
 }
 
 impl fmt::Display for Operation {
@@ -88,6 +874,7 @@ impl fmt::Display for Operation {
             Operation::Subtraction => '-',
             Operation::Multiplication => '*',
             Operation::Division => '/',
+            Operation::Power => '^',
         };
 
         write!(f, "{}", c)
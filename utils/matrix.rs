@@ -0,0 +1,119 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{CalcError, MAX_FRAME_LEN};
+
+/// Most numbers a matrix or vector can hold, and so the most any result can hold too. Each one
+/// can take up at most a quarter of a frame, so a request with two of them still fits in one.
+/// That's a 256x256 matrix, which is about as big as we want to be inverting anyway.
+pub const MAX_MATRIX_LEN: usize = MAX_FRAME_LEN / 4 / 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dimensions {
+    pub rows: u32,
+    pub columns: u32,
+}
+
+/// A matrix with its numbers stored one row after another
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Matrix {
+    pub rows: u32,
+    pub columns: u32,
+    pub data: Vec<f64>,
+}
+
+impl Matrix {
+    pub fn new(rows: u32, columns: u32, data: Vec<f64>) -> Result<Matrix, CalcError> {
+        let matrix = Matrix {
+            rows,
+            columns,
+            data,
+        };
+        matrix.check()?;
+
+        Ok(matrix)
+    }
+
+    /// Builds a matrix out of its rows, which all have to be the same length
+    pub fn from_rows(rows: &[Vec<f64>]) -> Result<Matrix, CalcError> {
+        let columns = rows.first().map_or(0, |row| row.len());
+
+        if rows.iter().any(|row| row.len() != columns) {
+            return Err(CalcError::InvalidArgument(
+                "rows have different lengths".to_string(),
+            ));
+        }
+
+        Matrix::new(rows.len() as u32, columns as u32, rows.concat())
+    }
+
+    pub fn dimensions(&self) -> Dimensions {
+        Dimensions {
+            rows: self.rows,
+            columns: self.columns,
+        }
+    }
+
+    pub fn get(&self, row: usize, column: usize) -> f64 {
+        self.data[row * self.columns as usize + column]
+    }
+
+    /// Makes sure there are as many numbers as the dimensions say, and not too many of them.
+    /// Anything that comes off the wire has to pass this before it gets used.
+    pub fn check(&self) -> Result<(), CalcError> {
+        let len = u64::from(self.rows) * u64::from(self.columns);
+
+        if len != self.data.len() as u64 {
+            let dimensions = self.dimensions();
+            let msg = format!(
+                "a {} matrix can't hold {} numbers",
+                dimensions,
+                self.data.len()
+            );
+            return Err(CalcError::InvalidArgument(msg));
+        }
+
+        if len > MAX_MATRIX_LEN as u64 {
+            let msg = format!("matrices can hold at most {} numbers", MAX_MATRIX_LEN);
+            return Err(CalcError::InvalidArgument(msg));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Dimensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.rows, self.columns)
+    }
+}
+
+/// One row per line, like `[1, 2]` over `[3, 4]`
+impl fmt::Display for Matrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, row) in self.data.chunks(self.columns.max(1) as usize).enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+
+            write!(f, "[")?;
+
+            for (j, num) in row.iter().enumerate() {
+                if j > 0 {
+                    write!(f, ", ")?;
+                }
+
+                write!(f, "{}", num)?;
+            }
+
+            write!(f, "]")?;
+        }
+
+        Ok(())
+    }
+}
@@ -2,6 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use std::io;
 use std::pin::Pin;
 
 use byteorder::{ByteOrder, LE};
@@ -9,8 +10,15 @@ use futures::stream::Stream;
 use futures::task::{Context, Poll};
 use tokio::io::AsyncRead;
 
+use crate::frame::FrameLimit;
+
 const LENGTH_BYTES: usize = 4;
 
+/// Longest packet we'll read. The length comes first and we don't trust it, so anything that
+/// says it's longer is an error before we allocate any of it. Every other limit on how much a
+/// single request can hold is worked out from this one.
+pub const MAX_FRAME_LEN: usize = 2 * 1024 * 1024;
+
 #[derive(Debug)]
 enum PacketState {
     Length,
@@ -24,6 +32,7 @@ pub struct PacketStreamer<A: AsyncRead + Unpin> {
     state: PacketState,
     buffer: Vec<u8>,
     pos: usize,
+    limit: FrameLimit,
 }
 
 impl<A: AsyncRead + Unpin> PacketStreamer<A> {
@@ -33,14 +42,20 @@ impl<A: AsyncRead + Unpin> PacketStreamer<A> {
             state: PacketState::Length,
             buffer: vec![0u8; LENGTH_BYTES],
             pos: 0,
+            limit: FrameLimit::new(MAX_FRAME_LEN),
         }
     }
+
+    /// Lets whoever reads the packets change how long they can be. It starts at `MAX_FRAME_LEN`.
+    pub fn limit(&self) -> FrameLimit {
+        self.limit.clone()
+    }
 }
 
 impl<A: AsyncRead + Unpin> Stream for PacketStreamer<A> {
-    type Item = Vec<u8>;
+    type Item = io::Result<Vec<u8>>;
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Vec<u8>>> {
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<io::Result<Vec<u8>>>> {
         // Get ourself (good pun) out of a pin
         // basically &mut self
         let s = Pin::get_mut(self);
@@ -52,10 +67,8 @@ impl<A: AsyncRead + Unpin> Stream for PacketStreamer<A> {
             PacketState::Data(len) => len,
         };
 
-
         // Outside loop to repeat this in case we finish the length state and can move on to data
         loop {
-
             // We're going to read as much as we can up till our target
             while s.pos < target_len {
                 let reader = Pin::new(&mut s.async_reader);
@@ -74,9 +87,9 @@ impl<A: AsyncRead + Unpin> Stream for PacketStreamer<A> {
                         s.pos += num;
                     }
 
-                    // If we received an error, we're definitely not going to be able to continue
-                    // TODO: use e?
-                    Poll::Ready(Err(_e)) => return Poll::Ready(None),
+                    // If we received an error, we're definitely not going to be able to continue,
+                    // but whoever is reading the packets probably wants to know why
+                    Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
 
                     // Return pending if we got pending
                     Poll::Pending => return Poll::Pending,
@@ -90,6 +103,13 @@ impl<A: AsyncRead + Unpin> Stream for PacketStreamer<A> {
                     // If we were in the length state, read the length from the bytes
                     let length = LE::read_u32(&s.buffer) as usize;
 
+                    if length > s.limit.get() {
+                        let msg = format!("packet of {} bytes is too long", length);
+                        let e = io::Error::new(io::ErrorKind::InvalidData, msg);
+
+                        return Poll::Ready(Some(Err(e)));
+                    }
+
                     // transition to the data state and supply the length of the data we need
                     s.state = PacketState::Data(length);
 
@@ -125,7 +145,7 @@ impl<A: AsyncRead + Unpin> Stream for PacketStreamer<A> {
                     let packet = std::mem::replace(&mut s.buffer, vec![0u8; LENGTH_BYTES]);
 
                     // Return the buffer that was in the struct
-                    return Poll::Ready(Some(packet));
+                    return Poll::Ready(Some(Ok(packet)));
                 }
             }
         }
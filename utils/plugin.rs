@@ -0,0 +1,46 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::os::raw::c_char;
+
+/// Which version of the layout below a plugin was built against. It goes up whenever anything
+/// here changes, so the server can turn away a library it would otherwise misread.
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/// The symbol every plugin library exports, as an `extern "C" fn() -> *const PluginTable`
+pub const PLUGIN_ENTRY_POINT: &str = "calc_plugin_operations";
+
+/// The operations a plugin library has, as its entry point hands them over. Everything in here
+/// has to stay valid for as long as the library is loaded, so it's usually a `static`. Nothing
+/// in it is Rust specific, so plugins can be written in anything that can export C functions.
+#[repr(C)]
+#[derive(Debug)]
+pub struct PluginTable {
+    /// Always `PLUGIN_ABI_VERSION`, as the plugin knew it
+    pub abi_version: u32,
+
+    pub len: usize,
+    pub operations: *const PluginOperation,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct PluginOperation {
+    /// The name clients ask for the operation by, nul terminated and UTF-8
+    pub name: *const c_char,
+
+    /// How many arguments `evaluate` gets
+    pub arity: u32,
+
+    /// Reads `arity` arguments and writes the result, returning 0 if it worked. Anything else is
+    /// passed on to the client as an error code. There's no way for the server to stop one of
+    /// these once it's started, so they should be quick. They can't panic either, since a panic
+    /// can't unwind out of an `extern "C"` function and aborts the whole server instead.
+    pub evaluate: unsafe extern "C" fn(arguments: *const f64, result: *mut f64) -> i32,
+}
+
+// Plugins only ever point these at data that never changes, so they can be shared between
+// threads, and put in a `static` by plugins written in Rust
+unsafe impl Sync for PluginTable {}
+unsafe impl Sync for PluginOperation {}
@@ -14,7 +14,10 @@ use crate::packet_sink::PacketSink;
 use crate::serialize::{Serializable, Serializer};
 
 #[derive(Debug)]
-pub struct SerealSink<S: Serializable + Unpin, A: AsyncWrite + Unpin>(PacketSink<A>, PhantomData<S>);
+pub struct SerealSink<S: Serializable + Unpin, A: AsyncWrite + Unpin>(
+    PacketSink<A>,
+    PhantomData<S>,
+);
 
 impl<S: Serializable + Unpin, A: AsyncWrite + Unpin> SerealSink<S, A> {
     pub fn new(writer: A) -> SerealSink<S, A> {
@@ -22,7 +25,6 @@ impl<S: Serializable + Unpin, A: AsyncWrite + Unpin> SerealSink<S, A> {
     }
 }
 
-
 impl<S: Serializable + Unpin, A: AsyncWrite + Unpin> Sink<&S> for SerealSink<S, A> {
     type Error = io::Error;
 
@@ -2,9 +2,9 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
-use std::io::Cursor;
-use std::pin::Pin;
+use std::io::{self, Cursor};
 use std::marker::PhantomData;
+use std::pin::Pin;
 
 use futures::stream::Stream;
 use futures::task::{Context, Poll};
@@ -14,7 +14,10 @@ use crate::deserialize::{Deserializable, Deserializer};
 use crate::packet_streamer::PacketStreamer;
 
 #[derive(Debug)]
-pub struct SerealStreamer<D: Deserializable + Unpin, A: AsyncRead + Unpin>(PacketStreamer<A>, PhantomData<D>);
+pub struct SerealStreamer<D: Deserializable + Unpin, A: AsyncRead + Unpin>(
+    PacketStreamer<A>,
+    PhantomData<D>,
+);
 
 impl<D: Deserializable + Unpin, A: AsyncRead + Unpin> SerealStreamer<D, A> {
     pub fn new(reader: A) -> SerealStreamer<D, A> {
@@ -23,17 +26,19 @@ impl<D: Deserializable + Unpin, A: AsyncRead + Unpin> SerealStreamer<D, A> {
 }
 
 impl<D: Deserializable + Unpin, A: AsyncRead + Unpin> Stream for SerealStreamer<D, A> {
-    type Item = D;
+    type Item = io::Result<D>;
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<D>> {
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<io::Result<D>>> {
         let SerealStreamer(packets, _) = self.get_mut();
 
         match Pin::new(packets).poll_next(cx) {
-            Poll::Ready(Some(packet)) => {
+            Poll::Ready(Some(Ok(packet))) => {
                 let mut cursor_bytes = Cursor::new(packet);
-                Poll::Ready(Some(cursor_bytes.deserialize().unwrap()))
+                Poll::Ready(Some(cursor_bytes.deserialize()))
             }
 
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e))),
+
             Poll::Ready(None) => Poll::Ready(None),
             Poll::Pending => Poll::Pending,
         }
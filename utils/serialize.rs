@@ -3,10 +3,22 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
 use std::io::{self, Write};
+use std::time::Duration;
 
 use byteorder::{WriteBytesExt, LE};
 
-use crate::{MathRequest, MathResult, Operation};
+use crate::{Aggregate, AggregateResult, BatchItem, BatchRequest, BatchResult, CalcError};
+use crate::{Complex, Convergence, PolynomialRequest, Progress, Request, Response, RootMethod};
+use crate::{ComplexOperation, ComplexRequest, ComplexResult, ComplexValue, Polar};
+use crate::{Computation, Credentials, IterativeRequest, MathRequest, MathResult, Operation};
+use crate::{CustomRequest, OperationInfo};
+use crate::{DerivativeRequest, Estimate, EstimateResult, IntegralRequest, RootsResult};
+use crate::{Dimensions, Matrix, MatrixOperation, MatrixRequest, MatrixResult, MatrixValue};
+use crate::{Distribution, StatisticsOperation, StatisticsRequest};
+use crate::{EvaluateRequest, Function, FunctionOperation, FunctionRequest, FunctionsResult};
+use crate::{Expr, ExpressionResult, Symbolic, SymbolicRequest};
+use crate::{Integer, IntegerOperation, IntegerRequest, IntegerResult, Overflow};
+use crate::{Quantity, QuantityResult, UnitOperation, UnitRequest};
 
 pub trait Serializable {
     fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()>;
@@ -20,6 +32,13 @@ pub trait Serializer: Write + Sized {
 
 impl<T> Serializer for T where T: Write + Sized {}
 
+impl Serializable for u8 {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        buf.write_u8(*self)?;
+        Ok(())
+    }
+}
+
 impl Serializable for u32 {
     fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
         buf.write_u32::<LE>(*self)?;
@@ -27,6 +46,27 @@ impl Serializable for u32 {
     }
 }
 
+impl Serializable for u64 {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        buf.write_u64::<LE>(*self)?;
+        Ok(())
+    }
+}
+
+impl Serializable for i64 {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        buf.write_i64::<LE>(*self)?;
+        Ok(())
+    }
+}
+
+impl Serializable for i128 {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        buf.write_i128::<LE>(*self)?;
+        Ok(())
+    }
+}
+
 impl Serializable for f64 {
     fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
         buf.write_f64::<LE>(*self)?;
@@ -34,6 +74,121 @@ impl Serializable for f64 {
     }
 }
 
+/// Durations only go over the wire with millisecond precision
+impl Serializable for Duration {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        (self.as_millis() as u64).serialize_to(buf)
+    }
+}
+
+impl Serializable for str {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        (self.len() as u32).serialize_to(buf)?;
+        buf.write_all(self.as_bytes())
+    }
+}
+
+impl Serializable for String {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.as_str().serialize_to(buf)
+    }
+}
+
+impl<S: Serializable> Serializable for Vec<S> {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        (self.len() as u32).serialize_to(buf)?;
+
+        for item in self {
+            item.serialize_to(buf)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: Serializable, E: Serializable> Serializable for Result<S, E> {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            Ok(val) => {
+                0u32.serialize_to(buf)?;
+                val.serialize_to(buf)
+            }
+
+            Err(err) => {
+                1u32.serialize_to(buf)?;
+                err.serialize_to(buf)
+            }
+        }
+    }
+}
+
+impl Serializable for CalcError {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            CalcError::Internal(msg) => {
+                0u32.serialize_to(buf)?;
+                msg.serialize_to(buf)
+            }
+
+            CalcError::Overloaded => 1u32.serialize_to(buf),
+            CalcError::TimedOut => 2u32.serialize_to(buf),
+
+            CalcError::RateLimited { retry_after } => {
+                3u32.serialize_to(buf)?;
+                retry_after.serialize_to(buf)
+            }
+
+            CalcError::TooManyConnections => 4u32.serialize_to(buf),
+            CalcError::Unauthenticated => 5u32.serialize_to(buf),
+
+            CalcError::InvalidExpression(msg) => {
+                6u32.serialize_to(buf)?;
+                msg.serialize_to(buf)
+            }
+
+            CalcError::BatchTooLarge { max } => {
+                7u32.serialize_to(buf)?;
+                max.serialize_to(buf)
+            }
+
+            CalcError::InvalidAggregation(msg) => {
+                8u32.serialize_to(buf)?;
+                msg.serialize_to(buf)
+            }
+
+            CalcError::DidNotConverge { iterations } => {
+                9u32.serialize_to(buf)?;
+                iterations.serialize_to(buf)
+            }
+
+            CalcError::InvalidArgument(msg) => {
+                10u32.serialize_to(buf)?;
+                msg.serialize_to(buf)
+            }
+
+            CalcError::DimensionMismatch { lhs, rhs } => {
+                11u32.serialize_to(buf)?;
+                lhs.serialize_to(buf)?;
+                rhs.serialize_to(buf)
+            }
+
+            CalcError::Singular => 12u32.serialize_to(buf),
+            CalcError::Overflow => 13u32.serialize_to(buf),
+
+            CalcError::IncompatibleUnits { lhs, rhs } => {
+                14u32.serialize_to(buf)?;
+                lhs.serialize_to(buf)?;
+                rhs.serialize_to(buf)
+            }
+
+            CalcError::UnknownOperation(name) => {
+                15u32.serialize_to(buf)?;
+                name.serialize_to(buf)
+            }
+        }
+    }
+}
+
 impl Serializable for Operation {
     fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
         let val: u32 = match self {
@@ -41,6 +196,7 @@ impl Serializable for Operation {
             Operation::Subtraction => 1,
             Operation::Multiplication => 2,
             Operation::Division => 3,
+            Operation::Power => 4,
         };
 
         val.serialize_to(buf)
@@ -66,3 +222,968 @@ impl Serializable for MathResult {
         Ok(())
     }
 }
+
+impl Serializable for BatchItem {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.operation.serialize_to(buf)?;
+        self.a.serialize_to(buf)?;
+        self.b.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for BatchRequest {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.items.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for BatchResult {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.res.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for Aggregate {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.count.serialize_to(buf)?;
+        self.sum.serialize_to(buf)?;
+        self.mean.serialize_to(buf)?;
+        self.variance.serialize_to(buf)?;
+        self.min.serialize_to(buf)?;
+        self.max.serialize_to(buf)?;
+        self.percentiles.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for AggregateResult {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.res.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for Computation {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            Computation::Sqrt(value) => {
+                0u32.serialize_to(buf)?;
+                value.serialize_to(buf)
+            }
+
+            Computation::Root {
+                expression,
+                variable,
+                method,
+                convergence,
+            } => {
+                1u32.serialize_to(buf)?;
+                expression.serialize_to(buf)?;
+                variable.serialize_to(buf)?;
+                method.serialize_to(buf)?;
+                convergence.serialize_to(buf)
+            }
+        }
+    }
+}
+
+impl Serializable for RootMethod {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            RootMethod::Bisection { lower, upper } => {
+                0u32.serialize_to(buf)?;
+                lower.serialize_to(buf)?;
+                upper.serialize_to(buf)
+            }
+
+            RootMethod::Brent { lower, upper } => {
+                1u32.serialize_to(buf)?;
+                lower.serialize_to(buf)?;
+                upper.serialize_to(buf)
+            }
+
+            RootMethod::Newton { guess } => {
+                2u32.serialize_to(buf)?;
+                guess.serialize_to(buf)
+            }
+        }
+    }
+}
+
+impl Serializable for Convergence {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.tolerance.serialize_to(buf)?;
+        self.max_iterations.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for Complex {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.re.serialize_to(buf)?;
+        self.im.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for Polar {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.magnitude.serialize_to(buf)?;
+        self.angle.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for ComplexOperation {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            ComplexOperation::Binary(operation, a, b) => {
+                0u32.serialize_to(buf)?;
+                operation.serialize_to(buf)?;
+                a.serialize_to(buf)?;
+                b.serialize_to(buf)
+            }
+
+            ComplexOperation::Sqrt(z) => {
+                1u32.serialize_to(buf)?;
+                z.serialize_to(buf)
+            }
+
+            ComplexOperation::Exp(z) => {
+                2u32.serialize_to(buf)?;
+                z.serialize_to(buf)
+            }
+
+            ComplexOperation::Ln(z) => {
+                3u32.serialize_to(buf)?;
+                z.serialize_to(buf)
+            }
+
+            ComplexOperation::Abs(z) => {
+                4u32.serialize_to(buf)?;
+                z.serialize_to(buf)
+            }
+
+            ComplexOperation::Arg(z) => {
+                5u32.serialize_to(buf)?;
+                z.serialize_to(buf)
+            }
+
+            ComplexOperation::Conjugate(z) => {
+                6u32.serialize_to(buf)?;
+                z.serialize_to(buf)
+            }
+
+            ComplexOperation::ToPolar(z) => {
+                7u32.serialize_to(buf)?;
+                z.serialize_to(buf)
+            }
+
+            ComplexOperation::FromPolar(polar) => {
+                8u32.serialize_to(buf)?;
+                polar.serialize_to(buf)
+            }
+        }
+    }
+}
+
+impl Serializable for ComplexValue {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            ComplexValue::Real(num) => {
+                0u32.serialize_to(buf)?;
+                num.serialize_to(buf)
+            }
+
+            ComplexValue::Complex(z) => {
+                1u32.serialize_to(buf)?;
+                z.serialize_to(buf)
+            }
+
+            ComplexValue::Polar(polar) => {
+                2u32.serialize_to(buf)?;
+                polar.serialize_to(buf)
+            }
+        }
+    }
+}
+
+impl Serializable for ComplexRequest {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.operation.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for ComplexResult {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.res.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for Distribution {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            Distribution::Normal { mean, std_dev } => {
+                0u32.serialize_to(buf)?;
+                mean.serialize_to(buf)?;
+                std_dev.serialize_to(buf)
+            }
+
+            Distribution::Binomial {
+                trials,
+                probability,
+            } => {
+                1u32.serialize_to(buf)?;
+                trials.serialize_to(buf)?;
+                probability.serialize_to(buf)
+            }
+
+            Distribution::Poisson { rate } => {
+                2u32.serialize_to(buf)?;
+                rate.serialize_to(buf)
+            }
+
+            Distribution::StudentT { degrees_of_freedom } => {
+                3u32.serialize_to(buf)?;
+                degrees_of_freedom.serialize_to(buf)
+            }
+
+            Distribution::ChiSquared { degrees_of_freedom } => {
+                4u32.serialize_to(buf)?;
+                degrees_of_freedom.serialize_to(buf)
+            }
+        }
+    }
+}
+
+impl Serializable for StatisticsOperation {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            StatisticsOperation::Pdf(distribution, x) => {
+                0u32.serialize_to(buf)?;
+                distribution.serialize_to(buf)?;
+                x.serialize_to(buf)
+            }
+
+            StatisticsOperation::Cdf(distribution, x) => {
+                1u32.serialize_to(buf)?;
+                distribution.serialize_to(buf)?;
+                x.serialize_to(buf)
+            }
+
+            StatisticsOperation::Quantile(distribution, p) => {
+                2u32.serialize_to(buf)?;
+                distribution.serialize_to(buf)?;
+                p.serialize_to(buf)
+            }
+
+            StatisticsOperation::Factorial(n) => {
+                3u32.serialize_to(buf)?;
+                n.serialize_to(buf)
+            }
+
+            StatisticsOperation::Choose(n, k) => {
+                4u32.serialize_to(buf)?;
+                n.serialize_to(buf)?;
+                k.serialize_to(buf)
+            }
+
+            StatisticsOperation::Permutations(n, k) => {
+                5u32.serialize_to(buf)?;
+                n.serialize_to(buf)?;
+                k.serialize_to(buf)
+            }
+
+            StatisticsOperation::Gamma(x) => {
+                6u32.serialize_to(buf)?;
+                x.serialize_to(buf)
+            }
+
+            StatisticsOperation::Beta(a, b) => {
+                7u32.serialize_to(buf)?;
+                a.serialize_to(buf)?;
+                b.serialize_to(buf)
+            }
+        }
+    }
+}
+
+impl Serializable for StatisticsRequest {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.operation.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for Quantity {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.value.serialize_to(buf)?;
+        self.unit.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for UnitOperation {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            UnitOperation::Binary(operation, a, b) => {
+                0u32.serialize_to(buf)?;
+                operation.serialize_to(buf)?;
+                a.serialize_to(buf)?;
+                b.serialize_to(buf)
+            }
+
+            UnitOperation::Convert(quantity, unit) => {
+                1u32.serialize_to(buf)?;
+                quantity.serialize_to(buf)?;
+                unit.serialize_to(buf)
+            }
+        }
+    }
+}
+
+impl Serializable for UnitRequest {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.operation.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for QuantityResult {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.res.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for Integer {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            Integer::I64(num) => {
+                0u32.serialize_to(buf)?;
+                num.serialize_to(buf)
+            }
+
+            Integer::U64(num) => {
+                1u32.serialize_to(buf)?;
+                num.serialize_to(buf)
+            }
+
+            Integer::I128(num) => {
+                2u32.serialize_to(buf)?;
+                num.serialize_to(buf)
+            }
+        }
+    }
+}
+
+impl Serializable for Overflow {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        let val: u32 = match self {
+            Overflow::Checked => 0,
+            Overflow::Wrapping => 1,
+            Overflow::Saturating => 2,
+        };
+
+        val.serialize_to(buf)
+    }
+}
+
+impl Serializable for IntegerOperation {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        let val: u32 = match self {
+            IntegerOperation::Addition => 0,
+            IntegerOperation::Subtraction => 1,
+            IntegerOperation::Multiplication => 2,
+            IntegerOperation::Division => 3,
+            IntegerOperation::Remainder => 4,
+            IntegerOperation::Power => 5,
+            IntegerOperation::Gcd => 6,
+            IntegerOperation::Lcm => 7,
+            IntegerOperation::ShiftLeft => 8,
+            IntegerOperation::ShiftRight => 9,
+            IntegerOperation::And => 10,
+            IntegerOperation::Or => 11,
+            IntegerOperation::Xor => 12,
+        };
+
+        val.serialize_to(buf)
+    }
+}
+
+impl Serializable for IntegerRequest {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.operation.serialize_to(buf)?;
+        self.a.serialize_to(buf)?;
+        self.b.serialize_to(buf)?;
+        self.overflow.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for IntegerResult {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.res.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for PolynomialRequest {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.coefficients.serialize_to(buf)?;
+        self.convergence.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for RootsResult {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.res.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for IntegralRequest {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.expression.serialize_to(buf)?;
+        self.variable.serialize_to(buf)?;
+        self.lower.serialize_to(buf)?;
+        self.upper.serialize_to(buf)?;
+        self.convergence.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for DerivativeRequest {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.expression.serialize_to(buf)?;
+        self.variable.serialize_to(buf)?;
+        self.at.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for Estimate {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.value.serialize_to(buf)?;
+        self.error.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for EstimateResult {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.res.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for Expr {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            Expr::Number(num) => {
+                0u32.serialize_to(buf)?;
+                num.serialize_to(buf)
+            }
+
+            Expr::Variable(name) => {
+                1u32.serialize_to(buf)?;
+                name.serialize_to(buf)
+            }
+
+            Expr::Negate(operand) => {
+                2u32.serialize_to(buf)?;
+                operand.serialize_to(buf)
+            }
+
+            Expr::Binary(operation, lhs, rhs) => {
+                3u32.serialize_to(buf)?;
+                operation.serialize_to(buf)?;
+                lhs.serialize_to(buf)?;
+                rhs.serialize_to(buf)
+            }
+
+            Expr::Call(name, arguments) => {
+                4u32.serialize_to(buf)?;
+                name.serialize_to(buf)?;
+                arguments.serialize_to(buf)
+            }
+        }
+    }
+}
+
+impl Serializable for Function {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.name.serialize_to(buf)?;
+        self.parameters.serialize_to(buf)?;
+        self.body.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for EvaluateRequest {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.expression.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for CustomRequest {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.operation.serialize_to(buf)?;
+        self.arguments.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for OperationInfo {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.name.serialize_to(buf)?;
+        self.arity.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for FunctionOperation {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            FunctionOperation::Define(definition) => {
+                0u32.serialize_to(buf)?;
+                definition.serialize_to(buf)
+            }
+
+            FunctionOperation::Remove(name) => {
+                1u32.serialize_to(buf)?;
+                name.serialize_to(buf)
+            }
+
+            FunctionOperation::List => 2u32.serialize_to(buf),
+        }
+    }
+}
+
+impl Serializable for FunctionRequest {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.operation.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for FunctionsResult {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.res.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for Symbolic {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            Symbolic::Derivative { variable } => {
+                0u32.serialize_to(buf)?;
+                variable.serialize_to(buf)
+            }
+
+            Symbolic::Simplify => 1u32.serialize_to(buf),
+        }
+    }
+}
+
+impl Serializable for SymbolicRequest {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.expression.serialize_to(buf)?;
+        self.operation.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for ExpressionResult {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.res.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for Dimensions {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.rows.serialize_to(buf)?;
+        self.columns.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for Matrix {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.rows.serialize_to(buf)?;
+        self.columns.serialize_to(buf)?;
+        self.data.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for MatrixOperation {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            MatrixOperation::Add(a, b) => {
+                0u32.serialize_to(buf)?;
+                a.serialize_to(buf)?;
+                b.serialize_to(buf)
+            }
+
+            MatrixOperation::Multiply(a, b) => {
+                1u32.serialize_to(buf)?;
+                a.serialize_to(buf)?;
+                b.serialize_to(buf)
+            }
+
+            MatrixOperation::Transpose(a) => {
+                2u32.serialize_to(buf)?;
+                a.serialize_to(buf)
+            }
+
+            MatrixOperation::Determinant(a) => {
+                3u32.serialize_to(buf)?;
+                a.serialize_to(buf)
+            }
+
+            MatrixOperation::Inverse(a) => {
+                4u32.serialize_to(buf)?;
+                a.serialize_to(buf)
+            }
+
+            MatrixOperation::Solve(a, b) => {
+                5u32.serialize_to(buf)?;
+                a.serialize_to(buf)?;
+                b.serialize_to(buf)
+            }
+
+            MatrixOperation::Dot(a, b) => {
+                6u32.serialize_to(buf)?;
+                a.serialize_to(buf)?;
+                b.serialize_to(buf)
+            }
+
+            MatrixOperation::Cross(a, b) => {
+                7u32.serialize_to(buf)?;
+                a.serialize_to(buf)?;
+                b.serialize_to(buf)
+            }
+        }
+    }
+}
+
+impl Serializable for MatrixValue {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            MatrixValue::Scalar(num) => {
+                0u32.serialize_to(buf)?;
+                num.serialize_to(buf)
+            }
+
+            MatrixValue::Vector(vector) => {
+                1u32.serialize_to(buf)?;
+                vector.serialize_to(buf)
+            }
+
+            MatrixValue::Matrix(matrix) => {
+                2u32.serialize_to(buf)?;
+                matrix.serialize_to(buf)
+            }
+        }
+    }
+}
+
+impl Serializable for MatrixRequest {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.operation.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for MatrixResult {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.res.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for IterativeRequest {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.computation.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for Progress {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        self.id.serialize_to(buf)?;
+        self.iteration.serialize_to(buf)?;
+        self.approximation.serialize_to(buf)?;
+
+        Ok(())
+    }
+}
+
+impl Serializable for Request {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            Request::Math(req) => {
+                0u32.serialize_to(buf)?;
+                req.serialize_to(buf)
+            }
+
+            Request::Batch(req) => {
+                1u32.serialize_to(buf)?;
+                req.serialize_to(buf)
+            }
+
+            Request::AggregateOpen { id } => {
+                2u32.serialize_to(buf)?;
+                id.serialize_to(buf)
+            }
+
+            Request::AggregatePush { id, values } => {
+                3u32.serialize_to(buf)?;
+                id.serialize_to(buf)?;
+                values.serialize_to(buf)
+            }
+
+            Request::AggregateFinish { id, percentiles } => {
+                4u32.serialize_to(buf)?;
+                id.serialize_to(buf)?;
+                percentiles.serialize_to(buf)
+            }
+
+            Request::Iterate(req) => {
+                5u32.serialize_to(buf)?;
+                req.serialize_to(buf)
+            }
+
+            Request::PolynomialRoots(req) => {
+                6u32.serialize_to(buf)?;
+                req.serialize_to(buf)
+            }
+
+            Request::Integrate(req) => {
+                7u32.serialize_to(buf)?;
+                req.serialize_to(buf)
+            }
+
+            Request::Differentiate(req) => {
+                8u32.serialize_to(buf)?;
+                req.serialize_to(buf)
+            }
+
+            Request::Symbolic(req) => {
+                9u32.serialize_to(buf)?;
+                req.serialize_to(buf)
+            }
+
+            Request::Matrix(req) => {
+                10u32.serialize_to(buf)?;
+                req.serialize_to(buf)
+            }
+
+            Request::Complex(req) => {
+                11u32.serialize_to(buf)?;
+                req.serialize_to(buf)
+            }
+
+            Request::Integer(req) => {
+                12u32.serialize_to(buf)?;
+                req.serialize_to(buf)
+            }
+
+            Request::Units(req) => {
+                13u32.serialize_to(buf)?;
+                req.serialize_to(buf)
+            }
+
+            Request::Statistics(req) => {
+                14u32.serialize_to(buf)?;
+                req.serialize_to(buf)
+            }
+
+            Request::Functions(req) => {
+                15u32.serialize_to(buf)?;
+                req.serialize_to(buf)
+            }
+
+            Request::Evaluate(req) => {
+                16u32.serialize_to(buf)?;
+                req.serialize_to(buf)
+            }
+
+            Request::Custom(req) => {
+                17u32.serialize_to(buf)?;
+                req.serialize_to(buf)
+            }
+        }
+    }
+}
+
+impl Serializable for Response {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            Response::Result(result) => {
+                0u32.serialize_to(buf)?;
+                result.serialize_to(buf)
+            }
+
+            Response::Goodbye => 1u32.serialize_to(buf),
+
+            Response::Rejected(err) => {
+                2u32.serialize_to(buf)?;
+                err.serialize_to(buf)
+            }
+
+            Response::Challenge(nonce) => {
+                3u32.serialize_to(buf)?;
+                nonce.serialize_to(buf)
+            }
+
+            Response::Batch(result) => {
+                4u32.serialize_to(buf)?;
+                result.serialize_to(buf)
+            }
+
+            Response::Aggregate(result) => {
+                5u32.serialize_to(buf)?;
+                result.serialize_to(buf)
+            }
+
+            Response::Progress(progress) => {
+                6u32.serialize_to(buf)?;
+                progress.serialize_to(buf)
+            }
+
+            Response::Roots(result) => {
+                7u32.serialize_to(buf)?;
+                result.serialize_to(buf)
+            }
+
+            Response::Estimate(result) => {
+                8u32.serialize_to(buf)?;
+                result.serialize_to(buf)
+            }
+
+            Response::Expression(result) => {
+                9u32.serialize_to(buf)?;
+                result.serialize_to(buf)
+            }
+
+            Response::Matrix(result) => {
+                10u32.serialize_to(buf)?;
+                result.serialize_to(buf)
+            }
+
+            Response::Complex(result) => {
+                11u32.serialize_to(buf)?;
+                result.serialize_to(buf)
+            }
+
+            Response::Integer(result) => {
+                12u32.serialize_to(buf)?;
+                result.serialize_to(buf)
+            }
+
+            Response::Quantity(result) => {
+                13u32.serialize_to(buf)?;
+                result.serialize_to(buf)
+            }
+
+            Response::Functions(result) => {
+                14u32.serialize_to(buf)?;
+                result.serialize_to(buf)
+            }
+
+            Response::Operations(operations) => {
+                15u32.serialize_to(buf)?;
+                operations.serialize_to(buf)
+            }
+        }
+    }
+}
+
+impl Serializable for Credentials {
+    fn serialize_to<T: Write>(&self, buf: &mut T) -> io::Result<()> {
+        match self {
+            Credentials::Anonymous => 0u32.serialize_to(buf),
+
+            Credentials::Token(token) => {
+                1u32.serialize_to(buf)?;
+                token.serialize_to(buf)
+            }
+
+            Credentials::Hmac { key_id, signature } => {
+                2u32.serialize_to(buf)?;
+                key_id.serialize_to(buf)?;
+                signature.serialize_to(buf)
+            }
+        }
+    }
+}
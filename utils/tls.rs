@@ -0,0 +1,545 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::future::poll_fn;
+use futures::task::{Context, Poll};
+use rustls::internal::pemfile;
+use rustls::{AllowAnyAuthenticatedClient, Certificate, ClientConfig, ClientSession, NoClientAuth};
+use rustls::{PrivateKey, RootCertStore, ServerConfig, ServerSession, Session};
+use tokio::io::{AsyncRead, AsyncWrite};
+use webpki::DNSNameRef;
+
+/// Wraps a stream in TLS. Everything written to it gets encrypted before it reaches the inner
+/// stream and everything read from it has already been decrypted, so `PacketStreamer` and
+/// `PacketSink` can sit on top of it like on any other stream.
+#[derive(Debug)]
+pub struct TlsStream<A: AsyncRead + AsyncWrite + Unpin, S: Session + Unpin> {
+    inner: A,
+    session: S,
+    eof: bool,
+    closing: bool,
+}
+
+/// Does the server side of the handshake on a freshly accepted stream
+pub async fn accept_tls<A>(
+    config: &Arc<ServerConfig>,
+    stream: A,
+) -> io::Result<TlsStream<A, ServerSession>>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut tls = TlsStream::new(stream, ServerSession::new(config));
+    poll_fn(|cx| tls.poll_handshake(cx)).await?;
+
+    Ok(tls)
+}
+
+/// Does the client side of the handshake, checking that the server's certificate is valid for
+/// `domain`
+pub async fn connect_tls<A>(
+    config: &Arc<ClientConfig>,
+    domain: &str,
+    stream: A,
+) -> io::Result<TlsStream<A, ClientSession>>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+{
+    let domain = DNSNameRef::try_from_ascii_str(domain)
+        .map_err(|_| invalid_data(format!("`{}` is not a valid domain name", domain)))?;
+
+    let mut tls = TlsStream::new(stream, ClientSession::new(config, domain));
+    poll_fn(|cx| tls.poll_handshake(cx)).await?;
+
+    Ok(tls)
+}
+
+/// Server settings from PEM files. With `client_ca`, clients also have to present a certificate
+/// signed by one of the authorities in it.
+pub fn server_tls_config<P: AsRef<Path>>(
+    cert: P,
+    key: P,
+    client_ca: Option<P>,
+) -> io::Result<Arc<ServerConfig>> {
+    let verifier = match client_ca {
+        Some(path) => AllowAnyAuthenticatedClient::new(load_root_store(path)?),
+        None => NoClientAuth::new(),
+    };
+
+    let mut config = ServerConfig::new(verifier);
+
+    config
+        .set_single_cert(load_certs(cert)?, load_private_key(key)?)
+        .map_err(|e| invalid_data(format!("bad server certificate: {}", e)))?;
+
+    Ok(Arc::new(config))
+}
+
+/// Client settings from PEM files. Only servers with certificates signed by one of the
+/// authorities in `roots` are trusted, and `identity` is the certificate and key to present to
+/// servers that ask for one.
+pub fn client_tls_config<P: AsRef<Path>>(
+    roots: P,
+    identity: Option<(P, P)>,
+) -> io::Result<Arc<ClientConfig>> {
+    let mut config = ClientConfig::new();
+    config.root_store = load_root_store(roots)?;
+
+    if let Some((cert, key)) = identity {
+        config.set_single_client_cert(load_certs(cert)?, load_private_key(key)?);
+    }
+
+    Ok(Arc::new(config))
+}
+
+fn load_certs<P: AsRef<Path>>(path: P) -> io::Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    match pemfile::certs(&mut reader) {
+        Ok(ref certs) if certs.is_empty() => Err(invalid_data("no certificates found".to_string())),
+
+        Ok(certs) => Ok(certs),
+        Err(()) => Err(invalid_data("could not parse certificates".to_string())),
+    }
+}
+
+/// Takes the first key in the file, PKCS#8 or RSA
+fn load_private_key<P: AsRef<Path>>(path: P) -> io::Result<PrivateKey> {
+    let path = path.as_ref();
+
+    let pkcs8 = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(path)?));
+    let rsa = pemfile::rsa_private_keys(&mut BufReader::new(File::open(path)?));
+
+    pkcs8
+        .unwrap_or_default()
+        .into_iter()
+        .chain(rsa.unwrap_or_default())
+        .next()
+        .ok_or_else(|| invalid_data("no private key found".to_string()))
+}
+
+fn load_root_store<P: AsRef<Path>>(path: P) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    let mut reader = BufReader::new(File::open(path)?);
+
+    match roots.add_pem_file(&mut reader) {
+        Ok((0, _)) | Err(()) => Err(invalid_data("no usable certificates found".to_string())),
+        Ok(_) => Ok(roots),
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// rustls wants blocking `Read` and `Write`, so this turns `Pending` into `WouldBlock` for it.
+/// The inner stream has registered our waker by then, so we get polled again once it's ready.
+struct SyncAdapter<'a, 'b, A> {
+    inner: &'a mut A,
+    cx: &'a mut Context<'b>,
+}
+
+impl<'a, 'b, A: AsyncRead + Unpin> Read for SyncAdapter<'a, 'b, A> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match Pin::new(&mut *self.inner).poll_read(self.cx, buf) {
+            Poll::Ready(res) => res,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl<'a, 'b, A: AsyncWrite + Unpin> Write for SyncAdapter<'a, 'b, A> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match Pin::new(&mut *self.inner).poll_write(self.cx, buf) {
+            Poll::Ready(res) => res,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match Pin::new(&mut *self.inner).poll_flush(self.cx) {
+            Poll::Ready(res) => res,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+/// Turns a `WouldBlock` from the `SyncAdapter` back into `Pending`
+fn unblock<T>(res: io::Result<T>) -> Poll<io::Result<T>> {
+    match res {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
+        res => Poll::Ready(res),
+    }
+}
+
+impl<A: AsyncRead + AsyncWrite + Unpin, S: Session + Unpin> TlsStream<A, S> {
+    fn new(inner: A, session: S) -> TlsStream<A, S> {
+        TlsStream {
+            inner,
+            session,
+            eof: false,
+            closing: false,
+        }
+    }
+
+    /// The certificates the other side presented, if it had to present any
+    pub fn peer_certificates(&self) -> Option<Vec<Certificate>> {
+        self.session.get_peer_certificates()
+    }
+
+    /// Reads whatever TLS records the inner stream has for us and decrypts them
+    fn poll_read_tls(&mut self, cx: &mut Context) -> Poll<io::Result<usize>> {
+        let mut reader = SyncAdapter {
+            inner: &mut self.inner,
+            cx,
+        };
+
+        let num = match unblock(self.session.read_tls(&mut reader)) {
+            Poll::Ready(res) => res?,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        if let Err(e) = self.session.process_new_packets() {
+            // Try to let the other side know what went wrong before we give up. If that doesn't
+            // work out we're failing anyway.
+            let _ = self.poll_write_tls(cx);
+            return Poll::Ready(Err(invalid_data(format!("TLS error: {}", e))));
+        }
+
+        Poll::Ready(Ok(num))
+    }
+
+    /// Writes out TLS records the session has queued up until there are none left
+    fn poll_write_tls(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while self.session.wants_write() {
+            let mut writer = SyncAdapter {
+                inner: &mut self.inner,
+                cx,
+            };
+
+            match unblock(self.session.write_tls(&mut writer)) {
+                Poll::Ready(res) => res?,
+                Poll::Pending => return Poll::Pending,
+            };
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_handshake(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while self.session.is_handshaking() {
+            if let Poll::Ready(res) = self.poll_write_tls(cx) {
+                res?;
+            } else {
+                return Poll::Pending;
+            }
+
+            if self.session.is_handshaking() && self.session.wants_read() {
+                match self.poll_read_tls(cx) {
+                    Poll::Ready(Ok(0)) => {
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()))
+                    }
+
+                    Poll::Ready(Ok(_)) => {}
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }
+
+        // The last handshake message might still be waiting to go out
+        match self.poll_write_tls(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut self.inner).poll_flush(cx),
+            other => other,
+        }
+    }
+}
+
+impl<A: AsyncRead + AsyncWrite + Unpin, S: Session + Unpin> AsyncRead for TlsStream<A, S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let tls = self.get_mut();
+
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        loop {
+            match tls.session.read(buf) {
+                Ok(0) => {}
+                Ok(num) => return Poll::Ready(Ok(num)),
+
+                // This is how rustls tells us the other side closed the connection cleanly
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionAborted => {
+                    return Poll::Ready(Ok(0))
+                }
+
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+
+            if tls.eof {
+                return Poll::Ready(Ok(0));
+            }
+
+            // No plaintext yet, so get some more records. Reading them might also have queued up
+            // something we need to answer, which we do on a best effort basis.
+            match tls.poll_read_tls(cx) {
+                Poll::Ready(Ok(0)) => tls.eof = true,
+                Poll::Ready(Ok(_)) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+
+            if let Poll::Ready(Err(e)) = tls.poll_write_tls(cx) {
+                return Poll::Ready(Err(e));
+            }
+        }
+    }
+}
+
+impl<A: AsyncRead + AsyncWrite + Unpin, S: Session + Unpin> AsyncWrite for TlsStream<A, S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let tls = self.get_mut();
+        let mut pos = 0;
+
+        // The session takes plaintext until its buffer is full, then we have to get some of the
+        // records out before it takes more
+        while pos < buf.len() {
+            pos += tls.session.write(&buf[pos..])?;
+
+            match tls.poll_write_tls(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending if pos == 0 => return Poll::Pending,
+                Poll::Pending => break,
+            }
+        }
+
+        Poll::Ready(Ok(pos))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let tls = self.get_mut();
+
+        tls.session.flush()?;
+
+        match tls.poll_write_tls(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut tls.inner).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let tls = self.get_mut();
+
+        if !tls.closing {
+            tls.session.send_close_notify();
+            tls.closing = true;
+        }
+
+        match tls.poll_write_tls(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut tls.inner).poll_shutdown(cx),
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::join;
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    /// A CA with a server and a client certificate it signed, written out as PEM files the same
+    /// way the server and client would be given them
+    struct Pki {
+        dir: PathBuf,
+    }
+
+    impl Pki {
+        fn new() -> Pki {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+            let dir = std::env::temp_dir().join(format!(
+                "calc-tls-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir_all(&dir).unwrap();
+
+            let ca = authority();
+            let server =
+                Certificate::from_params(CertificateParams::new(vec!["localhost".to_string()]))
+                    .unwrap();
+            let client =
+                Certificate::from_params(CertificateParams::new(vec!["client".to_string()]))
+                    .unwrap();
+
+            let write = |name: &str, contents: String| fs::write(dir.join(name), contents).unwrap();
+
+            write("ca.pem", ca.serialize_pem().unwrap());
+            write("other-ca.pem", authority().serialize_pem().unwrap());
+            write("server.pem", server.serialize_pem_with_signer(&ca).unwrap());
+            write("server.key", server.serialize_private_key_pem());
+            write("client.pem", client.serialize_pem_with_signer(&ca).unwrap());
+            write("client.key", client.serialize_private_key_pem());
+
+            Pki { dir }
+        }
+
+        fn path(&self, name: &str) -> PathBuf {
+            self.dir.join(name)
+        }
+
+        fn server(&self, require_client: bool) -> Arc<ServerConfig> {
+            let client_ca = if require_client {
+                Some(self.path("ca.pem"))
+            } else {
+                None
+            };
+
+            server_tls_config(self.path("server.pem"), self.path("server.key"), client_ca).unwrap()
+        }
+
+        fn client(&self, roots: &str, identity: bool) -> Arc<ClientConfig> {
+            let identity = if identity {
+                Some((self.path("client.pem"), self.path("client.key")))
+            } else {
+                None
+            };
+
+            client_tls_config(self.path(roots), identity).unwrap()
+        }
+    }
+
+    impl Drop for Pki {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn authority() -> Certificate {
+        let mut params = CertificateParams::new(Vec::new());
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+
+        Certificate::from_params(params).unwrap()
+    }
+
+    type Handshakes = (
+        io::Result<TlsStream<TcpStream, ServerSession>>,
+        io::Result<TlsStream<TcpStream, ClientSession>>,
+    );
+
+    /// Does both sides of the handshake over a real TCP connection at once
+    async fn handshake(server: Arc<ServerConfig>, client: Arc<ClientConfig>) -> Handshakes {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let accepting = async {
+            let (stream, _) = listener.accept().await?;
+            accept_tls(&server, stream).await
+        };
+
+        let connecting = async {
+            let stream = TcpStream::connect(addr).await?;
+            connect_tls(&client, "localhost", stream).await
+        };
+
+        join!(accepting, connecting)
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let pki = Pki::new();
+
+        let (server, client) = handshake(pki.server(false), pki.client("ca.pem", false)).await;
+        let (mut server, mut client) = (server.unwrap(), client.unwrap());
+
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        server.write_all(b"pong").await.unwrap();
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+
+        assert!(server.peer_certificates().is_none());
+    }
+
+    #[tokio::test]
+    async fn untrusted_server() {
+        let pki = Pki::new();
+
+        let (_, client) = handshake(pki.server(false), pki.client("other-ca.pem", false)).await;
+        let e = client.unwrap_err();
+
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("TLS error"), "{}", e);
+    }
+
+    #[tokio::test]
+    async fn client_certificate() {
+        let pki = Pki::new();
+
+        let (server, client) = handshake(pki.server(true), pki.client("ca.pem", true)).await;
+        let (mut server, mut client) = (server.unwrap(), client.unwrap());
+
+        client.write_all(b"hi").await.unwrap();
+        let mut buf = [0u8; 2];
+        server.read_exact(&mut buf).await.unwrap();
+
+        assert_eq!(&buf, b"hi");
+        assert_eq!(server.peer_certificates().map(|certs| certs.len()), Some(1));
+    }
+
+    #[tokio::test]
+    async fn missing_client_certificate() {
+        let pki = Pki::new();
+
+        let (server, client) = handshake(pki.server(true), pki.client("ca.pem", false)).await;
+        assert!(server.is_err());
+
+        // Depending on the TLS version the client might think it's done, but it never gets to
+        // read anything
+        if let Ok(mut client) = client {
+            let mut buf = Vec::new();
+            let res = client.read_to_end(&mut buf).await;
+
+            assert!(res.is_err() || buf.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn clean_close() {
+        let pki = Pki::new();
+
+        let (server, client) = handshake(pki.server(false), pki.client("ca.pem", false)).await;
+        let (mut server, mut client) = (server.unwrap(), client.unwrap());
+
+        client.write_all(b"bye").await.unwrap();
+        client.shutdown().await.unwrap();
+
+        // Everything sent before the close still arrives, and then it reads as a plain end
+        let mut buf = Vec::new();
+        server.read_to_end(&mut buf).await.unwrap();
+
+        assert_eq!(buf, b"bye");
+    }
+}
@@ -0,0 +1,401 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::CalcError;
+
+/// The SI base units, in the order `BaseUnits` keeps their exponents
+const BASE_SYMBOLS: [&str; 7] = ["m", "kg", "s", "A", "K", "mol", "cd"];
+
+/// Biggest power any base unit can be raised to, which is far past anything physical but keeps
+/// the exponents from overflowing
+const MAX_EXPONENT: i32 = 1000;
+
+const NONE: [i32; 7] = [0, 0, 0, 0, 0, 0, 0];
+const LENGTH: [i32; 7] = [1, 0, 0, 0, 0, 0, 0];
+const MASS: [i32; 7] = [0, 1, 0, 0, 0, 0, 0];
+const TIME: [i32; 7] = [0, 0, 1, 0, 0, 0, 0];
+const CURRENT: [i32; 7] = [0, 0, 0, 1, 0, 0, 0];
+const TEMPERATURE: [i32; 7] = [0, 0, 0, 0, 1, 0, 0];
+const AMOUNT: [i32; 7] = [0, 0, 0, 0, 0, 1, 0];
+const LUMINOSITY: [i32; 7] = [0, 0, 0, 0, 0, 0, 1];
+
+const AREA: [i32; 7] = [2, 0, 0, 0, 0, 0, 0];
+const VOLUME: [i32; 7] = [3, 0, 0, 0, 0, 0, 0];
+const FREQUENCY: [i32; 7] = [0, 0, -1, 0, 0, 0, 0];
+const SPEED: [i32; 7] = [1, 0, -1, 0, 0, 0, 0];
+const FORCE: [i32; 7] = [1, 1, -2, 0, 0, 0, 0];
+const PRESSURE: [i32; 7] = [-1, 1, -2, 0, 0, 0, 0];
+const ENERGY: [i32; 7] = [2, 1, -2, 0, 0, 0, 0];
+const POWER: [i32; 7] = [2, 1, -3, 0, 0, 0, 0];
+const CHARGE: [i32; 7] = [0, 0, 1, 1, 0, 0, 0];
+const VOLTAGE: [i32; 7] = [2, 1, -3, -1, 0, 0, 0];
+const RESISTANCE: [i32; 7] = [2, 1, -3, -2, 0, 0, 0];
+
+/// Every unit we know about. The `prefixed` ones also work with any of `PREFIXES` in front of
+/// them, like `km` or `mA`.
+const UNITS: &[Definition] = &[
+    // The SI base units, except that it's grams that take prefixes and not kilograms
+    prefixed("m", 1.0, LENGTH),
+    prefixed("g", 1e-3, MASS),
+    prefixed("s", 1.0, TIME),
+    prefixed("A", 1.0, CURRENT),
+    prefixed("K", 1.0, TEMPERATURE),
+    prefixed("mol", 1.0, AMOUNT),
+    prefixed("cd", 1.0, LUMINOSITY),
+    // Derived SI units
+    prefixed("Hz", 1.0, FREQUENCY),
+    prefixed("N", 1.0, FORCE),
+    prefixed("Pa", 1.0, PRESSURE),
+    prefixed("J", 1.0, ENERGY),
+    prefixed("W", 1.0, POWER),
+    prefixed("C", 1.0, CHARGE),
+    prefixed("V", 1.0, VOLTAGE),
+    prefixed("ohm", 1.0, RESISTANCE),
+    prefixed("Ω", 1.0, RESISTANCE),
+    // Everything else metric
+    prefixed("L", 1e-3, VOLUME),
+    plain("min", 60.0, TIME),
+    plain("h", 3600.0, TIME),
+    plain("day", 86400.0, TIME),
+    plain("ha", 1e4, AREA),
+    plain("t", 1e3, MASS),
+    prefixed("bar", 1e5, PRESSURE),
+    prefixed("Wh", 3600.0, ENERGY),
+    temperature("degC", 1.0, 273.15),
+    temperature("°C", 1.0, 273.15),
+    // Imperial and US customary, by their exact definitions in SI
+    plain("in", 0.0254, LENGTH),
+    plain("ft", 0.3048, LENGTH),
+    plain("yd", 0.9144, LENGTH),
+    plain("mi", 1609.344, LENGTH),
+    plain("acre", 4_046.856_422_4, AREA),
+    plain("gal", 3.785_411_784e-3, VOLUME),
+    plain("oz", 0.028_349_523_125, MASS),
+    plain("lb", 0.453_592_37, MASS),
+    plain("mph", 0.447_04, SPEED),
+    plain("lbf", 4.448_221_615_260_5, FORCE),
+    plain("psi", 6_894.757_293_168, PRESSURE),
+    temperature("degF", 5.0 / 9.0, 459.67 * 5.0 / 9.0),
+    temperature("°F", 5.0 / 9.0, 459.67 * 5.0 / 9.0),
+];
+
+/// Longer ones first, so `da` gets a chance before `d` does
+const PREFIXES: &[(&str, f64)] = &[
+    ("da", 1e1),
+    ("Y", 1e24),
+    ("Z", 1e21),
+    ("E", 1e18),
+    ("P", 1e15),
+    ("T", 1e12),
+    ("G", 1e9),
+    ("M", 1e6),
+    ("k", 1e3),
+    ("h", 1e2),
+    ("d", 1e-1),
+    ("c", 1e-2),
+    ("m", 1e-3),
+    ("u", 1e-6),
+    ("µ", 1e-6),
+    ("n", 1e-9),
+    ("p", 1e-12),
+    ("f", 1e-15),
+    ("a", 1e-18),
+    ("z", 1e-21),
+    ("y", 1e-24),
+];
+
+/// An amount of something, in units that `Unit::parse` takes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Quantity {
+    pub value: f64,
+    pub unit: String,
+}
+
+#[derive(Debug)]
+struct Definition {
+    symbol: &'static str,
+    factor: f64,
+    offset: f64,
+    base_units: [i32; 7],
+    prefixes: bool,
+}
+
+/// What a unit measures, as the powers of each SI base unit that make it up. Metres per second
+/// are `m^1 s^-1`, and a plain number has no powers at all. Two units can only be converted
+/// between or added together when these match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BaseUnits(pub [i32; 7]);
+
+/// A unit, as what it takes to turn an amount of it into SI base units. That's multiplying by
+/// `factor` and then adding `offset`, which is only ever not zero for temperature scales that
+/// don't start at absolute zero.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Unit {
+    pub factor: f64,
+    pub offset: f64,
+    pub base_units: BaseUnits,
+}
+
+impl Quantity {
+    pub fn new(value: f64, unit: &str) -> Quantity {
+        Quantity {
+            value,
+            unit: unit.to_string(),
+        }
+    }
+}
+
+impl BaseUnits {
+    pub const NONE: BaseUnits = BaseUnits(NONE);
+
+    pub fn is_none(self) -> bool {
+        self == BaseUnits::NONE
+    }
+
+    pub fn multiply(self, other: BaseUnits) -> Result<BaseUnits, CalcError> {
+        self.combine(other, |a, b| a.checked_add(b))
+    }
+
+    pub fn divide(self, other: BaseUnits) -> Result<BaseUnits, CalcError> {
+        self.combine(other, |a, b| a.checked_sub(b))
+    }
+
+    pub fn pow(self, exponent: i32) -> Result<BaseUnits, CalcError> {
+        self.combine(BaseUnits::NONE, |a, _| a.checked_mul(exponent))
+    }
+
+    fn combine<F>(self, other: BaseUnits, f: F) -> Result<BaseUnits, CalcError>
+    where
+        F: Fn(i32, i32) -> Option<i32>,
+    {
+        let mut exponents = NONE;
+
+        for (i, exponent) in exponents.iter_mut().enumerate() {
+            *exponent = f(self.0[i], other.0[i])
+                .filter(|exponent| exponent.abs() <= MAX_EXPONENT)
+                .ok_or_else(|| {
+                    let msg = format!("units can't go past powers of {}", MAX_EXPONENT);
+                    CalcError::InvalidArgument(msg)
+                })?;
+        }
+
+        Ok(BaseUnits(exponents))
+    }
+}
+
+impl Unit {
+    /// Parses units like `kg`, `km/h`, `N*m` or `kg m^2 s^-2`. Each `/` only divides by the one
+    /// unit right after it, so `m/s/s` is an acceleration. Spaces multiply the same as `*` does,
+    /// and `1` on its own is a plain number with no unit.
+    pub fn parse(units: &str) -> Result<Unit, CalcError> {
+        let mut unit = Unit {
+            factor: 1.0,
+            offset: 0.0,
+            base_units: BaseUnits::NONE,
+        };
+        let mut divide = false;
+        let mut terms = 0;
+
+        for token in tokenize(units) {
+            let term = match token {
+                "*" | "·" => continue,
+
+                "/" => {
+                    divide = true;
+                    continue;
+                }
+
+                term => term,
+            };
+
+            let (symbol, exponent) = match term.find('^') {
+                Some(i) => {
+                    let exponent = term[i + 1..].parse::<i32>().ok();
+                    let exponent = exponent.filter(|exponent| exponent.abs() <= MAX_EXPONENT);
+
+                    match exponent {
+                        Some(exponent) => (&term[..i], exponent),
+                        None => return Err(invalid(format!("bad exponent in {:?}", term))),
+                    }
+                }
+
+                None => (term, 1),
+            };
+
+            let exponent = if divide { -exponent } else { exponent };
+            let found = Unit::lookup(symbol)
+                .ok_or_else(|| invalid(format!("unknown unit {:?}", symbol)))?;
+
+            unit = Unit {
+                factor: unit.factor * found.factor.powi(exponent),
+                offset: unit.offset + found.offset,
+                base_units: unit.base_units.multiply(found.base_units.pow(exponent)?)?,
+            };
+
+            // A temperature measured from somewhere other than absolute zero can't be scaled
+            // along with anything else, what would twice 20°C even be?
+            if found.offset != 0.0 && exponent != 1 {
+                let msg = format!("{} can't be raised to a power or divided by", symbol);
+                return Err(invalid(msg));
+            }
+
+            divide = false;
+            terms += 1;
+        }
+
+        if unit.offset != 0.0 && terms > 1 {
+            let msg = format!("{:?} mixes a temperature scale with other units", units);
+            return Err(invalid(msg));
+        }
+
+        if divide {
+            return Err(invalid(format!(
+                "nothing to divide by at the end of {:?}",
+                units
+            )));
+        }
+
+        Ok(unit)
+    }
+
+    /// A single unit from the registry, with or without a prefix
+    pub fn lookup(symbol: &str) -> Option<Unit> {
+        if symbol == "1" {
+            return Some(Unit {
+                factor: 1.0,
+                offset: 0.0,
+                base_units: BaseUnits::NONE,
+            });
+        }
+
+        let unit = |definition: &Definition, scale: f64| Unit {
+            factor: definition.factor * scale,
+            offset: definition.offset,
+            base_units: BaseUnits(definition.base_units),
+        };
+
+        if let Some(definition) = UNITS.iter().find(|definition| definition.symbol == symbol) {
+            return Some(unit(definition, 1.0));
+        }
+
+        PREFIXES.iter().find_map(|&(prefix, scale)| {
+            let rest = symbol.strip_prefix(prefix)?;
+
+            UNITS
+                .iter()
+                .find(|definition| definition.prefixes && definition.symbol == rest)
+                .map(|definition| unit(definition, scale))
+        })
+    }
+
+    pub fn to_si(self, value: f64) -> f64 {
+        value * self.factor + self.offset
+    }
+
+    pub fn from_si(self, value: f64) -> f64 {
+        (value - self.offset) / self.factor
+    }
+}
+
+/// Splits units up into symbols with their exponents, and the `*`, `·` and `/` between them
+fn tokenize(units: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+
+    for (i, c) in units.char_indices() {
+        if c == '*' || c == '/' || c == '·' || c.is_whitespace() {
+            if let Some(start) = start.take() {
+                tokens.push(&units[start..i]);
+            }
+
+            if !c.is_whitespace() {
+                tokens.push(&units[i..i + c.len_utf8()]);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+
+    if let Some(start) = start {
+        tokens.push(&units[start..]);
+    }
+
+    tokens
+}
+
+const fn prefixed(symbol: &'static str, factor: f64, base_units: [i32; 7]) -> Definition {
+    Definition {
+        symbol,
+        factor,
+        offset: 0.0,
+        base_units,
+        prefixes: true,
+    }
+}
+
+const fn plain(symbol: &'static str, factor: f64, base_units: [i32; 7]) -> Definition {
+    Definition {
+        symbol,
+        factor,
+        offset: 0.0,
+        base_units,
+        prefixes: false,
+    }
+}
+
+const fn temperature(symbol: &'static str, factor: f64, offset: f64) -> Definition {
+    Definition {
+        symbol,
+        factor,
+        offset,
+        base_units: TEMPERATURE,
+        prefixes: false,
+    }
+}
+
+fn invalid(msg: String) -> CalcError {
+    CalcError::InvalidArgument(msg)
+}
+
+/// The same thing in SI base units, like `m*kg*s^-2`. That's always something `Unit::parse`
+/// takes back.
+impl fmt::Display for BaseUnits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_none() {
+            return write!(f, "1");
+        }
+
+        let mut first = true;
+
+        for (symbol, &exponent) in BASE_SYMBOLS.iter().zip(&self.0) {
+            if exponent == 0 {
+                continue;
+            }
+
+            if !first {
+                write!(f, "*")?;
+            }
+
+            first = false;
+
+            if exponent == 1 {
+                write!(f, "{}", symbol)?;
+            } else {
+                write!(f, "{}^{}", symbol, exponent)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.value, self.unit)
+    }
+}
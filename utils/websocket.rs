@@ -0,0 +1,508 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::cmp;
+use std::io::{self, Cursor, Read};
+use std::pin::Pin;
+
+use byteorder::{ReadBytesExt, BE};
+use futures::sink::Sink;
+use futures::stream::Stream;
+use futures::task::{Context, Poll};
+use serde::de::DeserializeOwned;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::deserialize::Deserializable;
+use crate::frame::{Frame, FrameLimit};
+use crate::packet_streamer::MAX_FRAME_LEN;
+
+/// Mixed into the client's key to prove we actually speak WebSocket (RFC 6455, section 1.3)
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Anything longer than this can't be a legitimate upgrade request
+const MAX_HEADER_LEN: usize = 8 * 1024;
+
+/// Biggest message we'll put back together, so a client can't make us buffer forever. Each one
+/// carries a frame, so they get the same limit as packets.
+const MAX_MESSAGE_LEN: usize = MAX_FRAME_LEN;
+
+/// How much we try to read from the underlying stream at once
+const READ_CHUNK: usize = 4 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Longest payload a ping, pong or close can have
+const MAX_CONTROL_LEN: usize = 125;
+
+/// A whole WebSocket message, after putting any fragments back together
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Binary(Vec<u8>),
+    Text(String),
+}
+
+/// The server side of a WebSocket connection over `A`. Reading gives whole messages and takes
+/// care of pings and closes on the way, writing takes whole messages.
+#[derive(Debug)]
+pub struct WebSocket<A: AsyncRead + AsyncWrite + Unpin> {
+    stream: A,
+
+    /// Bytes we've read but haven't made a frame out of yet
+    read_buffer: Vec<u8>,
+
+    /// The opcode and payload so far of a message that came in fragments
+    fragments: Option<(u8, Vec<u8>)>,
+
+    write_buffer: Vec<u8>,
+    write_pos: usize,
+
+    /// Whether we've sent our close frame already, nothing can come after it
+    closed: bool,
+
+    /// The status the client closed with, once it has. We stop reading then, but our own close
+    /// waits until whoever is writing is done, so answers still on their way get out first.
+    close_status: Option<Vec<u8>>,
+
+    /// The answer to the latest ping, waiting for everything queued before it to go out. Only
+    /// the latest one gets answered, so a client pinging without reading can't pile them up.
+    pong: Option<Vec<u8>>,
+
+    /// Longest message we'll take, which starts at `MAX_MESSAGE_LEN`
+    limit: FrameLimit,
+}
+
+/// A frame straight off the wire, with the mask already taken off
+struct RawFrame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Answers the HTTP upgrade request at the start of `stream`. Clients can ask for any of
+/// `protocols` in order of preference, and we tell them which one we picked.
+pub async fn accept_websocket<A>(
+    mut stream: A,
+    protocols: &[&'static str],
+) -> io::Result<(WebSocket<A>, Option<&'static str>)>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+{
+    // Go one byte at a time, so we don't read past the end of the request into the first frame
+    let mut request = Vec::new();
+    let mut byte = [0u8];
+
+    while !request.ends_with(b"\r\n\r\n") {
+        if request.len() >= MAX_HEADER_LEN {
+            return Err(bad_request(&mut stream, "upgrade request too long").await);
+        }
+
+        if stream.read(&mut byte).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        request.push(byte[0]);
+    }
+
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut parsed = httparse::Request::new(&mut headers);
+
+    if parsed.parse(&request).is_err() || parsed.method != Some("GET") {
+        return Err(bad_request(&mut stream, "not a WebSocket upgrade request").await);
+    }
+
+    let header = |name: &str| {
+        parsed
+            .headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .and_then(|header| std::str::from_utf8(header.value).ok())
+    };
+
+    let has_token = |name: &str, token: &str| {
+        header(name).is_some_and(|value| {
+            value
+                .split(',')
+                .any(|part| part.trim().eq_ignore_ascii_case(token))
+        })
+    };
+
+    if !has_token("Upgrade", "websocket") || !has_token("Connection", "upgrade") {
+        return Err(bad_request(&mut stream, "not a WebSocket upgrade request").await);
+    }
+
+    if header("Sec-WebSocket-Version") != Some("13") {
+        let response = "HTTP/1.1 426 Upgrade Required\r\nSec-WebSocket-Version: 13\r\n\
+                        Content-Length: 0\r\n\r\n";
+        stream.write_all(response.as_bytes()).await?;
+
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unsupported WebSocket version",
+        ));
+    }
+
+    let key = match header("Sec-WebSocket-Key") {
+        Some(key) => key.trim().to_string(),
+        None => return Err(bad_request(&mut stream, "missing Sec-WebSocket-Key").await),
+    };
+
+    // The client lists protocols by its own preference, so take the first we know
+    let protocol = header("Sec-WebSocket-Protocol").and_then(|offered| {
+        offered
+            .split(',')
+            .map(str::trim)
+            .find_map(|offered| protocols.iter().find(|&&ours| ours == offered).copied())
+    });
+
+    let mut response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n",
+        accept_key(&key)
+    );
+
+    if let Some(protocol) = protocol {
+        response.push_str(&format!("Sec-WebSocket-Protocol: {}\r\n", protocol));
+    }
+
+    response.push_str("\r\n");
+    stream.write_all(response.as_bytes()).await?;
+
+    Ok((WebSocket::new(stream), protocol))
+}
+
+/// Turns a client away before it ever became a WebSocket, giving back why for the logs
+async fn bad_request<A: AsyncWrite + Unpin>(stream: &mut A, reason: &str) -> io::Error {
+    let response = "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n";
+
+    // They're going away either way, so there's nothing to do if this doesn't make it
+    let _ = stream.write_all(response.as_bytes()).await;
+
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+fn accept_key(key: &str) -> String {
+    let mut sha = Sha1::new();
+    sha.input(key.as_bytes());
+    sha.input(HANDSHAKE_GUID.as_bytes());
+
+    base64::encode(&sha.result())
+}
+
+impl<A: AsyncRead + AsyncWrite + Unpin> WebSocket<A> {
+    fn new(stream: A) -> WebSocket<A> {
+        WebSocket {
+            stream,
+            read_buffer: Vec::new(),
+            fragments: None,
+            write_buffer: Vec::new(),
+            write_pos: 0,
+            closed: false,
+            close_status: None,
+            pong: None,
+            limit: FrameLimit::new(MAX_MESSAGE_LEN),
+        }
+    }
+
+    /// Lets whoever reads the messages change how long they can be, even once the socket has
+    /// been split
+    pub fn limit(&self) -> FrameLimit {
+        self.limit.clone()
+    }
+
+    /// Takes the next whole frame off the front of the read buffer, if it's all there yet
+    fn parse_frame(&mut self) -> io::Result<Option<RawFrame>> {
+        let mut cursor = Cursor::new(&self.read_buffer[..]);
+
+        let (first, second) = match (cursor.read_u8(), cursor.read_u8()) {
+            (Ok(first), Ok(second)) => (first, second),
+            _ => return Ok(None),
+        };
+
+        // Clients always have to mask what they send
+        if second & 0x80 == 0 {
+            return Err(invalid("unmasked frame from client"));
+        }
+
+        let len = match second & 0x7F {
+            126 => cursor.read_u16::<BE>().map(u64::from),
+            127 => cursor.read_u64::<BE>(),
+            len => Ok(u64::from(len)),
+        };
+
+        let fin = first & 0x80 != 0;
+        let opcode = first & 0x0F;
+        let mut mask = [0u8; 4];
+
+        let len = match len.and_then(|len| Read::read_exact(&mut cursor, &mut mask).map(|()| len)) {
+            Ok(len) if len > self.limit.get() as u64 => return Err(invalid("frame too long")),
+
+            // Control frames have to fit in one small frame, so they can go between the pieces
+            // of a message
+            Ok(len) if opcode & 0x8 != 0 && (!fin || len > MAX_CONTROL_LEN as u64) => {
+                return Err(invalid("control frame fragmented or too long"));
+            }
+
+            Ok(len) => len as usize,
+            Err(_) => return Ok(None),
+        };
+
+        let start = cursor.position() as usize;
+
+        if self.read_buffer.len() < start + len {
+            return Ok(None);
+        }
+
+        let mut payload: Vec<u8> = self.read_buffer.drain(..start + len).skip(start).collect();
+
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        Ok(Some(RawFrame {
+            fin,
+            opcode,
+            payload,
+        }))
+    }
+
+    /// Deals with one frame, giving back a message if it was the last piece of one
+    fn handle_frame(&mut self, frame: RawFrame, cx: &mut Context) -> io::Result<Option<Message>> {
+        let (opcode, payload) = match frame.opcode {
+            OP_TEXT | OP_BINARY if self.fragments.is_some() => {
+                return Err(invalid("new message before the last one was finished"));
+            }
+
+            OP_TEXT | OP_BINARY if !frame.fin => {
+                self.fragments = Some((frame.opcode, frame.payload));
+                return Ok(None);
+            }
+
+            OP_TEXT | OP_BINARY => (frame.opcode, frame.payload),
+
+            OP_CONTINUATION => {
+                let (opcode, mut payload) = match self.fragments.take() {
+                    Some(fragments) => fragments,
+                    None => return Err(invalid("continuation without a message to continue")),
+                };
+
+                if payload.len() + frame.payload.len() > self.limit.get() {
+                    return Err(invalid("message too long"));
+                }
+
+                payload.extend_from_slice(&frame.payload);
+
+                if !frame.fin {
+                    self.fragments = Some((opcode, payload));
+                    return Ok(None);
+                }
+
+                (opcode, payload)
+            }
+
+            // Pings get answered as soon as everything we've already queued is out. Until then
+            // the answer waits, and goes out with whatever gets sent or flushed next.
+            OP_PING => {
+                self.pong = Some(frame.payload);
+                let _ = self.poll_write_buffer(cx);
+                return Ok(None);
+            }
+
+            OP_PONG => return Ok(None),
+
+            other => return Err(invalid(&format!("unknown opcode {}", other))),
+        };
+
+        match opcode {
+            OP_TEXT => match String::from_utf8(payload) {
+                Ok(text) => Ok(Some(Message::Text(text))),
+                Err(_) => Err(invalid("text message is not UTF-8")),
+            },
+
+            _ => Ok(Some(Message::Binary(payload))),
+        }
+    }
+
+    /// Adds a frame to the write buffer. We're the server, so nothing we send gets masked.
+    fn queue_frame(&mut self, opcode: u8, payload: &[u8]) {
+        self.write_buffer.push(0x80 | opcode);
+
+        match payload.len() {
+            len if len < 126 => self.write_buffer.push(len as u8),
+
+            len if len <= usize::from(u16::MAX) => {
+                self.write_buffer.push(126);
+                self.write_buffer
+                    .extend_from_slice(&(len as u16).to_be_bytes());
+            }
+
+            len => {
+                self.write_buffer.push(127);
+                self.write_buffer
+                    .extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+
+        self.write_buffer.extend_from_slice(payload);
+    }
+
+    /// Sends our half of the closing handshake, unless we already did
+    fn queue_close(&mut self, payload: &[u8]) {
+        if !self.closed {
+            self.queue_frame(OP_CLOSE, payload);
+            self.closed = true;
+        }
+    }
+
+    fn poll_write_buffer(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        loop {
+            while self.write_pos < self.write_buffer.len() {
+                let writer = Pin::new(&mut self.stream);
+
+                match writer.poll_write(cx, &self.write_buffer[self.write_pos..]) {
+                    Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                    Poll::Ready(Ok(num)) => self.write_pos += num,
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            self.write_buffer.truncate(0);
+            self.write_pos = 0;
+
+            // Nothing can come after our close, not even a pong
+            match self.pong.take() {
+                Some(payload) if !self.closed => self.queue_frame(OP_PONG, &payload),
+                _ => break,
+            }
+        }
+
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+}
+
+impl<A: AsyncRead + AsyncWrite + Unpin> Stream for WebSocket<A> {
+    type Item = io::Result<Message>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<io::Result<Message>>> {
+        let ws = self.get_mut();
+
+        if ws.close_status.is_some() {
+            return Poll::Ready(None);
+        }
+
+        loop {
+            // Use up everything we've already read before reading more
+            match ws.parse_frame() {
+                Ok(Some(frame)) if frame.opcode == OP_CLOSE => {
+                    // We'll say goodbye with the same status they did once everything we still
+                    // have to send is out, but there's nothing more to read
+                    let status = &frame.payload[..cmp::min(frame.payload.len(), 2)];
+                    ws.close_status = Some(status.to_vec());
+
+                    return Poll::Ready(None);
+                }
+
+                Ok(Some(frame)) => match ws.handle_frame(frame, cx) {
+                    Ok(Some(message)) => return Poll::Ready(Some(Ok(message))),
+                    Ok(None) => continue,
+                    Err(e) => return Poll::Ready(Some(Err(e))),
+                },
+
+                Ok(None) => {}
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+
+            let filled = ws.read_buffer.len();
+            ws.read_buffer.resize(filled + READ_CHUNK, 0);
+
+            let reader = Pin::new(&mut ws.stream);
+            let res = reader.poll_read(cx, &mut ws.read_buffer[filled..]);
+
+            let num = match res {
+                Poll::Ready(Ok(num)) => num,
+                Poll::Ready(Err(e)) => {
+                    ws.read_buffer.truncate(filled);
+                    return Poll::Ready(Some(Err(e)));
+                }
+
+                Poll::Pending => {
+                    ws.read_buffer.truncate(filled);
+                    return Poll::Pending;
+                }
+            };
+
+            ws.read_buffer.truncate(filled + num);
+
+            // They went away without the closing handshake
+            if num == 0 {
+                return Poll::Ready(None);
+            }
+        }
+    }
+}
+
+impl<A: AsyncRead + AsyncWrite + Unpin> Sink<Message> for WebSocket<A> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Message) -> Result<(), io::Error> {
+        let ws = self.get_mut();
+
+        if ws.closed {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "WebSocket is closed",
+            ));
+        }
+
+        match item {
+            Message::Binary(bytes) => ws.queue_frame(OP_BINARY, &bytes),
+            Message::Text(text) => ws.queue_frame(OP_TEXT, text.as_bytes()),
+        }
+
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        self.get_mut().poll_write_buffer(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        let ws = self.get_mut();
+
+        // Answer their close with the same status, otherwise 1000 is a normal closure
+        let status = ws
+            .close_status
+            .clone()
+            .unwrap_or_else(|| 1000u16.to_be_bytes().to_vec());
+
+        ws.queue_close(&status);
+        ws.poll_write_buffer(cx)
+    }
+}
+
+/// Binary messages hold the same bytes as a packet would, text messages hold JSON
+impl Frame for Message {
+    fn decode<D: Deserializable + DeserializeOwned>(self) -> io::Result<D> {
+        match self {
+            Message::Binary(bytes) => bytes.decode(),
+            Message::Text(text) => serde_json::from_str(&text).map_err(|e| invalid(&e.to_string())),
+        }
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}